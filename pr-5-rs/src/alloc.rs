@@ -0,0 +1,521 @@
+use crate::{process::Process, ram::Ram};
+use color_eyre::Result;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    ops::Range,
+    rc::Rc,
+    str::FromStr,
+};
+
+/// Free-list allocations are rounded up to a multiple of this many bytes.
+pub const ALLOC_GRANULE: usize = 8;
+
+/// Smallest block the [`BuddyAllocator`] hands out.
+pub const MIN_BUDDY_SIZE: usize = 16;
+
+/// A contiguous region of memory handed out by an [`Allocator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    /// Start address of the region.
+    pub addr: usize,
+    /// Bytes actually reserved, after rounding.
+    pub size: usize,
+    /// Bytes that were asked for.
+    pub requested: usize,
+}
+
+impl Allocation {
+    #[must_use]
+    pub const fn range(&self) -> Range<usize> {
+        self.addr..self.addr + self.size
+    }
+}
+
+/// Contiguous memory allocator over an address space of [`capacity`](Allocator::capacity) bytes.
+pub trait Allocator: fmt::Debug {
+    /// Name of the algorithm, used in reports.
+    fn name(&self) -> &'static str;
+
+    /// Size of the managed address space.
+    fn capacity(&self) -> usize;
+
+    /// Reserve a region of at least `size` bytes.
+    fn allocate(&mut self, size: usize) -> Result<Allocation>;
+
+    /// Release the region starting at `addr`, merging it with adjacent free regions.
+    fn free(&mut self, addr: usize) -> Result<Allocation>;
+
+    /// Free regions, sorted by address.
+    fn holes(&self) -> Vec<Range<usize>>;
+
+    /// Live allocations, sorted by address.
+    fn allocations(&self) -> Vec<Allocation>;
+
+    /// Summarize how fragmented the address space is.
+    fn report(&self) -> FragmentationReport {
+        let allocations = self.allocations();
+        let holes = self.holes();
+        FragmentationReport {
+            capacity: self.capacity(),
+            reserved: allocations.iter().map(|a| a.size).sum(),
+            requested: allocations.iter().map(|a| a.requested).sum(),
+            free: holes.iter().map(ExactSizeIterator::len).sum(),
+            hole_count: holes.len(),
+            largest_hole: holes.iter().map(ExactSizeIterator::len).max().unwrap_or(0),
+        }
+    }
+}
+
+/// Snapshot of how well an [`Allocator`] is using its address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentationReport {
+    pub capacity: usize,
+    /// Bytes held by live allocations.
+    pub reserved: usize,
+    /// Bytes that live allocations asked for.
+    pub requested: usize,
+    /// Bytes in free regions.
+    pub free: usize,
+    pub hole_count: usize,
+    pub largest_hole: usize,
+}
+
+impl FragmentationReport {
+    /// Bytes wasted inside allocations because of rounding.
+    #[must_use]
+    pub const fn internal(&self) -> usize {
+        self.reserved - self.requested
+    }
+
+    /// Share of free memory that can't be used for an allocation as big as all of it:
+    /// `1 - largest_hole / free`. Zero when all free memory is one hole.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn external(&self) -> f64 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_hole as f64 / self.free as f64
+        }
+    }
+}
+
+impl fmt::Display for FragmentationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "\t\tЗанято: {}/{} байт (запрошено {}), свободно: {} байт в {} дырах",
+            self.reserved, self.capacity, self.requested, self.free, self.hole_count
+        )?;
+        write!(
+            f,
+            "\t\tВнутренняя фрагментация: {} байт, внешняя: {:.1}%, наибольшая дыра: {} байт",
+            self.internal(),
+            self.external() * 100.0,
+            self.largest_hole
+        )
+    }
+}
+
+/// Hole selection strategy of the [`FreeListAllocator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// The first hole big enough, searching from the start.
+    First,
+    /// The first hole big enough, searching from where the previous search stopped.
+    Next,
+    /// The smallest hole big enough.
+    Best,
+    /// The largest hole.
+    Worst,
+}
+
+/// Allocator that keeps a sorted list of holes and carves allocations out of them.
+#[derive(Debug)]
+pub struct FreeListAllocator {
+    fit: Fit,
+    capacity: usize,
+    holes: Vec<Range<usize>>,
+    allocated: BTreeMap<usize, Allocation>,
+    /// Address the next [`Fit::Next`] search starts from.
+    cursor: usize,
+}
+
+impl FreeListAllocator {
+    #[must_use]
+    pub fn new(fit: Fit, capacity: usize) -> Self {
+        Self {
+            fit,
+            capacity,
+            holes: std::iter::once(0..capacity).collect(),
+            allocated: BTreeMap::new(),
+            cursor: 0,
+        }
+    }
+
+    fn pick_hole(&self, size: usize) -> Option<usize> {
+        let fitting = self
+            .holes
+            .iter()
+            .enumerate()
+            .filter(|(_, hole)| hole.len() >= size);
+        match self.fit {
+            Fit::First => fitting.map(|(i, _)| i).next(),
+            Fit::Next => {
+                let (before, after): (Vec<_>, Vec<_>) =
+                    fitting.partition(|(_, hole)| hole.end <= self.cursor);
+                after.into_iter().chain(before).map(|(i, _)| i).next()
+            }
+            Fit::Best => fitting.min_by_key(|(_, hole)| hole.len()).map(|(i, _)| i),
+            Fit::Worst => fitting
+                .max_by_key(|(i, hole)| (hole.len(), std::cmp::Reverse(*i)))
+                .map(|(i, _)| i),
+        }
+    }
+}
+
+impl Allocator for FreeListAllocator {
+    fn name(&self) -> &'static str {
+        match self.fit {
+            Fit::First => "First fit",
+            Fit::Next => "Next fit",
+            Fit::Best => "Best fit",
+            Fit::Worst => "Worst fit",
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn allocate(&mut self, requested: usize) -> Result<Allocation> {
+        eyre::ensure!(requested > 0, "Cannot allocate 0 bytes");
+        let size = requested.next_multiple_of(ALLOC_GRANULE);
+        let index = self.pick_hole(size).ok_or(eyre::eyre!(
+            "Out of memory: no hole fits {size} bytes, the largest one is {} bytes",
+            self.holes
+                .iter()
+                .map(ExactSizeIterator::len)
+                .max()
+                .unwrap_or(0)
+        ))?;
+        let addr = self.holes[index].start;
+        self.holes[index].start += size;
+        if self.holes[index].is_empty() {
+            self.holes.remove(index);
+        }
+        self.cursor = addr + size;
+        let allocation = Allocation {
+            addr,
+            size,
+            requested,
+        };
+        self.allocated.insert(addr, allocation);
+        Ok(allocation)
+    }
+
+    fn free(&mut self, addr: usize) -> Result<Allocation> {
+        let allocation = self.allocated.remove(&addr).ok_or(eyre::eyre!(
+            "Cannot free {addr:#x}: nothing is allocated there"
+        ))?;
+        let index = self.holes.partition_point(|hole| hole.start < addr);
+        self.holes.insert(index, allocation.range());
+        // Coalesce with the following hole, then with the preceding one.
+        if index + 1 < self.holes.len() && self.holes[index].end == self.holes[index + 1].start {
+            self.holes[index].end = self.holes.remove(index + 1).end;
+        }
+        if index > 0 && self.holes[index - 1].end == self.holes[index].start {
+            self.holes[index - 1].end = self.holes.remove(index).end;
+        }
+        Ok(allocation)
+    }
+
+    fn holes(&self) -> Vec<Range<usize>> {
+        self.holes.clone()
+    }
+
+    fn allocations(&self) -> Vec<Allocation> {
+        self.allocated.values().copied().collect()
+    }
+}
+
+/// Buddy system: every block is a power of two in size and is split in halves to satisfy
+/// smaller requests. A freed block is merged with its buddy whenever the buddy is free too.
+#[derive(Debug)]
+pub struct BuddyAllocator {
+    capacity: usize,
+    /// Free block addresses, indexed by order. A block of order `k` is `MIN_BUDDY_SIZE << k` bytes.
+    free_lists: Vec<BTreeSet<usize>>,
+    allocated: BTreeMap<usize, Allocation>,
+}
+
+impl BuddyAllocator {
+    /// Create an allocator over `capacity` bytes, which must be a power of two.
+    pub fn new(capacity: usize) -> Result<Self> {
+        eyre::ensure!(
+            capacity.is_power_of_two() && capacity >= MIN_BUDDY_SIZE,
+            "Buddy allocator needs a power of two capacity of at least {MIN_BUDDY_SIZE} bytes, got {capacity}"
+        );
+        let max_order = (capacity / MIN_BUDDY_SIZE).trailing_zeros() as usize;
+        let mut free_lists = vec![BTreeSet::new(); max_order + 1];
+        free_lists[max_order].insert(0);
+        Ok(Self {
+            capacity,
+            free_lists,
+            allocated: BTreeMap::new(),
+        })
+    }
+
+    const fn block_size(order: usize) -> usize {
+        MIN_BUDDY_SIZE << order
+    }
+}
+
+impl Allocator for BuddyAllocator {
+    fn name(&self) -> &'static str {
+        "Buddy"
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn allocate(&mut self, requested: usize) -> Result<Allocation> {
+        eyre::ensure!(requested > 0, "Cannot allocate 0 bytes");
+        let size = requested.max(MIN_BUDDY_SIZE).next_power_of_two();
+        let order = (size / MIN_BUDDY_SIZE).trailing_zeros() as usize;
+        let available = (order..self.free_lists.len())
+            .find(|&k| !self.free_lists[k].is_empty())
+            .ok_or(eyre::eyre!("Out of memory: no free block of {size} bytes"))?;
+        let addr = self.free_lists[available].pop_first().unwrap_or_default();
+        // Split the block down, keeping the lower half and freeing the upper one each time.
+        for k in (order..available).rev() {
+            self.free_lists[k].insert(addr + Self::block_size(k));
+        }
+        let allocation = Allocation {
+            addr,
+            size,
+            requested,
+        };
+        self.allocated.insert(addr, allocation);
+        Ok(allocation)
+    }
+
+    fn free(&mut self, addr: usize) -> Result<Allocation> {
+        let allocation = self.allocated.remove(&addr).ok_or(eyre::eyre!(
+            "Cannot free {addr:#x}: nothing is allocated there"
+        ))?;
+        let mut order = (allocation.size / MIN_BUDDY_SIZE).trailing_zeros() as usize;
+        let mut block = addr;
+        while order + 1 < self.free_lists.len() {
+            let buddy = block ^ Self::block_size(order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(block);
+        Ok(allocation)
+    }
+
+    fn holes(&self) -> Vec<Range<usize>> {
+        let mut blocks: Vec<_> = self
+            .free_lists
+            .iter()
+            .enumerate()
+            .flat_map(|(k, list)| list.iter().map(move |&a| a..a + Self::block_size(k)))
+            .collect();
+        blocks.sort_by_key(|b| b.start);
+        // Free buddies of different parents can sit next to each other, report them as one hole.
+        let mut holes: Vec<Range<usize>> = vec![];
+        for block in blocks {
+            match holes.last_mut() {
+                Some(last) if last.end == block.start => last.end = block.end,
+                _ => holes.push(block),
+            }
+        }
+        holes
+    }
+
+    fn allocations(&self) -> Vec<Allocation> {
+        self.allocated.values().copied().collect()
+    }
+}
+
+/// Names of the available [`Allocator`]s, for selecting one at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocatorKind {
+    Fit(Fit),
+    Buddy,
+}
+
+impl AllocatorKind {
+    pub const ALL: [Self; 5] = [
+        Self::Fit(Fit::First),
+        Self::Fit(Fit::Next),
+        Self::Fit(Fit::Best),
+        Self::Fit(Fit::Worst),
+        Self::Buddy,
+    ];
+
+    /// Build the allocator over `capacity` bytes.
+    pub fn build(self, capacity: usize) -> Result<Box<dyn Allocator>> {
+        Ok(match self {
+            Self::Fit(fit) => Box::new(FreeListAllocator::new(fit, capacity)),
+            Self::Buddy => Box::new(BuddyAllocator::new(capacity)?),
+        })
+    }
+}
+
+impl FromStr for AllocatorKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "first" => Ok(Self::Fit(Fit::First)),
+            "next" => Ok(Self::Fit(Fit::Next)),
+            "best" => Ok(Self::Fit(Fit::Best)),
+            "worst" => Ok(Self::Fit(Fit::Worst)),
+            "buddy" => Ok(Self::Buddy),
+            _ => Err(eyre::eyre!(
+                "Unknown allocator '{s}', expected one of: first, next, best, worst, buddy"
+            )),
+        }
+    }
+}
+
+/// Contiguous memory model: whole processes placed into [`Ram`] by an [`Allocator`].
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct ContiguousMemory {
+    ram: Rc<Ram>,
+    pub(crate) allocator: Box<dyn Allocator>,
+    pub(crate) processes: HashMap<u16, Allocation>,
+}
+
+#[allow(dead_code)]
+impl ContiguousMemory {
+    /// Manage all of `ram` with `allocator`. The allocator's capacity must not exceed the RAM size.
+    pub fn new(ram: &Rc<Ram>, allocator: Box<dyn Allocator>) -> Result<Self> {
+        eyre::ensure!(
+            allocator.capacity() <= ram.size(),
+            "Allocator manages {} bytes, but RAM only has {}",
+            allocator.capacity(),
+            ram.size()
+        );
+        Ok(Self {
+            ram: Rc::clone(ram),
+            allocator,
+            processes: HashMap::new(),
+        })
+    }
+
+    /// Allocate room for a [`Process`] and copy its instructions there.
+    pub fn load_process(&mut self, process: &Process) -> Result<Allocation> {
+        eyre::ensure!(
+            !self.processes.contains_key(&process.pid),
+            "Process with pid {} is already loaded",
+            process.pid
+        );
+        let allocation = self.allocator.allocate(process.instructions.len())?;
+        if let Err(violation) = self.ram.write(allocation.addr, &process.instructions) {
+            self.allocator.free(allocation.addr)?;
+            return Err(violation.into());
+        }
+        self.processes.insert(process.pid, allocation);
+        Ok(allocation)
+    }
+
+    /// Copy a [`Process`] back out of [`Ram`], zero its region and free it.
+    pub fn unload_process(&mut self, pid: u16) -> Result<Process> {
+        let allocation = self.processes.remove(&pid).ok_or(eyre::eyre!(
+            "Cannot unload process: process with pid {pid} isn't loaded"
+        ))?;
+        let process =
+            Process::from_image(pid, self.ram.read(allocation.addr, allocation.requested)?);
+        self.ram.zero(allocation.addr, allocation.size)?;
+        self.allocator.free(allocation.addr)?;
+        Ok(process)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Allocator, BuddyAllocator, ContiguousMemory, Fit, FreeListAllocator};
+    use crate::{process::Process, ram::Ram};
+    use color_eyre::Result;
+    use std::rc::Rc;
+
+    /// Leave holes of 16, 48 and 32 bytes (in that order) in a 256-byte space, followed by a
+    /// final 64-byte hole.
+    fn fragmented(fit: Fit) -> Result<FreeListAllocator> {
+        let mut allocator = FreeListAllocator::new(fit, 256);
+        let sizes = [16, 8, 48, 8, 32, 80];
+        let addrs = sizes
+            .iter()
+            .map(|&size| Ok(allocator.allocate(size)?.addr))
+            .collect::<Result<Vec<_>>>()?;
+        for i in [0, 2, 4] {
+            allocator.free(addrs[i])?;
+        }
+        Ok(allocator)
+    }
+
+    #[test]
+    fn fit_strategies_pick_different_holes() -> Result<()> {
+        assert_eq!(fragmented(Fit::First)?.allocate(30)?.addr, 24);
+        assert_eq!(fragmented(Fit::Best)?.allocate(30)?.addr, 80);
+        assert_eq!(fragmented(Fit::Worst)?.allocate(30)?.addr, 192);
+
+        let mut next = fragmented(Fit::Next)?;
+        assert_eq!(next.allocate(30)?.addr, 192);
+        assert_eq!(next.allocate(30)?.addr, 224);
+        assert_eq!(next.allocate(30)?.addr, 24);
+        Ok(())
+    }
+
+    #[test]
+    fn free_coalesces_neighbours() -> Result<()> {
+        let mut allocator = fragmented(Fit::First)?;
+        let report = allocator.report();
+        assert_eq!(
+            (report.free, report.hole_count, report.largest_hole),
+            (160, 4, 64)
+        );
+        allocator.free(16)?;
+        allocator.free(72)?;
+        assert_eq!(allocator.holes(), [0..112, 192..256]);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn buddy_split_and_merge() -> Result<()> {
+        let mut buddy = BuddyAllocator::new(256)?;
+        let a = buddy.allocate(20)?;
+        let b = buddy.allocate(16)?;
+        assert_eq!((a.addr, a.size, b.addr, b.size), (0, 32, 32, 16));
+        assert_eq!(buddy.report().internal(), 12);
+        assert_eq!(buddy.holes(), [48..256]);
+        buddy.free(a.addr)?;
+        buddy.free(b.addr)?;
+        assert_eq!(buddy.holes(), [0..256]);
+        assert_eq!(buddy.free_lists[4].len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn contiguous_round_trip() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut memory =
+            ContiguousMemory::new(&ram, Box::new(FreeListAllocator::new(Fit::Best, 256)))?;
+        let process = Process::with_size(5, 37);
+        let allocation = memory.load_process(&process)?;
+        assert_eq!(allocation.size, 40);
+        assert_eq!(memory.unload_process(5)?.instructions, process.instructions);
+        assert!(ram.bytes.borrow().iter().all(|&b| b == 0));
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+/// Fixed-size set of bits, one per slot of some resource. A set bit means the slot is taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+#[allow(dead_code)]
+impl Bitmap {
+    /// Create a bitmap of `len` clear bits.
+    #[must_use]
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    /// Number of bits in the bitmap.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether bit `index` is set. Bits past the end are reported as set.
+    #[must_use]
+    pub fn get(&self, index: usize) -> bool {
+        index >= self.len || self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Set bit `index` to `value`.
+    pub fn set(&mut self, index: usize, value: bool) {
+        assert!(
+            index < self.len,
+            "bit {index} is out of a {}-bit bitmap",
+            self.len
+        );
+        if value {
+            self.words[index / 64] |= 1 << (index % 64);
+        } else {
+            self.words[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    /// Number of set bits.
+    #[must_use]
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Find the first run of `count` clear bits, returning the index it starts at.
+    #[must_use]
+    pub fn find_clear_run(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for index in 0..self.len {
+            if self.get(index) {
+                run = 0;
+            } else {
+                run += 1;
+                if run == count {
+                    return Some(index + 1 - count);
+                }
+            }
+        }
+        None
+    }
+
+    /// Pack the bits into bytes, least significant bit first.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        (0..self.len.div_ceil(8))
+            .map(|i| (self.words[i / 8] >> ((i % 8) * 8)) as u8)
+            .collect()
+    }
+
+    /// Unpack a bitmap of `len` bits from bytes produced by [`Bitmap::to_bytes`].
+    #[must_use]
+    pub fn from_bytes(len: usize, bytes: &[u8]) -> Self {
+        let mut bitmap = Self::new(len);
+        for (i, &byte) in bytes.iter().take(len.div_ceil(8)).enumerate() {
+            bitmap.words[i / 8] |= u64::from(byte) << ((i % 8) * 8);
+        }
+        if !len.is_multiple_of(64) {
+            if let Some(last) = bitmap.words.last_mut() {
+                *last &= (1 << (len % 64)) - 1;
+            }
+        }
+        bitmap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bitmap;
+
+    #[test]
+    fn clear_runs() {
+        let mut bitmap = Bitmap::new(70);
+        for i in [0, 2, 3, 65] {
+            bitmap.set(i, true);
+        }
+        assert_eq!(bitmap.count_ones(), 4);
+        assert_eq!(bitmap.find_clear_run(1), Some(1));
+        assert_eq!(bitmap.find_clear_run(2), Some(4));
+        assert_eq!(bitmap.find_clear_run(61), Some(4));
+        assert_eq!(bitmap.find_clear_run(62), None);
+        bitmap.set(2, false);
+        assert_eq!(bitmap.find_clear_run(2), Some(1));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut bitmap = Bitmap::new(70);
+        for i in [1, 8, 63, 64, 69] {
+            bitmap.set(i, true);
+        }
+        let bytes = bitmap.to_bytes();
+        assert_eq!(bytes.len(), 9);
+        assert_eq!(Bitmap::from_bytes(70, &bytes), bitmap);
+    }
+}
@@ -0,0 +1,566 @@
+use crate::{
+    config::MemoryConfig,
+    isa::DEFAULT_STEPS,
+    page_table::PageTableKind,
+    perm::Mode,
+    replacement::PolicyKind,
+    scheduler::{SchedulerKind, DEFAULT_QUANTUM},
+    tlb::TlbConfig,
+    workset::WorkingSetConfig,
+};
+use color_eyre::Result;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Использование:
+    pr-5-rs [demo] [--save ФАЙЛ]  Загрузить случайные процессы в RAM
+                                 и сохранить её снимок в ФАЙЛ
+    pr-5-rs inspect ФАЙЛ         Показать содержимое снимка RAM
+    pr-5-rs trace [ФАЙЛ] [ОПЦИИ]  Прогнать трассу обращений к памяти (из ФАЙЛА или stdin)
+    pr-5-rs alloc [-n N]         Сравнить алгоритмы непрерывного распределения памяти
+                                 на N случайных загрузках/выгрузках [по умолчанию: 24]
+    pr-5-rs schedule [ФАЙЛ] [ОПЦИИ]
+                                 Спланировать процессы из ФАЙЛА или случайные на процессоре
+    pr-5-rs run ФАЙЛ [ОПЦИИ]     Собрать программу на ассемблере из ФАЙЛА, выполнить её
+                                 из страницы RAM и прогнать её обращения через пейджер
+    pr-5-rs mode РЕЖИМ [ИЗМЕНЕНИЕ...]
+                                 Применить к режиму доступа изменения, как chmod,
+                                 и показать результат
+    pr-5-rs help                 Показать эту справку
+
+Общие опции:
+    --seed <N>          Зерно генератора случайных чисел (также переменная окружения MIREA_SEED)
+    --plain             Не раскрашивать вывод, например для записи в файл
+    --hex-width <N>     Количество байт в строке шестнадцатеричных дампов
+    --ram-size <N>      Размер RAM в байтах, кратный размеру страницы [по умолчанию: 2 страницы]
+    --page-size <N>     Размер страницы в байтах, степень двойки [по умолчанию: 256]
+    --process-size <N>  Размер слота процесса в байтах, делитель размера страницы [по умолчанию: 32]
+
+Формат трассы: по одному обращению `pid vaddr r|w` на строку, `#` начинает комментарий.
+
+Опции trace:
+    -p, --policy <fifo|lru|clock|optimal>  Алгоритм замещения страниц [по умолчанию: fifo]
+    -f, --frames <N>                       Количество кадров RAM [по умолчанию: все]
+    -t, --page-table <ВИД|all>             Таблица страниц: flat, 2-level, 3-level, inverted, hashed
+                                           или все по очереди [по умолчанию: flat]
+    --tlb <N>                              Кэшировать трансляции в TLB из N записей
+    --tlb-ways <N|full>                    Ассоциативность TLB [по умолчанию: full]
+    --tlb-policy <fifo|lru|clock|optimal>  Алгоритм замещения записей TLB [по умолчанию: lru]
+    --no-asid                              Не помечать записи TLB PID процесса,
+                                           а сбрасывать TLB при каждом переключении контекста
+    -w, --window <N>                       Следить за рабочими множествами и частотой отказов
+                                           процессов в окне из N обращений
+                                           (несовместимо с --tlb и --page-table)
+    --suspend                              Приостанавливать процессы, когда их рабочие множества
+                                           не помещаются в кадры (требует --window)
+
+Формат файла процессов: по одному процессу `pid,arrival,cpu,io,memory,priority` на строку,
+где cpu и io — интервалы CPU и ввода-вывода через пробел (io на один меньше), например
+`1,0,5 3,2,64,1`. Строка заголовка и всё после `#` пропускаются.
+
+Опции schedule:
+    -a, --algorithm <АЛГОРИТМ|all>  Алгоритм планирования: fcfs, sjf, srtf, rr, priority,
+                                    priority-p (с вытеснением), mlfq или все по очереди
+                                    [по умолчанию: fcfs]
+    -q, --quantum <N>               Квант времени rr и верхней очереди mlfq [по умолчанию: 2]
+    -n, --processes <N>             Количество случайных процессов [по умолчанию: 5]
+
+Ассемблер: по одной инструкции на строку, `метка:` перед ней, `;` начинает комментарий.
+Регистры r0-r3, инструкции: halt, load rN, [адрес], store rN, [адрес], li rN, число,
+add rN, rM, jmp адрес, jnz rN, адрес, syscall 0 (выход с кодом r0), syscall 1 (вывести r0),
+`.byte a, b, ...` для данных.
+
+Опции run:
+    -s, --steps <N>  Максимальное количество выполняемых инструкций [по умолчанию: 1000]
+    --emit-trace     Только напечатать трассу обращений программы в формате trace
+
+Режим доступа: восьмеричный (`750`) или символьный (`rwxr-x---`, `s`/`t` вместо `x`
+для setuid, setgid и sticky). Изменение: режим доступа или условия через запятую
+`[ugoa][+-=][rwxst]`, например `u+x,go-w`.";
+
+/// Parsed command line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cli {
+    /// Seed for [`rng`](crate::rng), if one was passed with `--seed`.
+    pub seed: Option<u64>,
+    /// Disable colored output.
+    pub plain: bool,
+    /// Bytes per row of every [`HexView`](crate::hexview::HexView).
+    pub hex_width: Option<usize>,
+    /// Geometry of the simulated memory.
+    pub memory: MemoryConfig,
+    pub command: Command,
+}
+
+impl Cli {
+    /// Parse the command line arguments, not including the program name.
+    /// Common options may appear anywhere, everything else is left to [`Command::parse`].
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut seed = None;
+        let mut plain = false;
+        let mut hex_width = None;
+        let defaults = MemoryConfig::default();
+        let (mut ram_size, mut page_size, mut process_size) =
+            (None, defaults.page_size, defaults.process_size);
+        let mut rest = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    let value = value(&arg, args.next())?;
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|e| eyre::eyre!("Invalid seed '{value}': {e}"))?,
+                    );
+                }
+                "--plain" => plain = true,
+                "--hex-width" => {
+                    let width: usize = value(&arg, args.next())?.parse()?;
+                    eyre::ensure!(width > 0, "Hex dump width must be positive");
+                    hex_width = Some(width);
+                }
+                "--ram-size" => ram_size = Some(value(&arg, args.next())?.parse()?),
+                "--page-size" => page_size = value(&arg, args.next())?.parse()?,
+                "--process-size" => process_size = value(&arg, args.next())?.parse()?,
+                _ => rest.push(arg),
+            }
+        }
+        // Keep the default number of frames if only the page size was changed.
+        let ram_size = match ram_size {
+            Some(ram_size) => ram_size,
+            None => page_size
+                .checked_mul(defaults.frame_count())
+                .ok_or(eyre::eyre!(
+                    "RAM size must fit in 32 bits, got {} pages of {page_size} bytes",
+                    defaults.frame_count()
+                ))?,
+        };
+        Ok(Self {
+            seed,
+            plain,
+            hex_width,
+            memory: MemoryConfig::new(ram_size, page_size, process_size)?,
+            command: Command::parse(rest)?,
+        })
+    }
+}
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Load random processes into RAM and show the result, saving a snapshot if asked to.
+    Demo { save: Option<PathBuf> },
+    /// Show a snapshot written by [`Command::Demo`].
+    Inspect { path: PathBuf },
+    /// Replay a memory reference trace from a file, or from stdin if there's no `input`.
+    Trace {
+        input: Option<PathBuf>,
+        policy: PolicyKind,
+        /// Frames to use, all of them if `None`.
+        frames: Option<usize>,
+        /// Page tables to replay the trace against, one after another.
+        page_tables: Vec<PageTableKind>,
+        tlb: Option<TlbConfig>,
+        /// Track working sets instead of printing every reference.
+        working_set: Option<WorkingSetConfig>,
+    },
+    /// Run the same random workload against every contiguous [`Allocator`](crate::alloc::Allocator).
+    Alloc { steps: usize },
+    /// Schedule the processes of a workload file, or `count` random ones if there's no `input`,
+    /// with every algorithm in `algorithms`.
+    Schedule {
+        input: Option<PathBuf>,
+        algorithms: Vec<SchedulerKind>,
+        quantum: usize,
+        count: u16,
+    },
+    /// Assemble the program in `input`, execute it out of a [`Page`](crate::page::Page) for at most
+    /// `steps` instructions and replay its memory references, or just print them if `emit_trace`.
+    Run {
+        input: PathBuf,
+        steps: usize,
+        emit_trace: bool,
+    },
+    /// Show `mode` after the changes it was given, in both notations.
+    Mode { mode: Mode },
+    /// Print [`USAGE`].
+    Help,
+}
+
+impl Command {
+    /// Parse the command line arguments, not including the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        if args.peek().is_some_and(|arg| arg.starts_with("--")) {
+            return Self::parse_demo(args);
+        }
+        match args.next().as_deref() {
+            None => Ok(Self::Demo { save: None }),
+            Some("demo") => Self::parse_demo(args),
+            Some("inspect") => {
+                let path = value("inspect", args.next())?;
+                Ok(Self::Inspect {
+                    path: PathBuf::from(path),
+                })
+            }
+            Some("help" | "-h" | "--help") => Ok(Self::Help),
+            Some("trace") => {
+                let mut input = None;
+                let mut policy = PolicyKind::default();
+                let mut frames = None;
+                let mut page_tables = vec![PageTableKind::default()];
+                let mut tlb = TlbConfig::new(0);
+                let mut tlb_options = vec![];
+                let mut page_table_option = None;
+                let mut window = None;
+                let mut suspend = false;
+                while let Some(arg) = args.next() {
+                    if arg.starts_with("--tlb-") || arg == "--no-asid" {
+                        tlb_options.push(arg.clone());
+                    }
+                    match arg.as_str() {
+                        "-p" | "--policy" => policy = value(&arg, args.next())?.parse()?,
+                        "-f" | "--frames" => {
+                            let count: usize = value(&arg, args.next())?.parse()?;
+                            eyre::ensure!(count > 0, "Frame count must be positive");
+                            frames = Some(count);
+                        }
+                        "-t" | "--page-table" => {
+                            page_table_option = Some(arg.clone());
+                            page_tables = match value(&arg, args.next())?.as_str() {
+                                "all" => PageTableKind::ALL.to_vec(),
+                                kind => vec![kind.parse()?],
+                            };
+                        }
+                        "--tlb" => {
+                            tlb.entries = value(&arg, args.next())?.parse()?;
+                            eyre::ensure!(tlb.entries > 0, "TLB must have at least one entry");
+                        }
+                        "--tlb-ways" => tlb.associativity = value(&arg, args.next())?.parse()?,
+                        "--tlb-policy" => tlb.policy = value(&arg, args.next())?.parse()?,
+                        "--no-asid" => tlb.tagged = false,
+                        "-w" | "--window" => {
+                            let size: usize = value(&arg, args.next())?.parse()?;
+                            eyre::ensure!(size > 0, "Working set window must be positive");
+                            window = Some(size);
+                        }
+                        "--suspend" => suspend = true,
+                        "-" => input = None,
+                        _ if arg.starts_with('-') => eyre::bail!("Unknown option '{arg}'"),
+                        _ => input = Some(PathBuf::from(arg)),
+                    }
+                }
+                if tlb.entries == 0 {
+                    if let Some(option) = tlb_options.first() {
+                        eyre::bail!("Option '{option}' requires --tlb");
+                    }
+                }
+                if suspend && window.is_none() {
+                    eyre::bail!("Option '--suspend' requires --window");
+                }
+                // Working sets are tracked over a flat page table without a TLB.
+                if window.is_some() {
+                    let tlb_option = (tlb.entries > 0).then(|| String::from("--tlb"));
+                    if let Some(option) = tlb_option.or(page_table_option) {
+                        eyre::bail!("Option '{option}' can't be used with --window");
+                    }
+                }
+                Ok(Self::Trace {
+                    input,
+                    policy,
+                    frames,
+                    page_tables,
+                    tlb: (tlb.entries > 0).then_some(tlb),
+                    working_set: window.map(|window| WorkingSetConfig { window, suspend }),
+                })
+            }
+            Some("alloc") => {
+                let mut steps = 24;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-n" | "--steps" => steps = value(&arg, args.next())?.parse()?,
+                        _ => eyre::bail!("Unknown option '{arg}'"),
+                    }
+                }
+                Ok(Self::Alloc { steps })
+            }
+            Some("schedule") => {
+                let mut input = None;
+                let mut algorithms = vec![SchedulerKind::default()];
+                let mut quantum = DEFAULT_QUANTUM;
+                let mut count = 5;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-a" | "--algorithm" => {
+                            algorithms = match value(&arg, args.next())?.as_str() {
+                                "all" => SchedulerKind::ALL.to_vec(),
+                                kind => vec![kind.parse()?],
+                            };
+                        }
+                        "-q" | "--quantum" => {
+                            quantum = value(&arg, args.next())?.parse()?;
+                            eyre::ensure!(quantum > 0, "Quantum must be positive");
+                        }
+                        "-n" | "--processes" => {
+                            count = value(&arg, args.next())?.parse()?;
+                            eyre::ensure!(count > 0, "Process count must be positive");
+                        }
+                        _ if arg.starts_with('-') => eyre::bail!("Unknown option '{arg}'"),
+                        _ => input = Some(PathBuf::from(arg)),
+                    }
+                }
+                Ok(Self::Schedule {
+                    input,
+                    algorithms,
+                    quantum,
+                    count,
+                })
+            }
+            Some("run") => {
+                let mut input = None;
+                let mut steps = DEFAULT_STEPS;
+                let mut emit_trace = false;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-s" | "--steps" => {
+                            steps = value(&arg, args.next())?.parse()?;
+                            eyre::ensure!(steps > 0, "Step limit must be positive");
+                        }
+                        "--emit-trace" => emit_trace = true,
+                        _ if arg.starts_with('-') => eyre::bail!("Unknown option '{arg}'"),
+                        _ => input = Some(PathBuf::from(arg)),
+                    }
+                }
+                Ok(Self::Run {
+                    input: input.ok_or(eyre::eyre!("Command 'run' requires a program file"))?,
+                    steps,
+                    emit_trace,
+                })
+            }
+            Some("mode") => {
+                let mut mode: Mode = value("mode", args.next())?.parse()?;
+                for change in args {
+                    mode = mode.apply(&change)?;
+                }
+                Ok(Self::Mode { mode })
+            }
+            Some(other) => eyre::bail!("Unknown command '{other}', see `pr-5-rs help`"),
+        }
+    }
+
+    fn parse_demo(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut save = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save" => save = Some(PathBuf::from(value(&arg, args.next())?)),
+                _ => eyre::bail!("Unknown option '{arg}'"),
+            }
+        }
+        Ok(Self::Demo { save })
+    }
+}
+
+/// Get the value that must follow the `option` flag.
+fn value(option: &str, value: Option<String>) -> Result<String> {
+    value.ok_or(eyre::eyre!("Option '{option}' requires a value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, Command};
+    use crate::{
+        config::MemoryConfig,
+        page_table::PageTableKind,
+        perm::Mode,
+        replacement::PolicyKind,
+        scheduler::SchedulerKind,
+        tlb::{Associativity, TlbConfig},
+        workset::WorkingSetConfig,
+    };
+    use color_eyre::Result;
+    use std::path::PathBuf;
+
+    fn parse(line: &str) -> Result<Command> {
+        Command::parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn trace_options() -> Result<()> {
+        assert_eq!(parse("")?, Command::Demo { save: None });
+        assert_eq!(
+            parse("trace refs.txt --policy LRU -f 1")?,
+            Command::Trace {
+                input: Some(PathBuf::from("refs.txt")),
+                policy: PolicyKind::Lru,
+                frames: Some(1),
+                page_tables: vec![PageTableKind::Flat],
+                tlb: None,
+                working_set: None,
+            }
+        );
+        assert_eq!(
+            parse("trace --tlb 8 --tlb-ways 2 --no-asid")?,
+            Command::Trace {
+                input: None,
+                policy: PolicyKind::Fifo,
+                frames: None,
+                page_tables: vec![PageTableKind::Flat],
+                tlb: Some(TlbConfig {
+                    associativity: Associativity::Ways(2),
+                    tagged: false,
+                    ..TlbConfig::new(8)
+                }),
+                working_set: None,
+            }
+        );
+        assert!(matches!(
+            parse("trace -w 4 --suspend")?,
+            Command::Trace {
+                working_set: Some(WorkingSetConfig {
+                    window: 4,
+                    suspend: true
+                }),
+                ..
+            }
+        ));
+        assert!(parse("trace --suspend").is_err());
+        assert!(parse("trace -w 4 --tlb 8").is_err());
+        assert!(parse("trace -w 4 -t 2-level").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn schedule_options() -> Result<()> {
+        assert_eq!(
+            parse("schedule -a rr -q 3")?,
+            Command::Schedule {
+                input: None,
+                algorithms: vec![SchedulerKind::RoundRobin],
+                quantum: 3,
+                count: 5,
+            }
+        );
+        assert!(matches!(
+            parse("schedule --algorithm all -n 2")?,
+            Command::Schedule { algorithms, count: 2, .. } if algorithms.len() == 7
+        ));
+        assert!(matches!(
+            parse("schedule jobs.csv")?,
+            Command::Schedule { input: Some(path), .. } if path.as_os_str() == "jobs.csv"
+        ));
+        assert!(parse("schedule -q 0").is_err());
+        assert!(parse("schedule -a lottery").is_err());
+        assert!(parse("trace --window 0").is_err());
+        assert!(parse("trace --tlb-ways 2").is_err());
+        assert!(matches!(
+            parse("trace -t all")?,
+            Command::Trace { page_tables, .. } if page_tables.len() == 5
+        ));
+        assert!(parse("trace -t 4-level").is_err());
+        assert!(parse("trace --tlb 4 --tlb-ways 0").is_err());
+        assert!(parse("trace --frames 0").is_err());
+        assert!(parse("trace --policy").is_err());
+        assert!(parse("trace --bogus").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn run_options() -> Result<()> {
+        assert_eq!(
+            parse("run countdown.s -s 50 --emit-trace")?,
+            Command::Run {
+                input: PathBuf::from("countdown.s"),
+                steps: 50,
+                emit_trace: true,
+            }
+        );
+        assert!(matches!(
+            parse("run countdown.s")?,
+            Command::Run {
+                steps: 1000,
+                emit_trace: false,
+                ..
+            }
+        ));
+        assert!(parse("run").is_err());
+        assert!(parse("run countdown.s --steps 0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mode_changes() -> Result<()> {
+        assert_eq!(
+            parse("mode rwxr-x--- u+s go-x,o+r")?,
+            Command::Mode { mode: Mode(0o4744) }
+        );
+        assert_eq!(parse("mode 644 755")?, Command::Mode { mode: Mode(0o755) });
+        assert!(parse("mode").is_err());
+        assert!(parse("mode 999").is_err());
+        assert!(parse("mode 644 u+q").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn demo_snapshot() -> Result<()> {
+        let save = Some(PathBuf::from("ram.bin"));
+        assert_eq!(
+            parse("--save ram.bin")?,
+            Command::Demo { save: save.clone() }
+        );
+        assert_eq!(parse("demo --save ram.bin")?, Command::Demo { save });
+        assert_eq!(
+            parse("inspect ram.bin")?,
+            Command::Inspect {
+                path: PathBuf::from("ram.bin")
+            }
+        );
+        assert!(parse("inspect").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn seed_anywhere() -> Result<()> {
+        let cli = Cli::parse("trace --seed 17 -f 1".split_whitespace().map(String::from))?;
+        assert_eq!(cli.seed, Some(17));
+        assert!(matches!(
+            cli.command,
+            Command::Trace {
+                frames: Some(1),
+                ..
+            }
+        ));
+        assert!(Cli::parse(["--seed".to_string()]).is_err());
+
+        let cli = Cli::parse(
+            "--plain inspect ram.bin --hex-width 8"
+                .split_whitespace()
+                .map(String::from),
+        )?;
+        assert!(cli.plain);
+        assert_eq!(cli.hex_width, Some(8));
+        Ok(())
+    }
+
+    #[test]
+    fn memory_geometry() -> Result<()> {
+        let parse = |line: &str| Cli::parse(line.split_whitespace().map(String::from));
+        assert_eq!(parse("")?.memory, MemoryConfig::default());
+        let memory = parse("--page-size 4096 --process-size 64")?.memory;
+        assert_eq!(memory.ram_size, 2 * 4096);
+        assert_eq!(memory.slot_count(), 64);
+        assert_eq!(
+            parse("--ram-size 65536 --page-size 1024 trace")?
+                .memory
+                .frame_count(),
+            64
+        );
+        assert!(parse("--page-size 1000").is_err());
+        assert!(parse("--ram-size 1000").is_err());
+        assert!(parse("--page-size 9223372036854775808 inspect")
+            .unwrap_err()
+            .to_string()
+            .starts_with("RAM size must fit in 32 bits"));
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    page::{MAX_PAGE_COUNT, PAGE_SIZE},
+    process::PROCESS_SIZE,
+};
+use color_eyre::Result;
+
+/// Widest row a hexdump of a page is drawn with by default.
+const MAX_PAGE_DIM: usize = 32;
+
+/// Geometry of the simulated memory: how big [`Ram`](crate::ram::Ram) is, how it's split
+/// into [`Page`](crate::page::Page)s and how big a slot of a page is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryConfig {
+    pub(crate) ram_size: usize,
+    pub(crate) page_size: usize,
+    pub(crate) process_size: usize,
+}
+
+impl Default for MemoryConfig {
+    /// [`MAX_PAGE_COUNT`] pages of [`PAGE_SIZE`] bytes, split into [`PROCESS_SIZE`]-byte slots.
+    fn default() -> Self {
+        Self {
+            ram_size: PAGE_SIZE * MAX_PAGE_COUNT,
+            page_size: PAGE_SIZE,
+            process_size: PROCESS_SIZE,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl MemoryConfig {
+    /// Validate a memory geometry. The page size must be a power of two that divides the RAM size,
+    /// and the process size must divide the page size.
+    pub fn new(ram_size: usize, page_size: usize, process_size: usize) -> Result<Self> {
+        eyre::ensure!(
+            page_size.is_power_of_two(),
+            "Page size must be a power of two, got {page_size}"
+        );
+        eyre::ensure!(
+            ram_size >= page_size && ram_size.is_multiple_of(page_size),
+            "RAM size must be a nonzero multiple of the page size ({page_size}), got {ram_size}"
+        );
+        eyre::ensure!(
+            process_size > 0 && page_size.is_multiple_of(process_size),
+            "Process size must divide the page size ({page_size}), got {process_size}"
+        );
+        eyre::ensure!(
+            u32::try_from(ram_size).is_ok(),
+            "RAM size must fit in 32 bits, got {ram_size}"
+        );
+        Ok(Self {
+            ram_size,
+            page_size,
+            process_size,
+        })
+    }
+
+    /// Number of page frames in RAM.
+    #[must_use]
+    pub const fn frame_count(&self) -> usize {
+        self.ram_size / self.page_size
+    }
+
+    /// Number of process slots in a page.
+    #[must_use]
+    pub const fn slot_count(&self) -> usize {
+        self.page_size / self.process_size
+    }
+
+    /// Bytes per row of a page hexdump: the side of the square a page would make, capped at [`MAX_PAGE_DIM`].
+    #[must_use]
+    pub fn page_dim(&self) -> usize {
+        (1 << (self.page_size.trailing_zeros() / 2)).min(MAX_PAGE_DIM)
+    }
+
+    /// Split a virtual address into a virtual page number and an offset within that page.
+    #[must_use]
+    pub const fn split_vaddr(&self, vaddr: usize) -> (usize, usize) {
+        (vaddr / self.page_size, vaddr % self.page_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryConfig;
+    use color_eyre::Result;
+
+    #[test]
+    fn geometry() -> Result<()> {
+        let config = MemoryConfig::new(64 * 4096, 4096, 64)?;
+        assert_eq!(config.frame_count(), 64);
+        assert_eq!(config.slot_count(), 64);
+        assert_eq!(config.page_dim(), 32);
+        assert_eq!(config.split_vaddr(4096 * 3 + 5), (3, 5));
+        assert_eq!(MemoryConfig::default().page_dim(), 16);
+
+        assert!(MemoryConfig::new(1024, 384, 32).is_err());
+        assert!(MemoryConfig::new(1000, 256, 32).is_err());
+        assert!(MemoryConfig::new(128, 256, 32).is_err());
+        assert!(MemoryConfig::new(1024, 256, 48).is_err());
+        Ok(())
+    }
+}
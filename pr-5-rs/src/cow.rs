@@ -0,0 +1,326 @@
+use crate::{
+    hexview::HexView,
+    page_table::Mmu,
+    process::Process,
+    ram::{Protection, Ram},
+};
+use color_eyre::Result;
+use std::{collections::HashMap, fmt, rc::Rc};
+
+/// Counters collected by [`CowMemory`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CowStats {
+    pub forks: usize,
+    /// Shared pages that had to be duplicated because somebody wrote to them.
+    pub copies: usize,
+}
+
+/// Paged memory where [`fork`](CowMemory::fork) shares every page of the parent with the child
+/// and a shared page is only copied on the first write to it (copy-on-write).
+///
+/// Shared frames are write-protected in [`Ram`], so the only way to write to them is through
+/// [`CowMemory::write`], which breaks the sharing first.
+#[derive(Debug)]
+pub struct CowMemory {
+    ram: Rc<Ram>,
+    pub(crate) mmu: Mmu,
+    /// Number of pages mapped to each frame. Zero means the frame is free.
+    pub(crate) refcounts: Vec<usize>,
+    /// Size of the image of every loaded process.
+    pub(crate) sizes: HashMap<u16, usize>,
+    pub(crate) stats: CowStats,
+}
+
+#[allow(dead_code)]
+impl CowMemory {
+    /// Manage every frame of `ram`.
+    #[must_use]
+    pub fn new(ram: &Rc<Ram>) -> Self {
+        Self {
+            ram: Rc::clone(ram),
+            mmu: Mmu::new(ram.config),
+            refcounts: vec![0; ram.config.frame_count()],
+            sizes: HashMap::new(),
+            stats: CowStats::default(),
+        }
+    }
+
+    /// Give every page of a [`Process`] a frame of its own and copy its instructions there.
+    pub fn load_process(&mut self, process: &Process) -> Result<()> {
+        eyre::ensure!(
+            !self.sizes.contains_key(&process.pid),
+            "Process with pid {} is already loaded",
+            process.pid
+        );
+        let page_size = self.page_size();
+        let page_count = process.instructions.len().div_ceil(page_size);
+        let free = self.refcounts.iter().filter(|&&count| count == 0).count();
+        eyre::ensure!(
+            page_count <= free,
+            "Out of memory: process with pid {} needs {page_count} frame(s), but only {free} are free",
+            process.pid
+        );
+        for (vpn, chunk) in process.instructions.chunks(page_size).enumerate() {
+            let frame = self.free_frame()?;
+            self.ram.write(frame * page_size, chunk)?;
+            self.refcounts[frame] = 1;
+            self.mmu.map(process.pid, vpn, frame);
+        }
+        self.sizes.insert(process.pid, process.instructions.len());
+        Ok(())
+    }
+
+    /// Create a child of process `parent` with the pid `child`. The child gets no frames of its own:
+    /// every page is shared with the parent until one of them writes to it.
+    pub fn fork(&mut self, parent: u16, child: u16) -> Result<Process> {
+        let size = *self.sizes.get(&parent).ok_or(eyre::eyre!(
+            "Cannot fork: process with pid {parent} isn't loaded"
+        ))?;
+        eyre::ensure!(
+            !self.sizes.contains_key(&child),
+            "Cannot fork: process with pid {child} is already loaded"
+        );
+        for (_, vpn, frame) in self.pages_of(parent) {
+            self.mmu.map(child, vpn, frame);
+            self.refcounts[frame] += 1;
+            self.ram.protect(self.frame_range(frame), Protection::RX)?;
+        }
+        self.sizes.insert(child, size);
+        self.stats.forks += 1;
+        Ok(Process::from_image(child, self.read(child, 0, size)?))
+    }
+
+    /// Remove process `pid`, freeing the frames nobody else shares.
+    pub fn unload_process(&mut self, pid: u16) -> Result<()> {
+        eyre::ensure!(
+            self.sizes.remove(&pid).is_some(),
+            "Cannot unload process: process with pid {pid} isn't loaded"
+        );
+        for (_, _, frame) in self.pages_of(pid) {
+            self.release(frame)?;
+        }
+        self.mmu.remove_process(pid);
+        Ok(())
+    }
+
+    /// Read `len` bytes at the virtual address `vaddr` of process `pid`.
+    pub fn read(&mut self, pid: u16, vaddr: usize, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        for (vaddr, size) in self.split(vaddr, len) {
+            let paddr = self.mmu.translate(pid, vaddr)?;
+            bytes.extend(self.ram.read(paddr, size)?);
+        }
+        Ok(bytes)
+    }
+
+    /// Write `data` at the virtual address `vaddr` of process `pid`,
+    /// first copying every shared page it touches. Either the whole write happens or,
+    /// if a page isn't mapped or there aren't enough free frames for the copies, nothing does.
+    pub fn write(&mut self, pid: u16, vaddr: usize, data: &[u8]) -> Result<()> {
+        let mut pieces = vec![];
+        for (addr, size) in self.split(vaddr, data.len()) {
+            let frame = self.mmu.translate(pid, addr)? / self.page_size();
+            pieces.push((addr, size, frame));
+        }
+        let shared = pieces
+            .iter()
+            .filter(|&&(_, _, frame)| self.refcounts[frame] > 1)
+            .count();
+        let free = self.refcounts.iter().filter(|&&count| count == 0).count();
+        eyre::ensure!(
+            shared <= free,
+            "Out of memory: writing {} byte(s) at {vaddr:#x} needs {shared} frame(s) to copy shared pages into, but only {free} are free",
+            data.len()
+        );
+        let mut done = 0;
+        for (vaddr, size, mut frame) in pieces {
+            let (vpn, offset) = self.ram.config.split_vaddr(vaddr);
+            if self.refcounts[frame] > 1 {
+                frame = self.copy(pid, vpn, frame)?;
+            }
+            self.ram
+                .write(frame * self.page_size() + offset, &data[done..done + size])?;
+            done += size;
+        }
+        Ok(())
+    }
+
+    /// Number of frames in use.
+    #[must_use]
+    pub fn used_frames(&self) -> usize {
+        self.refcounts.iter().filter(|&&count| count > 0).count()
+    }
+
+    /// Number of frames the loaded processes would use if nothing was shared.
+    #[must_use]
+    pub fn mapped_pages(&self) -> usize {
+        self.refcounts.iter().sum()
+    }
+
+    /// Give page `vpn` of process `pid`, currently sharing `frame`, a private copy of it.
+    fn copy(&mut self, pid: u16, vpn: usize, frame: usize) -> Result<usize> {
+        let copy = self.free_frame()?;
+        let bytes = self.ram.read(frame * self.page_size(), self.page_size())?;
+        self.ram.write(copy * self.page_size(), &bytes)?;
+        self.refcounts[copy] = 1;
+        self.mmu.map(pid, vpn, copy);
+        self.release(frame)?;
+        self.stats.copies += 1;
+        Ok(copy)
+    }
+
+    /// Drop one reference to `frame`. A frame left with a single page becomes writable again,
+    /// and a frame left with none is zeroed.
+    fn release(&mut self, frame: usize) -> Result<()> {
+        self.refcounts[frame] -= 1;
+        match self.refcounts[frame] {
+            0 => {
+                self.ram.protect(self.frame_range(frame), Protection::RWX)?;
+                self.ram.zero(frame * self.page_size(), self.page_size())?;
+            }
+            1 => self.ram.protect(self.frame_range(frame), Protection::RWX)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn free_frame(&self) -> Result<usize> {
+        self.refcounts
+            .iter()
+            .position(|&count| count == 0)
+            .ok_or(eyre::eyre!("Out of memory: every frame is in use"))
+    }
+
+    /// Pages of process `pid` as `(pid, vpn, frame)`.
+    fn pages_of(&self, pid: u16) -> Vec<(u16, usize, usize)> {
+        self.mmu
+            .table
+            .mappings()
+            .into_iter()
+            .filter(|&(owner, _, _)| owner == pid)
+            .collect()
+    }
+
+    /// Split `len` bytes at `vaddr` into pieces that don't cross page boundaries.
+    fn split(&self, vaddr: usize, len: usize) -> Vec<(usize, usize)> {
+        let page_size = self.page_size();
+        let mut pieces = vec![];
+        let mut done = 0;
+        while done < len {
+            let addr = vaddr + done;
+            let size = (page_size - addr % page_size).min(len - done);
+            pieces.push((addr, size));
+            done += size;
+        }
+        pieces
+    }
+
+    fn frame_range(&self, frame: usize) -> std::ops::Range<usize> {
+        frame * self.page_size()..(frame + 1) * self.page_size()
+    }
+
+    fn page_size(&self) -> usize {
+        self.ram.config.page_size
+    }
+}
+
+impl fmt::Display for CowMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mappings = self.mmu.table.mappings();
+        let bytes = self.ram.bytes.borrow();
+        for (frame, &count) in self.refcounts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let range = self.frame_range(frame);
+            let mut pages: Vec<_> = mappings
+                .iter()
+                .filter(|&&(_, _, mapped)| mapped == frame)
+                .map(|&(pid, vpn, _)| (pid, vpn))
+                .collect();
+            pages.sort_unstable();
+            let title = format!("Кадр №{frame}, ссылок: {count}, страницы (PID, №): {pages:?}");
+            let view = HexView::new(title, &bytes[range.clone()])
+                .base(range.start)
+                .width(self.ram.config.page_dim());
+            let view = pages
+                .iter()
+                .fold(view, |view, &(pid, _)| view.owner(range.clone(), pid));
+            writeln!(f, "\n{view}")?;
+        }
+        write!(
+            f,
+            "\t\tЗанято кадров: {} (без копирования при записи: {}), развилок: {}, копий: {}",
+            self.used_frames(),
+            self.mapped_pages(),
+            self.stats.forks,
+            self.stats.copies
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowMemory;
+    use crate::{config::MemoryConfig, process::Process, ram::Ram};
+    use color_eyre::Result;
+    use std::rc::Rc;
+
+    #[test]
+    fn fork_shares_until_write() -> Result<()> {
+        let ram = Rc::new(Ram::new(MemoryConfig::new(64, 16, 16)?));
+        let mut memory = CowMemory::new(&ram);
+        let parent = Process::with_size(1, 24);
+        memory.load_process(&parent)?;
+        let child = memory.fork(1, 2)?;
+        assert_eq!(child.instructions, parent.instructions);
+        assert_eq!((memory.used_frames(), memory.mapped_pages()), (2, 4));
+        // Shared frames can't be written to behind the memory's back.
+        assert!(ram.write(0, &[0]).is_err());
+
+        memory.write(2, 18, &[0xAA, 0xBB])?;
+        assert_eq!(memory.stats.copies, 1);
+        assert_eq!((memory.used_frames(), memory.mapped_pages()), (3, 4));
+        assert_eq!(memory.read(2, 18, 2)?, [0xAA, 0xBB]);
+        assert_eq!(memory.read(1, 18, 2)?, parent.instructions[18..20]);
+        // The parent is the only one left on the original frame, so it writes in place.
+        memory.write(1, 16, &[1])?;
+        assert_eq!(memory.stats.copies, 1);
+
+        memory.unload_process(1)?;
+        assert_eq!(memory.used_frames(), 2);
+        assert_eq!(memory.read(2, 0, 16)?, parent.instructions[..16]);
+        memory.unload_process(2)?;
+        assert!(ram.bytes.borrow().iter().all(|&byte| byte == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn copy_needs_a_free_frame() -> Result<()> {
+        let ram = Rc::new(Ram::new(MemoryConfig::new(32, 16, 16)?));
+        let mut memory = CowMemory::new(&ram);
+        memory.load_process(&Process::with_size(1, 32))?;
+        memory.fork(1, 2)?;
+        assert!(memory.fork(1, 2).is_err());
+        assert!(memory.write(2, 0, &[0]).is_err());
+        assert!(memory.load_process(&Process::with_size(3, 1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_are_all_or_nothing() -> Result<()> {
+        let ram = Rc::new(Ram::new(MemoryConfig::new(64, 16, 16)?));
+        let mut memory = CowMemory::new(&ram);
+        let parent = Process::with_size(1, 48);
+        memory.load_process(&parent)?;
+        memory.fork(1, 2)?;
+        // Three shared pages, but only one free frame to copy them into.
+        assert!(memory.write(2, 0, &[0; 40]).is_err());
+        assert!(memory.write(2, 40, &[0; 16]).is_err());
+        assert_eq!(memory.stats.copies, 0);
+        assert_eq!(memory.read(2, 0, 48)?, parent.instructions);
+        memory.write(2, 20, &[0; 4])?;
+        assert_eq!(memory.stats.copies, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::disk::BLOCK_SIZE;
+use color_eyre::Result;
+use std::{error::Error, fmt};
+
+/// Bytes of a directory entry on disk.
+pub const DIRENT_SIZE: usize = 32;
+pub const DIRENTS_PER_BLOCK: usize = BLOCK_SIZE / DIRENT_SIZE;
+/// Longest name an entry can hold: whatever is left after the inode number and the name length.
+pub const MAX_NAME_LEN: usize = DIRENT_SIZE - 5;
+
+/// A name in a directory and the inode it refers to.
+///
+/// On disk, an entry is the inode number, the length of the name and the name itself, zero-padded
+/// to [`DIRENT_SIZE`]. Inode 0 is never given to a file, so an entry with it is a free slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub(crate) inode: usize,
+    pub(crate) name: String,
+}
+
+#[allow(dead_code)]
+impl DirEntry {
+    /// An entry for `name`, which must be a valid file name.
+    pub fn new(name: &str, inode: usize) -> Result<Self> {
+        eyre::ensure!(
+            !name.is_empty() && !name.contains('/') && name.len() <= MAX_NAME_LEN,
+            "Invalid file name '{name}': it must be 1 to {MAX_NAME_LEN} bytes long and have no '/'"
+        );
+        Ok(Self {
+            inode,
+            name: name.to_string(),
+        })
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; DIRENT_SIZE] {
+        let mut bytes = [0; DIRENT_SIZE];
+        bytes[..4].copy_from_slice(&u32::try_from(self.inode).unwrap_or(0).to_le_bytes());
+        bytes[4] = u8::try_from(self.name.len()).unwrap_or(0);
+        bytes[5..5 + self.name.len()].copy_from_slice(self.name.as_bytes());
+        bytes
+    }
+
+    /// Read an entry written by [`DirEntry::to_bytes`], or `None` if the slot is free.
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8; DIRENT_SIZE]) -> Option<Self> {
+        let inode = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let len = usize::from(bytes[4]).min(MAX_NAME_LEN);
+        (inode != 0).then(|| Self {
+            inode,
+            name: String::from_utf8_lossy(&bytes[5..5 + len]).into_owned(),
+        })
+    }
+
+    /// Whether this is the `.` or `..` entry every directory has.
+    #[must_use]
+    pub fn is_special(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+}
+
+/// Failure to resolve or change a path in the [`Filesystem`](crate::file::Filesystem),
+/// or to use a file opened in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsError {
+    NotFound(String),
+    /// A path goes through something that isn't a directory, or a directory operation was
+    /// given a file.
+    NotADirectory(String),
+    /// A file operation was given a directory.
+    IsADirectory(String),
+    AlreadyExists(String),
+    DirectoryNotEmpty(String),
+    /// The descriptor isn't open, or isn't open for this operation.
+    BadDescriptor(usize),
+    /// The [`Credentials`](crate::perm::Credentials) of the caller don't allow the operation.
+    PermissionDenied(String),
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "No such file or directory: '{path}'"),
+            Self::NotADirectory(path) => write!(f, "Not a directory: '{path}'"),
+            Self::IsADirectory(path) => write!(f, "Is a directory: '{path}'"),
+            Self::AlreadyExists(path) => write!(f, "File exists: '{path}'"),
+            Self::DirectoryNotEmpty(path) => write!(f, "Directory not empty: '{path}'"),
+            Self::BadDescriptor(fd) => write!(f, "Bad file descriptor: {fd}"),
+            Self::PermissionDenied(path) => write!(f, "Permission denied: '{path}'"),
+        }
+    }
+}
+
+impl Error for FsError {}
+
+/// Split `path` into the path of its parent, empty for the current directory,
+/// and its last component.
+#[must_use]
+pub fn split(path: &str) -> (&str, &str) {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None if trimmed.is_empty() && path.starts_with('/') => ("/", "."),
+        None => ("", trimmed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split, DirEntry, MAX_NAME_LEN};
+    use color_eyre::Result;
+
+    #[test]
+    fn entries_round_trip() -> Result<()> {
+        let entry = DirEntry::new("main.rs", 5)?;
+        assert_eq!(DirEntry::from_bytes(&entry.to_bytes()), Some(entry));
+        assert_eq!(DirEntry::from_bytes(&[0; 32]), None);
+        assert!(DirEntry::new("", 1).is_err());
+        assert!(DirEntry::new("a/b", 1).is_err());
+        assert!(DirEntry::new(&"x".repeat(MAX_NAME_LEN + 1), 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn split_paths() {
+        assert_eq!(split("/usr/bin/ls"), ("/usr/bin", "ls"));
+        assert_eq!(split("/usr/"), ("/", "usr"));
+        assert_eq!(split("src/main.rs"), ("src", "main.rs"));
+        assert_eq!(split("main.rs"), ("", "main.rs"));
+        assert_eq!(split("/"), ("/", "."));
+    }
+}
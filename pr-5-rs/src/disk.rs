@@ -0,0 +1,171 @@
+use crate::{bitmap::Bitmap, hexview::HexView};
+use color_eyre::Result;
+use colored::{Color, Colorize};
+use std::fmt;
+
+pub const BLOCK_SIZE: usize = 512;
+pub const BLOCK_DIM: usize = BLOCK_SIZE / 16;
+
+/// Blocks of the device the `fs` demo formats, 128 KiB.
+#[allow(dead_code)]
+pub const DEFAULT_BLOCK_COUNT: usize = 256;
+
+/// Blocks shown per row of the [`BlockDevice`] map.
+const MAP_WIDTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub(crate) bytes: [u8; BLOCK_SIZE],
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Self {
+            bytes: [0; BLOCK_SIZE],
+        }
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let view = HexView::new("Block", &self.bytes)
+            .width(BLOCK_DIM)
+            .color(Color::Green);
+        write!(f, "{view}")
+    }
+}
+
+/// Simulated disk of a fixed number of [`Block`]s, with a free-space bitmap.
+///
+/// Allocated blocks are always zeroed, so nothing a deleted file left behind leaks into a new one.
+#[derive(Debug, Clone)]
+pub struct BlockDevice {
+    pub(crate) blocks: Vec<Block>,
+    /// Allocated blocks.
+    pub(crate) used: Bitmap,
+}
+
+#[allow(dead_code)]
+impl BlockDevice {
+    /// A device of `block_count` blocks, all of them free.
+    #[must_use]
+    pub fn new(block_count: usize) -> Self {
+        Self {
+            blocks: (0..block_count).map(|_| Block::default()).collect(),
+            used: Bitmap::new(block_count),
+        }
+    }
+
+    #[must_use]
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    #[must_use]
+    pub fn free_count(&self) -> usize {
+        self.block_count() - self.used.count_ones()
+    }
+
+    /// Bytes of allocated blocks.
+    #[must_use]
+    pub fn usage(&self) -> usize {
+        self.used.count_ones() * BLOCK_SIZE
+    }
+
+    /// Allocate `count` zeroed blocks, the lowest-numbered free ones first. Either every block is
+    /// allocated or, if the disk is full, none are.
+    pub fn allocate(&mut self, count: usize) -> Result<Vec<usize>> {
+        let free = self.free_count();
+        eyre::ensure!(
+            count <= free,
+            "Disk full: need {count} block(s), but only {free} of {} are free",
+            self.block_count()
+        );
+        let blocks: Vec<usize> = (0..self.block_count())
+            .filter(|&block| !self.used.get(block))
+            .take(count)
+            .collect();
+        for &block in &blocks {
+            self.used.set(block, true);
+            self.blocks[block].bytes.fill(0);
+        }
+        Ok(blocks)
+    }
+
+    /// Return `block` to the pool of free blocks.
+    pub fn free(&mut self, block: usize) -> Result<()> {
+        eyre::ensure!(
+            block < self.block_count() && self.used.get(block),
+            "Cannot free block {block}: it isn't allocated"
+        );
+        self.used.set(block, false);
+        Ok(())
+    }
+
+    pub fn block(&self, block: usize) -> Result<&Block> {
+        self.blocks
+            .get(block)
+            .ok_or(eyre::eyre!("Block {block} is out of the device"))
+    }
+
+    pub fn block_mut(&mut self, block: usize) -> Result<&mut Block> {
+        self.blocks
+            .get_mut(block)
+            .ok_or(eyre::eyre!("Block {block} is out of the device"))
+    }
+}
+
+impl fmt::Display for BlockDevice {
+    /// Map of the device: a filled square for every allocated block, a dot for every free one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "\t\tДиск: блоков {}, свободно {}, занято {} байт",
+            self.block_count(),
+            self.free_count(),
+            self.usage()
+        )?;
+        for row in (0..self.block_count()).step_by(MAP_WIDTH) {
+            let end = (row + MAP_WIDTH).min(self.block_count());
+            let cells: String = (row..end)
+                .map(|block| {
+                    if self.used.get(block) {
+                        "■".green().to_string()
+                    } else {
+                        "·".dimmed().to_string()
+                    }
+                })
+                .collect();
+            writeln!(f, "\t{row:>6} {cells}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockDevice;
+    use color_eyre::Result;
+
+    #[test]
+    fn allocate_until_full() -> Result<()> {
+        let mut disk = BlockDevice::new(8);
+        assert_eq!(disk.allocate(3)?, [0, 1, 2]);
+        disk.block_mut(1)?.bytes[7] = 1;
+        disk.free(1)?;
+        assert_eq!(disk.allocate(2)?, [1, 3]);
+        assert_eq!(disk.free_count(), 4);
+        assert_eq!(disk.block(1)?.bytes[7], 0);
+        assert!(disk
+            .allocate(5)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Disk full"));
+        // A failed allocation takes nothing.
+        assert_eq!(disk.free_count(), 4);
+        assert!(disk.free(7).is_err());
+        assert!(disk.free(8).is_err());
+        assert!(disk.block(8).is_err());
+        Ok(())
+    }
+}
@@ -1,31 +1,649 @@
+use crate::{
+    dir::{self, DirEntry, FsError, DIRENTS_PER_BLOCK, DIRENT_SIZE},
+    disk::{BlockDevice, BLOCK_SIZE},
+    handle::OpenFile,
+    inode::{metadata_blocks, Inode, InodeTable, S_IFDIR, S_IFMT, S_IFREG},
+    pager::BackingStore,
+    perm::{Credentials, Mode, S_ISGID, S_ISUID, S_ISVTX},
+    ram::Protection,
+};
+use color_eyre::Result;
 use colored::Colorize;
-use rand::Rng;
-use std::{collections::HashMap, fmt};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
-pub const BLOCK_SIZE: usize = 512;
-pub const BLOCK_DIM: usize = BLOCK_SIZE / 16;
+/// Inodes of a filesystem created by [`Filesystem::new`].
+pub const INODE_COUNT: usize = 64;
+/// Inode of the root directory.
+pub const ROOT_INODE: usize = 1;
+/// Magic number at the start of the superblock.
+pub const MAGIC: &[u8; 8] = b"MIREAFS\0";
 
+/// Files and directories on a [`BlockDevice`].
+///
+/// The device starts with a superblock, followed by the [`InodeTable`]; the rest of it holds
+/// the contents of files and directories and their indirect blocks. A directory is a file of
+/// [`DirEntry`]s, starting with `.` and `..`, and the root directory is [`ROOT_INODE`].
+/// Owners map to the numeric IDs stored in the inodes.
+///
+/// Every operation is checked against the [`Credentials`] of the caller: walking through
+/// a directory needs its execute bit, listing it the read bit, and adding or removing entries
+/// both write and execute. In a setgid directory new entries get the group of the directory,
+/// and in a sticky one only the owner of an entry or of the directory may remove it.
+/// As on Linux, setuid means nothing for a directory.
 #[allow(dead_code)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug)]
 pub struct Filesystem {
-    pub(crate) files: HashMap<String, File>,
+    pub(crate) disk: Rc<RefCell<BlockDevice>>,
+    pub(crate) inodes: InodeTable,
+    /// Directory relative paths are resolved from.
+    pub(crate) cwd: usize,
+    /// Open files, indexed by descriptor.
+    pub(crate) open_files: Vec<Option<OpenFile>>,
+    /// How many [`FileImage`]s of each inode are alive. Like open files, they keep the blocks
+    /// of the inode from being freed or reused.
+    pub(crate) images: Rc<RefCell<HashMap<usize, usize>>>,
+    /// User and group names, indexed by UID and GID.
+    pub(crate) users: Vec<String>,
+    pub(crate) groups: Vec<String>,
+    /// Time of the last operation, in ticks.
+    pub(crate) clock: u64,
+    /// Who every operation is done as, root unless changed with [`Filesystem::login`].
+    pub(crate) credentials: Credentials,
 }
 
 #[allow(dead_code)]
 impl Filesystem {
-    pub fn add_file(&mut self, file: &File) {
-        self.files.insert(file.name.clone(), file.clone());
+    /// Format a device of `block_count` blocks with a filesystem holding just the root directory.
+    pub fn new(block_count: usize) -> Result<Self> {
+        let inodes = InodeTable {
+            start: 1,
+            count: INODE_COUNT,
+        };
+        let reserved = inodes.start + inodes.blocks();
+        eyre::ensure!(
+            block_count > reserved + 1,
+            "Device of {block_count} block(s) is too small: the superblock, inode table and root directory take {}",
+            reserved + 1
+        );
+        let mut disk = BlockDevice::new(block_count);
+        disk.allocate(reserved)?;
+        let superblock = &mut disk.block_mut(0)?.bytes;
+        superblock[..MAGIC.len()].copy_from_slice(MAGIC);
+        for (i, field) in [block_count, INODE_COUNT, inodes.start]
+            .into_iter()
+            .enumerate()
+        {
+            let at = MAGIC.len() + 4 * i;
+            superblock[at..at + 4].copy_from_slice(&u32::try_from(field)?.to_le_bytes());
+        }
+        let mut fs = Self {
+            disk: Rc::new(RefCell::new(disk)),
+            inodes,
+            cwd: ROOT_INODE,
+            open_files: vec![],
+            images: Rc::default(),
+            users: vec![],
+            groups: vec![],
+            clock: 0,
+            credentials: Credentials::root(),
+        };
+        let (uid, gid) = fs.ids(&fs.credentials.owner())?;
+        let root = fs.new_inode(S_IFDIR | 0o755, uid, gid);
+        fs.write_inode(ROOT_INODE, &root)?;
+        fs.init_dir(ROOT_INODE, ROOT_INODE)?;
+        Ok(fs)
     }
 
-    pub fn get_files(&self) -> Vec<File> {
-        self.files.values().cloned().collect()
+    /// Do every following operation as `credentials`.
+    pub fn login(&mut self, credentials: Credentials) {
+        self.credentials = credentials;
+    }
+
+    /// Create an empty regular [`File`] at the path in its `name`.
+    /// Only root may create a file owned by someone else.
+    pub fn add_file(&mut self, file: &File) -> Result<usize> {
+        if !self.credentials.is_root() && file.owner != self.credentials.owner() {
+            return Err(FsError::PermissionDenied(file.name.clone()).into());
+        }
+        self.create(&file.name, S_IFREG | file.mode, &file.owner)
     }
 
+    /// Create an empty directory at `path`, owned by the caller.
+    pub fn mkdir(&mut self, path: &str) -> Result<usize> {
+        let owner = self.credentials.owner();
+        self.create(path, S_IFDIR | 0o755, &owner)
+    }
+
+    /// Remove the empty directory at `path`.
+    pub fn rmdir(&mut self, path: &str) -> Result<()> {
+        let number = self.resolve(path)?;
+        let inode = self.read_inode(number)?;
+        if !inode.is_dir() {
+            return Err(FsError::NotADirectory(path.to_string()).into());
+        }
+        eyre::ensure!(number != ROOT_INODE, "Cannot remove the root directory");
+        let (_, name) = dir::split(path);
+        eyre::ensure!(
+            name != "." && name != "..",
+            "Cannot remove '{path}': refusing to remove '.' or '..'"
+        );
+        if self.entries(number)?.iter().any(|(_, e)| !e.is_special()) {
+            return Err(FsError::DirectoryNotEmpty(path.to_string()).into());
+        }
+        let parent = self.find_entry(number, "..")?.unwrap_or(ROOT_INODE);
+        self.check_unlink(parent, number, path)?;
+        self.remove_entry(parent, name)?;
+        self.free_inode(number)?;
+        let mut parent_inode = self.read_inode(parent)?;
+        parent_inode.links -= 1;
+        self.write_inode(parent, &parent_inode)?;
+        if self.cwd == number {
+            self.cwd = parent;
+        }
+        Ok(())
+    }
+
+    /// Entries of the directory at `path`, `.` and `..` included.
+    pub fn readdir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let number = self.resolve(path)?;
+        if !self.read_inode(number)?.is_dir() {
+            return Err(FsError::NotADirectory(path.to_string()).into());
+        }
+        self.check(number, Protection::READ, path)?;
+        Ok(self.entries(number)?.into_iter().map(|(_, e)| e).collect())
+    }
+
+    /// Make the directory at `path` the one relative paths are resolved from.
+    pub fn chdir(&mut self, path: &str) -> Result<()> {
+        let number = self.resolve(path)?;
+        if !self.read_inode(number)?.is_dir() {
+            return Err(FsError::NotADirectory(path.to_string()).into());
+        }
+        self.check(number, Protection::EXECUTE, path)?;
+        self.cwd = number;
+        Ok(())
+    }
+
+    /// Inode number of the file or directory at `path`, which is absolute if it starts with `/`
+    /// and relative to the current directory otherwise. Every directory on the way must be
+    /// searchable by the caller.
+    pub fn resolve(&self, path: &str) -> Result<usize> {
+        let (mut number, mut walked) = if path.starts_with('/') {
+            (ROOT_INODE, String::from("/"))
+        } else {
+            (self.cwd, String::new())
+        };
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !self.read_inode(number)?.is_dir() {
+                return Err(FsError::NotADirectory(walked).into());
+            }
+            self.check(
+                number,
+                Protection::EXECUTE,
+                if walked.is_empty() { "." } else { &walked },
+            )?;
+            if !walked.is_empty() && !walked.ends_with('/') {
+                walked.push('/');
+            }
+            walked.push_str(name);
+            if name != "." {
+                number = self
+                    .find_entry(number, name)?
+                    .ok_or(FsError::NotFound(walked.clone()))?;
+            }
+        }
+        Ok(number)
+    }
+
+    /// Every regular file, with its absolute path as the name.
+    pub fn get_files(&self) -> Result<Vec<File>> {
+        self.walk(ROOT_INODE, "")?
+            .into_iter()
+            .filter(|(_, _, inode)| !inode.is_dir())
+            .map(|(path, _, inode)| {
+                Ok(File {
+                    owner: self.owner(&inode),
+                    name: path,
+                    mode: inode.permissions(),
+                })
+            })
+            .collect()
+    }
+
+    /// Bytes of the device taken by the filesystem, its own structures included.
     pub fn usage(&self) -> usize {
-        self.files
-            .values()
-            .map(|f| f.blocks.len() * BLOCK_SIZE)
-            .sum()
+        self.disk.borrow().usage()
+    }
+
+    /// The inode of the file or directory at `path`.
+    pub fn stat(&self, path: &str) -> Result<Inode> {
+        self.read_inode(self.resolve(path)?)
+    }
+
+    /// Allocate `block_count` more data blocks for the file at `path`, along with the indirect
+    /// blocks needed to address them, without changing its size, so later writes don't run out
+    /// of space. Fails without allocating anything if the disk is full.
+    pub fn reserve(&mut self, path: &str, block_count: usize) -> Result<()> {
+        let number = self.regular_file(path)?;
+        self.check(number, Protection::WRITE, path)?;
+        self.grow(number, block_count)?;
+        Ok(())
+    }
+
+    /// Remove the name `path` of a file, freeing the file when it was the last one.
+    pub fn delete(&mut self, path: &str) -> Result<File> {
+        let number = self.regular_file(path)?;
+        eyre::ensure!(!self.is_open(number), "Cannot delete '{path}': it is open");
+        eyre::ensure!(
+            !self.is_mapped(number),
+            "Cannot delete '{path}': it is mapped"
+        );
+        let (parent, name) = dir::split(path);
+        let parent = self.resolve(if parent.is_empty() { "." } else { parent })?;
+        self.check_unlink(parent, number, path)?;
+        self.remove_entry(parent, name)?;
+        let mut inode = self.read_inode(number)?;
+        inode.links -= 1;
+        inode.ctime = self.tick();
+        if inode.links == 0 {
+            self.free_inode(number)?;
+        } else {
+            self.write_inode(number, &inode)?;
+        }
+        Ok(File {
+            owner: self.owner(&inode),
+            name: path.to_string(),
+            mode: inode.permissions(),
+        })
+    }
+
+    /// The contents of the file at `path`, as a [`BackingStore`] a process can be paged in from.
+    /// While the image is alive, the file can be neither deleted nor resized.
+    pub fn image(&self, path: &str) -> Result<FileImage> {
+        let number = self.regular_file(path)?;
+        self.check(number, Protection::READ, path)?;
+        let inode = self.read_inode(number)?;
+        *self.images.borrow_mut().entry(number).or_default() += 1;
+        Ok(FileImage {
+            disk: Rc::clone(&self.disk),
+            blocks: inode.data_blocks(&self.disk.borrow())?,
+            size: inode.size as usize,
+            number,
+            images: Rc::clone(&self.images),
+        })
+    }
+
+    /// Whether any [`FileImage`] of inode `number` is alive.
+    #[must_use]
+    pub fn is_mapped(&self, number: usize) -> bool {
+        self.images.borrow().contains_key(&number)
+    }
+
+    /// Print the inode of the file at `path` and its first `count` data blocks.
+    pub fn show_blocks(&self, path: &str, count: usize) -> Result<()> {
+        let number = self.resolve(path)?;
+        let inode = self.read_inode(number)?;
+        let disk = self.disk.borrow();
+        println!(
+            "\n\t\tФайл '{}', inode №{number}:\n{inode}\n",
+            path.bold().purple(),
+        );
+        for block in inode.data_blocks(&disk)?.into_iter().take(count) {
+            println!("{}", disk.block(block)?);
+        }
+        Ok(())
+    }
+
+    /// Change the permissions of the file or directory at `path`, which only its owner
+    /// and root may do. The setgid bit is dropped if the caller isn't in the group of the file.
+    pub fn chmod(&mut self, path: &str, mode: Mode) -> Result<()> {
+        let number = self.resolve(path)?;
+        let mut inode = self.read_inode(number)?;
+        let owner = self.owner(&inode);
+        let mut mode = mode.0 & Mode::MASK;
+        if !self.credentials.is_root() {
+            if self.credentials.user != owner.name {
+                return Err(FsError::PermissionDenied(path.to_string()).into());
+            }
+            if !self.credentials.in_group(&owner.group) {
+                mode &= !S_ISGID;
+            }
+        }
+        inode.mode = inode.mode & S_IFMT | mode;
+        inode.ctime = self.tick();
+        self.write_inode(number, &inode)
+    }
+
+    /// Give the file or directory at `path` to `owner`. Root may give it to anyone, its owner
+    /// may only change the group to one they are in. Changing the owner of a regular file
+    /// drops its setuid and setgid bits.
+    pub fn chown(&mut self, path: &str, owner: &Owner) -> Result<()> {
+        let number = self.resolve(path)?;
+        let mut inode = self.read_inode(number)?;
+        let current = self.owner(&inode);
+        if !self.credentials.is_root()
+            && (self.credentials.user != current.name
+                || owner.name != current.name
+                || !self.credentials.in_group(&owner.group))
+        {
+            return Err(FsError::PermissionDenied(path.to_string()).into());
+        }
+        (inode.uid, inode.gid) = self.ids(owner)?;
+        if !inode.is_dir() {
+            inode.mode &= !(S_ISUID | S_ISGID);
+        }
+        inode.ctime = self.tick();
+        self.write_inode(number, &inode)
+    }
+
+    /// Create a file of type and permissions `mode` at `path`, linked into its parent directory.
+    /// The caller must be able to write to the parent. If the parent is setgid, the file gets
+    /// its group instead of the one of `owner`, and a new directory is setgid as well.
+    pub(crate) fn create(&mut self, path: &str, mode: u16, owner: &Owner) -> Result<usize> {
+        let (parent_path, name) = dir::split(path);
+        let parent = self.resolve(if parent_path.is_empty() {
+            "."
+        } else {
+            parent_path
+        })?;
+        let parent_inode = self.read_inode(parent)?;
+        if !parent_inode.is_dir() {
+            return Err(FsError::NotADirectory(parent_path.to_string()).into());
+        }
+        if name == "." || name == ".." || self.find_entry(parent, name)?.is_some() {
+            return Err(FsError::AlreadyExists(path.to_string()).into());
+        }
+        self.check(
+            parent,
+            Protection::WRITE | Protection::EXECUTE,
+            if parent_path.is_empty() {
+                "."
+            } else {
+                parent_path
+            },
+        )?;
+        let mut owner = owner.clone();
+        let mut mode = mode;
+        if parent_inode.mode & S_ISGID != 0 {
+            owner.group = self.owner(&parent_inode).group;
+            if mode & S_IFMT == S_IFDIR {
+                mode |= S_ISGID;
+            }
+        }
+        let number = self.inodes.find_free(&self.disk.borrow())?;
+        self.add_entry(parent, &DirEntry::new(name, number)?)?;
+        let (uid, gid) = self.ids(&owner)?;
+        let inode = self.new_inode(mode, uid, gid);
+        self.write_inode(number, &inode)?;
+        if inode.is_dir() {
+            if let Err(e) = self.init_dir(number, parent) {
+                self.remove_entry(parent, name)?;
+                self.free_inode(number)?;
+                return Err(e);
+            }
+            let mut parent_inode = self.read_inode(parent)?;
+            parent_inode.links += 1;
+            self.write_inode(parent, &parent_inode)?;
+        }
+        Ok(number)
+    }
+
+    /// Resolve `path`, which must be a regular file.
+    pub(crate) fn regular_file(&self, path: &str) -> Result<usize> {
+        let number = self.resolve(path)?;
+        if self.read_inode(number)?.is_dir() {
+            return Err(FsError::IsADirectory(path.to_string()).into());
+        }
+        Ok(number)
+    }
+
+    /// Fail with [`FsError::PermissionDenied`] for `path` unless the caller may access
+    /// inode `number` in every way `wanted` asks for.
+    pub(crate) fn check(&self, number: usize, wanted: Protection, path: &str) -> Result<()> {
+        let inode = self.read_inode(number)?;
+        let mode = Mode(inode.permissions());
+        if self
+            .credentials
+            .may(&self.owner(&inode), mode, wanted, inode.is_dir())
+        {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied(path.to_string()).into())
+        }
+    }
+
+    /// Check that the caller may remove the entry for inode `number`, at `path`, from directory
+    /// `parent`: they must be able to write to it and, if it's sticky, own the entry or the directory.
+    fn check_unlink(&self, parent: usize, number: usize, path: &str) -> Result<()> {
+        let (parent_path, _) = dir::split(path);
+        let parent_path = if parent_path.is_empty() {
+            "."
+        } else {
+            parent_path
+        };
+        self.check(parent, Protection::WRITE | Protection::EXECUTE, parent_path)?;
+        let parent_inode = self.read_inode(parent)?;
+        let user = &self.credentials.user;
+        if parent_inode.mode & S_ISVTX != 0
+            && !self.credentials.is_root()
+            && *user != self.owner(&self.read_inode(number)?).name
+            && *user != self.owner(&parent_inode).name
+        {
+            return Err(FsError::PermissionDenied(path.to_string()).into());
+        }
+        Ok(())
+    }
+
+    fn new_inode(&mut self, mode: u16, uid: u16, gid: u16) -> Inode {
+        let now = self.tick();
+        Inode {
+            mode,
+            uid,
+            gid,
+            links: 1,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            ..Inode::default()
+        }
+    }
+
+    /// Give the new directory `number` its `.` and `..` entries.
+    fn init_dir(&mut self, number: usize, parent: usize) -> Result<()> {
+        self.add_entry(number, &DirEntry::new(".", number)?)?;
+        self.add_entry(number, &DirEntry::new("..", parent)?)?;
+        let mut inode = self.read_inode(number)?;
+        inode.links = 2;
+        self.write_inode(number, &inode)
+    }
+
+    pub(crate) fn read_inode(&self, number: usize) -> Result<Inode> {
+        self.inodes.read(&self.disk.borrow(), number)
+    }
+
+    pub(crate) fn write_inode(&self, number: usize, inode: &Inode) -> Result<()> {
+        self.inodes
+            .write(&mut self.disk.borrow_mut(), number, inode)
+    }
+
+    /// Append `block_count` data blocks to inode `number`, returning the updated inode.
+    /// Fails without allocating anything if the disk is full.
+    pub(crate) fn grow(&mut self, number: usize, block_count: usize) -> Result<Inode> {
+        let now = self.tick();
+        let mut disk = self.disk.borrow_mut();
+        let mut inode = self.inodes.read(&disk, number)?;
+        let have = inode.blocks();
+        let need = block_count + metadata_blocks(have + block_count) - metadata_blocks(have);
+        let free = disk.free_count();
+        eyre::ensure!(
+            need <= free,
+            "Disk full: need {need} more block(s), {block_count} of them for data, but only {free} are free"
+        );
+        for block in disk.allocate(block_count)? {
+            inode.push_block(&mut disk, block)?;
+        }
+        inode.mtime = now;
+        inode.ctime = now;
+        self.inodes.write(&mut disk, number, &inode)?;
+        Ok(inode)
+    }
+
+    /// Return the data and indirect blocks of inode `number` to the device and the inode to the table.
+    fn free_inode(&self, number: usize) -> Result<()> {
+        let mut disk = self.disk.borrow_mut();
+        let inode = self.inodes.read(&disk, number)?;
+        let mut blocks = inode.data_blocks(&disk)?;
+        blocks.extend(inode.indirect_blocks(&disk)?);
+        for block in blocks {
+            disk.free(block)?;
+        }
+        self.inodes.write(&mut disk, number, &Inode::default())
+    }
+
+    /// Used entries of directory `number`, along with the slots they're in.
+    fn entries(&self, number: usize) -> Result<Vec<(usize, DirEntry)>> {
+        let disk = self.disk.borrow();
+        let inode = self.inodes.read(&disk, number)?;
+        let mut entries = vec![];
+        for (index, block) in inode.data_blocks(&disk)?.into_iter().enumerate() {
+            let bytes = &disk.block(block)?.bytes;
+            for (i, slot) in bytes.chunks_exact(DIRENT_SIZE).enumerate() {
+                if let Some(entry) = DirEntry::from_bytes(slot.try_into()?) {
+                    entries.push((index * DIRENTS_PER_BLOCK + i, entry));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find_entry(&self, number: usize, name: &str) -> Result<Option<usize>> {
+        Ok(self
+            .entries(number)?
+            .into_iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(_, entry)| entry.inode))
+    }
+
+    /// Put `entry` into the first free slot of directory `number`, growing it by a block if it's full.
+    fn add_entry(&mut self, number: usize, entry: &DirEntry) -> Result<()> {
+        let mut used: Vec<usize> = self
+            .entries(number)?
+            .into_iter()
+            .map(|(slot, _)| slot)
+            .collect();
+        used.sort_unstable();
+        let mut inode = self.read_inode(number)?;
+        let capacity = inode.blocks() * DIRENTS_PER_BLOCK;
+        let slot = (0..capacity)
+            .find(|slot| used.binary_search(slot).is_err())
+            .unwrap_or(capacity);
+        if slot == capacity {
+            inode = self.grow(number, 1)?;
+            inode.size = (inode.blocks() * BLOCK_SIZE) as u64;
+        }
+        inode.mtime = self.tick();
+        self.write_inode(number, &inode)?;
+        self.write_slot(&inode, slot, &entry.to_bytes())
+    }
+
+    fn remove_entry(&mut self, number: usize, name: &str) -> Result<()> {
+        let slot = self
+            .entries(number)?
+            .into_iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(slot, _)| slot)
+            .ok_or(FsError::NotFound(name.to_string()))?;
+        let mut inode = self.read_inode(number)?;
+        inode.mtime = self.tick();
+        self.write_inode(number, &inode)?;
+        self.write_slot(&inode, slot, &[0; DIRENT_SIZE])
+    }
+
+    fn write_slot(&self, inode: &Inode, slot: usize, bytes: &[u8; DIRENT_SIZE]) -> Result<()> {
+        let mut disk = self.disk.borrow_mut();
+        let block = inode.block(&disk, slot / DIRENTS_PER_BLOCK)?;
+        let offset = slot % DIRENTS_PER_BLOCK * DIRENT_SIZE;
+        disk.block_mut(block)?.bytes[offset..offset + DIRENT_SIZE].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Everything under directory `number`, whose path is `path`, depth first and sorted by name,
+    /// as `(path, inode number, inode)`.
+    fn walk(&self, number: usize, path: &str) -> Result<Vec<(String, usize, Inode)>> {
+        let mut entries = self.entries(number)?;
+        entries.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        let mut found = vec![];
+        for (_, entry) in entries.into_iter().filter(|(_, e)| !e.is_special()) {
+            let path = format!("{path}/{}", entry.name);
+            let inode = self.read_inode(entry.inode)?;
+            let is_dir = inode.is_dir();
+            found.push((path.clone(), entry.inode, inode));
+            if is_dir {
+                found.extend(self.walk(entry.inode, &path)?);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Owner of an inode by its IDs.
+    fn owner(&self, inode: &Inode) -> Owner {
+        let name = |names: &[String], id: u16| {
+            names
+                .get(usize::from(id))
+                .cloned()
+                .unwrap_or(id.to_string())
+        };
+        Owner {
+            name: name(&self.users, inode.uid),
+            group: name(&self.groups, inode.gid),
+        }
+    }
+
+    /// UID and GID of `owner`, registering names seen for the first time.
+    fn ids(&mut self, owner: &Owner) -> Result<(u16, u16)> {
+        let id = |names: &mut Vec<String>, name: &str| -> Result<u16> {
+            let index = names.iter().position(|n| n == name).unwrap_or_else(|| {
+                names.push(name.to_string());
+                names.len() - 1
+            });
+            Ok(u16::try_from(index)?)
+        };
+        Ok((
+            id(&mut self.users, &owner.name)?,
+            id(&mut self.groups, &owner.group)?,
+        ))
+    }
+
+    /// Advance the clock, returning the new time.
+    pub(crate) fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl fmt::Display for Filesystem {
+    /// Tree of every file and directory, followed by the map of the device.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\t\t/: inode №{ROOT_INODE}")?;
+        for (path, number, inode) in self.walk(ROOT_INODE, "").map_err(|_| fmt::Error)? {
+            let depth = path.matches('/').count();
+            let (_, name) = dir::split(&path);
+            let name = if inode.is_dir() {
+                format!("{name}/")
+            } else {
+                name.to_string()
+            };
+            writeln!(
+                f,
+                "\t\t{:indent$}{name}: inode №{number}, {} байт, блоков данных {}, служебных {}",
+                "",
+                inode.size,
+                inode.blocks(),
+                inode.metadata_blocks(),
+                indent = 2 * depth
+            )?;
+        }
+        write!(f, "{}", self.disk.borrow())
     }
 }
 
@@ -35,7 +653,6 @@ pub struct File {
     pub(crate) owner: Owner,
     pub(crate) name: String,
     pub(crate) mode: u16,
-    pub(crate) blocks: Vec<Block>,
 }
 
 #[allow(dead_code)]
@@ -59,44 +676,58 @@ impl File {
     pub fn chown(&mut self, new_owner: &(impl Into<Owner> + Clone)) {
         self.owner = new_owner.clone().into();
     }
+}
 
-    pub fn reserve(&mut self, block_count: usize) {
-        (0..block_count).for_each(|_| self.blocks.push(Block::default()));
-    }
+/// Contents of a [`File`] on its device, read as one contiguous run of bytes.
+/// The image can't grow: writes past the size of the file are dropped.
+#[derive(Debug)]
+pub struct FileImage {
+    disk: Rc<RefCell<BlockDevice>>,
+    blocks: Vec<usize>,
+    size: usize,
+    /// Inode of the file, released in [`Filesystem::images`] when the image is dropped.
+    number: usize,
+    images: Rc<RefCell<HashMap<usize, usize>>>,
+}
 
-    pub fn show_blocks(&self) {
-        println!("\n\t\tБлоки файла '{}':\n", self.name.bold().purple());
-        self.blocks.iter().for_each(|b| println!("{b}"));
+impl Drop for FileImage {
+    fn drop(&mut self) {
+        let mut images = self.images.borrow_mut();
+        if let Some(count) = images.get_mut(&self.number) {
+            *count -= 1;
+            if *count == 0 {
+                images.remove(&self.number);
+            }
+        }
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-pub struct Block {
-    bytes: [u8; BLOCK_SIZE],
-}
+impl BackingStore for FileImage {
+    fn len(&self) -> usize {
+        self.size
+    }
 
-impl Default for Block {
-    fn default() -> Self {
-        let mut bytes = [0u8; BLOCK_SIZE];
-        rand::thread_rng().fill(&mut bytes[..]);
-        Self { bytes }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        buf.fill(0);
+        let disk = self.disk.borrow();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let at = offset + i;
+            match self.blocks.get(at / BLOCK_SIZE) {
+                Some(&block) if at < self.size => *byte = disk.blocks[block].bytes[at % BLOCK_SIZE],
+                _ => break,
+            }
+        }
     }
-}
 
-impl fmt::Display for Block {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        let size = BLOCK_DIM;
-        writeln!(f, "\t┌── Block {:─<1$}┐", "", size * 3 - 8)?;
-        for chunk in self.bytes.chunks(size) {
-            write!(f, "\t│ ")?;
-            for byte in chunk {
-                let formatted_byte = format!("{byte:02x}").green();
-                write!(f, "{formatted_byte} ")?;
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        let mut disk = self.disk.borrow_mut();
+        for (i, &byte) in data.iter().enumerate() {
+            let at = offset + i;
+            match self.blocks.get(at / BLOCK_SIZE) {
+                Some(&block) if at < self.size => disk.blocks[block].bytes[at % BLOCK_SIZE] = byte,
+                _ => break,
             }
-            writeln!(f, "│")?;
         }
-        writeln!(f, "\t└{:─<1$}┘", "", size * 3 + 1)
     }
 }
 
@@ -118,7 +749,14 @@ impl Default for Owner {
 
 #[cfg(test)]
 mod tests {
-    use super::{File, Owner};
+    use super::{File, Filesystem, Owner, ROOT_INODE};
+    use crate::{
+        dir::{FsError, DIRENTS_PER_BLOCK},
+        disk::{BLOCK_SIZE, DEFAULT_BLOCK_COUNT},
+        handle::OpenFlags,
+        perm::{Credentials, Mode, S_ISGID},
+    };
+    use color_eyre::Result;
 
     #[test]
     fn rename() {
@@ -155,4 +793,238 @@ mod tests {
 
         file.delete();
     }
+
+    #[test]
+    fn blocks_come_from_the_disk() -> Result<()> {
+        let mut fs = Filesystem::new(18 + 5)?;
+        assert_eq!(fs.usage(), 18 * BLOCK_SIZE);
+        for name in ["a", "b"] {
+            let mut file = File::default();
+            file.rename(&name);
+            fs.add_file(&file)?;
+        }
+        fs.reserve("a", 3)?;
+        assert!(fs.reserve("b", 3).is_err());
+        assert_eq!(fs.stat("b")?.blocks(), 0);
+        fs.reserve("b", 2)?;
+        assert_eq!(fs.disk.borrow().free_count(), 0);
+
+        let data = |fs: &Filesystem, name| fs.stat(name)?.data_blocks(&fs.disk.borrow());
+        assert_eq!(data(&fs, "a")?, [18, 19, 20]);
+        fs.delete("a")?;
+        fs.reserve("b", 2)?;
+        assert_eq!(data(&fs, "b")?, [21, 22, 18, 19]);
+        assert!(fs.delete("a").is_err());
+        fs.delete("/b")?;
+        assert_eq!(fs.disk.borrow().free_count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn directories_and_paths() -> Result<()> {
+        fn error<T>(result: Result<T>) -> Option<FsError> {
+            result.err()?.downcast().ok()
+        }
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        fs.mkdir("/usr")?;
+        fs.mkdir("usr/bin")?;
+        let mut file = File::default();
+        file.rename(&"/usr/bin/ls");
+        let ls = fs.add_file(&file)?;
+
+        assert_eq!(fs.resolve("/usr/bin/ls")?, ls);
+        assert_eq!(fs.resolve("/usr/./bin/../bin/ls")?, ls);
+        assert_eq!(fs.resolve("/..")?, ROOT_INODE);
+        fs.chdir("/usr/bin")?;
+        assert_eq!(fs.resolve("ls")?, ls);
+        assert_eq!(fs.resolve("../..")?, ROOT_INODE);
+        let names: Vec<_> = fs.readdir(".")?.into_iter().map(|e| e.name).collect();
+        assert_eq!(names, [".", "..", "ls"]);
+        // Every subdirectory links back to its parent with `..`.
+        assert_eq!(fs.stat("/usr")?.links, 3);
+
+        assert_eq!(
+            error(fs.resolve("/usr/lib/libc.so")),
+            Some(FsError::NotFound(String::from("/usr/lib")))
+        );
+        assert_eq!(
+            error(fs.resolve("ls/x")),
+            Some(FsError::NotADirectory(String::from("ls")))
+        );
+        assert_eq!(
+            error(fs.add_file(&file)),
+            Some(FsError::AlreadyExists(String::from("/usr/bin/ls")))
+        );
+        assert_eq!(
+            error(fs.rmdir("/usr")),
+            Some(FsError::DirectoryNotEmpty(String::from("/usr")))
+        );
+        assert_eq!(
+            error(fs.rmdir("ls")),
+            Some(FsError::NotADirectory(String::from("ls")))
+        );
+        assert_eq!(
+            error(fs.delete("/usr")),
+            Some(FsError::IsADirectory(String::from("/usr")))
+        );
+        assert!(fs.rmdir("/").is_err());
+
+        let used = fs.disk.borrow().free_count();
+        fs.delete("ls")?;
+        fs.rmdir("/usr/bin")?;
+        assert_eq!(fs.cwd, fs.resolve("/usr")?);
+        assert_eq!(fs.stat("/usr")?.links, 2);
+        fs.rmdir("/usr")?;
+        // Both directories gave back their blocks, and so did their inodes.
+        assert_eq!(fs.disk.borrow().free_count(), used + 2);
+        assert_eq!(fs.readdir("/")?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn directories_grow() -> Result<()> {
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        for i in 0..DIRENTS_PER_BLOCK {
+            fs.mkdir(&format!("/d{i}"))?;
+        }
+        assert_eq!(fs.stat("/")?.blocks(), 2);
+        assert_eq!(fs.stat("/")?.links, 2 + DIRENTS_PER_BLOCK as u16);
+        fs.rmdir("/d3")?;
+        fs.mkdir("/e")?;
+        assert_eq!(fs.readdir("/")?[5].name, "e");
+        assert_eq!(fs.get_files()?.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn permissions() -> Result<()> {
+        fn denied<T>(result: Result<T>) -> Option<String> {
+            match result.err()?.downcast().ok()? {
+                FsError::PermissionDenied(path) => Some(path),
+                _ => None,
+            }
+        }
+        let alice = Credentials::new("alice", &["staff"]);
+        let bob = Credentials::new("bob", &["users", "staff"]);
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        fs.mkdir("/home")?;
+        fs.mkdir("/tmp")?;
+        fs.chmod("/tmp", "rwxrwxrwt".parse()?)?;
+        fs.mkdir("/shared")?;
+        fs.chown(
+            "/shared",
+            &Owner {
+                name: String::from("root"),
+                group: String::from("staff"),
+            },
+        )?;
+        fs.chmod("/shared", Mode(0o2775))?;
+
+        fs.login(alice.clone());
+        assert_eq!(denied(fs.mkdir("/home/alice")), Some(String::from("/home")));
+        let fd = fs.open("/tmp/a", OpenFlags::WRITE | OpenFlags::CREATE)?;
+        fs.close(fd)?;
+        assert_eq!(fs.get_files()?[0].owner, alice.owner());
+        assert_eq!(
+            denied(fs.chmod("/home", Mode(0o777))),
+            Some(String::from("/home"))
+        );
+        // Alice can't give the file away, nor hand it to a group she isn't in.
+        let mut owner = bob.owner();
+        assert!(denied(fs.chown("/tmp/a", &owner)).is_some());
+        owner.name = String::from("alice");
+        assert!(denied(fs.chown("/tmp/a", &owner)).is_some());
+
+        fs.login(bob.clone());
+        let fd = fs.open("/tmp/a", OpenFlags::READ)?;
+        fs.close(fd)?;
+        assert_eq!(
+            denied(fs.open("/tmp/a", OpenFlags::RW)),
+            Some(String::from("/tmp/a"))
+        );
+        assert_eq!(
+            denied(fs.reserve("/tmp/a", 1)),
+            Some(String::from("/tmp/a"))
+        );
+        // /tmp is writable by everyone, but sticky.
+        assert_eq!(denied(fs.delete("/tmp/a")), Some(String::from("/tmp/a")));
+        fs.mkdir("/shared/bob")?;
+        let inode = fs.stat("/shared/bob")?;
+        // Bob's own group is users, but /shared hands down its group.
+        assert_eq!(
+            fs.owner(&inode),
+            Owner {
+                name: String::from("bob"),
+                group: String::from("staff"),
+            }
+        );
+        assert_ne!(inode.mode & S_ISGID, 0);
+
+        fs.login(alice);
+        fs.chmod("/tmp/a", Mode(0o644).apply("go=")?)?;
+        fs.login(bob);
+        assert!(denied(fs.open("/tmp/a", OpenFlags::READ)).is_some());
+        assert!(denied(fs.chmod("/tmp/a", Mode(0o666))).is_some());
+        let mut file = File::default();
+        file.rename(&"/shared/f");
+        assert_eq!(denied(fs.add_file(&file)), Some(String::from("/shared/f")));
+
+        fs.login(Credentials::root());
+        fs.add_file(&file)?;
+        fs.chmod("/shared", Mode(0o2771))?;
+        fs.login(Credentials::new("carol", &["users"]));
+        // Carol may walk through /shared, but not list it.
+        assert_eq!(fs.stat("/shared/f")?.permissions(), 0);
+        assert_eq!(denied(fs.readdir("/shared")), Some(String::from("/shared")));
+        fs.login(Credentials::root());
+        fs.chmod("/shared", Mode(0o770))?;
+        fs.login(Credentials::new("carol", &["users"]));
+        assert_eq!(denied(fs.stat("/shared/f")), Some(String::from("/shared")));
+        assert_eq!(denied(fs.chdir("/shared")), Some(String::from("/shared")));
+        Ok(())
+    }
+
+    #[test]
+    fn large_files_use_indirect_blocks() -> Result<()> {
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        let owner = Owner {
+            name: String::from("root"),
+            group: String::from("wheel"),
+        };
+        let mut file = File::default();
+        file.rename(&"main.rs");
+        file.chown(&owner);
+        file.chmod(&0o644_u16);
+        fs.add_file(&file)?;
+        fs.reserve("main.rs", 64 * 1024 / BLOCK_SIZE)?;
+        assert_eq!(fs.get_files()?[0].name, "/main.rs");
+        // Reserved blocks don't count towards the size until something is written to them.
+        assert_eq!(fs.stat("main.rs")?.size, 0);
+        let fd = fs.open("main.rs", OpenFlags::WRITE)?;
+        fs.write(fd, &[b'x'; 64 * 1024])?;
+        fs.close(fd)?;
+
+        let inode = fs.stat("main.rs")?;
+        assert_eq!(inode.size, 64 * 1024);
+        assert_eq!((inode.blocks(), inode.metadata_blocks()), (128, 1));
+        assert_eq!(
+            inode.indirect_blocks(&fs.disk.borrow())?,
+            [inode.indirect as usize]
+        );
+        assert_eq!(inode.permissions(), 0o644);
+        assert!(inode.mtime > inode.atime);
+        let files = fs.get_files()?;
+        assert_eq!(files[0].owner, owner);
+        // The inode lives in the table on the device, not just in memory.
+        assert_eq!(
+            fs.inodes.read(&fs.disk.borrow(), fs.resolve("main.rs")?)?,
+            inode
+        );
+
+        let free = fs.disk.borrow().free_count();
+        fs.delete("main.rs")?;
+        assert_eq!(fs.disk.borrow().free_count(), free + 129);
+        assert!(fs.stat("main.rs").is_err());
+        Ok(())
+    }
 }
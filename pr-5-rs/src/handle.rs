@@ -0,0 +1,387 @@
+use crate::{
+    dir::FsError,
+    disk::BLOCK_SIZE,
+    file::Filesystem,
+    inode::{MAX_BLOCKS, S_IFREG},
+    ram::Protection,
+};
+use color_eyre::Result;
+use std::{fmt, io::SeekFrom};
+
+/// How [`Filesystem::open`] opens a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u8);
+
+#[allow(dead_code)]
+impl OpenFlags {
+    pub const READ: Self = Self(0b0_0001);
+    pub const WRITE: Self = Self(0b0_0010);
+    pub const RW: Self = Self(0b0_0011);
+    /// Create the file if it doesn't exist.
+    pub const CREATE: Self = Self(0b0_0100);
+    /// Cut the file to zero bytes when opening it.
+    pub const TRUNCATE: Self = Self(0b0_1000);
+    /// Write at the end of the file, wherever the offset is.
+    pub const APPEND: Self = Self(0b1_0000);
+
+    /// Whether every flag in `other` is set in `self`.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for OpenFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |flag: Self, c: char| if self.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}{}",
+            flag(Self::READ, 'r'),
+            flag(Self::WRITE, 'w'),
+            flag(Self::CREATE, 'c'),
+            flag(Self::TRUNCATE, 't'),
+            flag(Self::APPEND, 'a')
+        )
+    }
+}
+
+/// A file opened with [`Filesystem::open`], referred to by its descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFile {
+    pub(crate) inode: usize,
+    /// Where the next read or write starts.
+    pub(crate) offset: usize,
+    pub(crate) flags: OpenFlags,
+}
+
+#[allow(dead_code)]
+impl Filesystem {
+    /// Open the regular file at `path`, returning its descriptor, the lowest one not in use.
+    /// The caller must be allowed to read or write the file as `flags` ask for, unless they
+    /// have just created it, in which case it's theirs.
+    pub fn open(&mut self, path: &str, flags: OpenFlags) -> Result<usize> {
+        eyre::ensure!(
+            flags.contains(OpenFlags::READ) || flags.contains(OpenFlags::WRITE),
+            "Cannot open '{path}': a file must be opened for reading, writing or both"
+        );
+        eyre::ensure!(
+            flags.contains(OpenFlags::WRITE) || !flags.contains(OpenFlags::TRUNCATE),
+            "Cannot open '{path}': truncating a file needs it opened for writing"
+        );
+        let number = match self.regular_file(path) {
+            Err(e)
+                if flags.contains(OpenFlags::CREATE)
+                    && matches!(e.downcast_ref(), Some(FsError::NotFound(_))) =>
+            {
+                let owner = self.credentials.owner();
+                self.create(path, S_IFREG | 0o644, &owner)?
+            }
+            result => {
+                let number = result?;
+                let wanted = |flag, right| {
+                    if flags.contains(flag) {
+                        right
+                    } else {
+                        Protection::NONE
+                    }
+                };
+                self.check(
+                    number,
+                    wanted(OpenFlags::READ, Protection::READ)
+                        | wanted(OpenFlags::WRITE, Protection::WRITE),
+                    path,
+                )?;
+                number
+            }
+        };
+        if flags.contains(OpenFlags::TRUNCATE) {
+            self.resize(number, 0)?;
+        }
+        let file = OpenFile {
+            inode: number,
+            offset: 0,
+            flags,
+        };
+        match self.open_files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.open_files[fd] = Some(file);
+                Ok(fd)
+            }
+            None => {
+                self.open_files.push(Some(file));
+                Ok(self.open_files.len() - 1)
+            }
+        }
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<()> {
+        self.handle(fd)?;
+        self.open_files[fd] = None;
+        Ok(())
+    }
+
+    /// Read up to `len` bytes at the offset of `fd`, fewer if the file ends sooner,
+    /// and move the offset past them.
+    pub fn read(&mut self, fd: usize, len: usize) -> Result<Vec<u8>> {
+        let file = self.handle(fd)?;
+        if !file.flags.contains(OpenFlags::READ) {
+            return Err(FsError::BadDescriptor(fd).into());
+        }
+        let mut inode = self.read_inode(file.inode)?;
+        let size = inode.size as usize;
+        let end = file.offset.saturating_add(len).min(size).max(file.offset);
+        let mut bytes = Vec::with_capacity(end - file.offset);
+        {
+            let disk = self.disk.borrow();
+            let mut at = file.offset;
+            while at < end {
+                let block = disk.block(inode.block(&disk, at / BLOCK_SIZE)?)?;
+                let chunk = (BLOCK_SIZE - at % BLOCK_SIZE).min(end - at);
+                bytes.extend_from_slice(&block.bytes[at % BLOCK_SIZE..at % BLOCK_SIZE + chunk]);
+                at += chunk;
+            }
+        }
+        inode.atime = self.tick();
+        self.write_inode(file.inode, &inode)?;
+        self.set_offset(fd, end);
+        Ok(bytes)
+    }
+
+    /// Write `data` at the offset of `fd`, or at the end of the file if it was opened with
+    /// [`OpenFlags::APPEND`], allocating blocks as the file grows. Moves the offset past the data.
+    /// Writing nothing leaves the file as it is, even past its end.
+    pub fn write(&mut self, fd: usize, data: &[u8]) -> Result<usize> {
+        let file = self.handle(fd)?;
+        if !file.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadDescriptor(fd).into());
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut inode = self.read_inode(file.inode)?;
+        let start = if file.flags.contains(OpenFlags::APPEND) {
+            inode.size as usize
+        } else {
+            file.offset
+        };
+        let end = start
+            .checked_add(data.len())
+            .filter(|&end| end.div_ceil(BLOCK_SIZE) <= MAX_BLOCKS)
+            .ok_or(eyre::eyre!(
+                "File is too big: an inode addresses at most {MAX_BLOCKS} blocks"
+            ))?;
+        let blocks = end.div_ceil(BLOCK_SIZE);
+        if blocks > inode.blocks() {
+            inode = self.grow(file.inode, blocks - inode.blocks())?;
+        }
+        {
+            let mut disk = self.disk.borrow_mut();
+            let mut at = start;
+            while at < end {
+                let block = inode.block(&disk, at / BLOCK_SIZE)?;
+                let chunk = (BLOCK_SIZE - at % BLOCK_SIZE).min(end - at);
+                disk.block_mut(block)?.bytes[at % BLOCK_SIZE..at % BLOCK_SIZE + chunk]
+                    .copy_from_slice(&data[at - start..at - start + chunk]);
+                at += chunk;
+            }
+        }
+        inode.size = inode.size.max(end as u64);
+        let now = self.tick();
+        inode.mtime = now;
+        inode.ctime = now;
+        self.write_inode(file.inode, &inode)?;
+        self.set_offset(fd, end);
+        Ok(data.len())
+    }
+
+    /// Move the offset of `fd`, returning the new one. The offset may go past the end of the file:
+    /// writing there leaves a gap of zeros.
+    pub fn seek(&mut self, fd: usize, position: SeekFrom) -> Result<usize> {
+        let file = self.handle(fd)?;
+        let size = self.read_inode(file.inode)?.size;
+        let (base, delta) = match position {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::End(delta) => (size, delta),
+            SeekFrom::Current(delta) => (file.offset as u64, delta),
+        };
+        let offset = base.checked_add_signed(delta).ok_or(eyre::eyre!(
+            "Cannot seek descriptor {fd} before the start of the file"
+        ))?;
+        let offset = usize::try_from(offset)?;
+        self.set_offset(fd, offset);
+        Ok(offset)
+    }
+
+    /// Cut or extend the file of `fd` to `len` bytes, extending it with zeros.
+    /// The offset stays where it was.
+    pub fn truncate(&mut self, fd: usize, len: usize) -> Result<()> {
+        let file = self.handle(fd)?;
+        if !file.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadDescriptor(fd).into());
+        }
+        self.resize(file.inode, len)
+    }
+
+    /// Whether any descriptor refers to inode `number`.
+    #[must_use]
+    pub fn is_open(&self, number: usize) -> bool {
+        self.open_files
+            .iter()
+            .flatten()
+            .any(|file| file.inode == number)
+    }
+
+    fn handle(&self, fd: usize) -> Result<OpenFile> {
+        Ok(self
+            .open_files
+            .get(fd)
+            .copied()
+            .flatten()
+            .ok_or(FsError::BadDescriptor(fd))?)
+    }
+
+    fn set_offset(&mut self, fd: usize, offset: usize) {
+        if let Some(Some(file)) = self.open_files.get_mut(fd) {
+            file.offset = offset;
+        }
+    }
+
+    /// Give inode `number` exactly the blocks `len` bytes need and a size of `len`.
+    ///
+    /// Bytes past the size of a file are kept zeroed, so that growing it again reads back zeros.
+    fn resize(&mut self, number: usize, len: usize) -> Result<()> {
+        eyre::ensure!(
+            !self.is_mapped(number),
+            "Cannot resize inode {number}: it is mapped"
+        );
+        let mut inode = self.read_inode(number)?;
+        let blocks = len.div_ceil(BLOCK_SIZE);
+        if blocks > inode.blocks() {
+            inode = self.grow(number, blocks - inode.blocks())?;
+        } else {
+            let mut disk = self.disk.borrow_mut();
+            while inode.blocks() > blocks {
+                inode.pop_block(&mut disk)?;
+            }
+            if len < inode.size as usize && !len.is_multiple_of(BLOCK_SIZE) {
+                let block = inode.block(&disk, blocks - 1)?;
+                disk.block_mut(block)?.bytes[len % BLOCK_SIZE..].fill(0);
+            }
+        }
+        inode.size = len as u64;
+        let now = self.tick();
+        inode.mtime = now;
+        inode.ctime = now;
+        self.write_inode(number, &inode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenFlags;
+    use crate::{
+        dir::FsError,
+        disk::{BLOCK_SIZE, DEFAULT_BLOCK_COUNT},
+        file::Filesystem,
+    };
+    use color_eyre::Result;
+    use std::io::SeekFrom;
+
+    #[test]
+    fn read_write_across_blocks() -> Result<()> {
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        let fd = fs.open("/notes.txt", OpenFlags::RW | OpenFlags::CREATE)?;
+        let data: Vec<u8> = (0..=255).cycle().take(BLOCK_SIZE + 100).collect();
+        assert_eq!(fs.write(fd, &data)?, data.len());
+        let inode = fs.stat("/notes.txt")?;
+        assert_eq!((inode.size, inode.blocks()), (data.len() as u64, 2));
+
+        assert_eq!(
+            fs.seek(fd, SeekFrom::Start(BLOCK_SIZE as u64 - 2))?,
+            BLOCK_SIZE - 2
+        );
+        assert_eq!(fs.read(fd, 4)?, data[BLOCK_SIZE - 2..BLOCK_SIZE + 2]);
+        assert_eq!(fs.seek(fd, SeekFrom::End(-3))?, data.len() - 3);
+        assert_eq!(fs.read(fd, 10)?, data[data.len() - 3..]);
+        assert!(fs.read(fd, 10)?.is_empty());
+        assert!(fs.seek(fd, SeekFrom::Current(-1000)).is_err());
+
+        // Writing past the end leaves a gap of zeros.
+        fs.seek(fd, SeekFrom::End(10))?;
+        fs.write(fd, b"end")?;
+        fs.seek(fd, SeekFrom::Start(data.len() as u64))?;
+        assert_eq!(fs.read(fd, 13)?, b"\0\0\0\0\0\0\0\0\0\0end");
+        fs.close(fd)?;
+        assert_eq!(
+            fs.read(fd, 1).unwrap_err().downcast::<FsError>()?,
+            FsError::BadDescriptor(fd)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn far_offsets() -> Result<()> {
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        let fd = fs.open("sparse", OpenFlags::RW | OpenFlags::CREATE)?;
+        fs.write(fd, b"abc")?;
+        fs.seek(fd, SeekFrom::Start(1))?;
+        assert_eq!(fs.read(fd, usize::MAX)?, b"bc");
+
+        // An empty write doesn't grow the file, however far past its end.
+        fs.seek(fd, SeekFrom::Start(5000))?;
+        assert_eq!(fs.write(fd, b"")?, 0);
+        let inode = fs.stat("sparse")?;
+        assert_eq!((inode.size, inode.blocks()), (3, 1));
+
+        fs.seek(fd, SeekFrom::Start(u64::MAX))?;
+        assert!(fs.read(fd, usize::MAX)?.is_empty());
+        assert!(fs.write(fd, b"x").is_err());
+        assert_eq!(fs.stat("sparse")?.size, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_and_append() -> Result<()> {
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+        let fd = fs.open("log", OpenFlags::WRITE | OpenFlags::CREATE)?;
+        fs.write(fd, &[7; 3 * BLOCK_SIZE])?;
+        let free = fs.disk.borrow().free_count();
+        fs.truncate(fd, 10)?;
+        assert_eq!(fs.stat("log")?.blocks(), 1);
+        assert_eq!(fs.disk.borrow().free_count(), free + 2);
+        // Growing again reads back zeros, not what was cut off.
+        fs.truncate(fd, 20)?;
+        fs.close(fd)?;
+
+        let fd = fs.open("log", OpenFlags::READ)?;
+        assert_eq!(fs.read(fd, 100)?, [[7; 10], [0; 10]].concat());
+        assert!(fs.write(fd, b"x").is_err());
+        let appender = fs.open("log", OpenFlags::WRITE | OpenFlags::APPEND)?;
+        assert_eq!(appender, fd + 1);
+        fs.write(appender, b"ab")?;
+        fs.write(appender, b"c")?;
+        assert_eq!(fs.read(fd, 100)?, b"abc");
+        assert!(fs.delete("log").is_err());
+        fs.close(fd)?;
+        fs.close(appender)?;
+
+        let fd = fs.open("log", OpenFlags::RW | OpenFlags::TRUNCATE)?;
+        assert_eq!(fs.stat("log")?.size, 0);
+        assert!(fs.read(fd, 1)?.is_empty());
+        assert!(fs.open("missing", OpenFlags::READ).is_err());
+        assert!(fs.open("/", OpenFlags::READ).is_err());
+        assert!(fs
+            .open("log", OpenFlags::READ | OpenFlags::TRUNCATE)
+            .is_err());
+        Ok(())
+    }
+}
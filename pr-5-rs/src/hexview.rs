@@ -0,0 +1,192 @@
+use colored::{Color, Colorize};
+use std::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Row width forced on every [`HexView`] by [`set_row_width`]. Zero means "use the view's own".
+static ROW_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Colors owners are highlighted with, picked by PID.
+const OWNER_PALETTE: [Color; 6] = [
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Red,
+];
+
+/// Force every [`HexView`] to show `width` bytes per row, or let each view pick its own if `None`.
+pub fn set_row_width(width: Option<usize>) {
+    ROW_WIDTH.store(width.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Boxed hexdump of a byte slice with an address gutter and an ASCII pane.
+///
+/// Nonzero bytes are highlighted with the color of the PID that owns them, or with the default
+/// color of the view if nobody does. With `plain` (or when `colored` is told not to colorize),
+/// no ANSI escapes are written at all, so the output can be piped into a file.
+#[derive(Debug, Clone)]
+pub struct HexView<'a> {
+    title: String,
+    bytes: &'a [u8],
+    base: usize,
+    width: usize,
+    group: Option<usize>,
+    color: Color,
+    owners: Vec<(Range<usize>, u16)>,
+    plain: bool,
+}
+
+#[allow(dead_code)]
+impl<'a> HexView<'a> {
+    /// A view of `bytes` titled `title`, 16 bytes per row, starting at address zero.
+    #[must_use]
+    pub fn new(title: impl Into<String>, bytes: &'a [u8]) -> Self {
+        Self {
+            title: title.into(),
+            bytes,
+            base: 0,
+            width: 16,
+            group: None,
+            color: Color::Blue,
+            owners: vec![],
+            plain: false,
+        }
+    }
+
+    /// Absolute address of the first byte.
+    #[must_use]
+    pub const fn base(mut self, base: usize) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Bytes per row.
+    #[must_use]
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width.max(1);
+        self
+    }
+
+    /// Draw a separator every `group` bytes, e.g. on page boundaries.
+    #[must_use]
+    pub const fn group(mut self, group: usize) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    /// Color of nonzero bytes nobody owns.
+    #[must_use]
+    pub const fn color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Mark the absolute addresses `range` as owned by process `pid`.
+    #[must_use]
+    pub fn owner(mut self, range: Range<usize>, pid: u16) -> Self {
+        self.owners.push((range, pid));
+        self
+    }
+
+    /// Don't write any ANSI escapes.
+    #[must_use]
+    pub const fn plain(mut self, plain: bool) -> Self {
+        self.plain = plain;
+        self
+    }
+
+    fn row_width(&self) -> usize {
+        match ROW_WIDTH.load(Ordering::Relaxed) {
+            0 => self.width,
+            forced => forced,
+        }
+    }
+
+    fn paint(&self, text: &str, address: usize, byte: u8) -> String {
+        if self.plain {
+            return text.to_string();
+        }
+        let owner = self
+            .owners
+            .iter()
+            .find(|(range, _)| range.contains(&address))
+            .map(|&(_, pid)| OWNER_PALETTE[usize::from(pid) % OWNER_PALETTE.len()]);
+        match (owner, byte) {
+            (Some(color), _) => text.color(color).bold().to_string(),
+            (None, 0) => text.bright_black().to_string(),
+            (None, _) => text.color(self.color).bold().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for HexView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.row_width();
+        let last = self.base + self.bytes.len().saturating_sub(1);
+        let digits = format!("{last:x}").len().max(4);
+        // " 0x" + address + " │ " + hex + " │ " + ascii + " "
+        let inner = 3 + digits + 3 + width * 3 - 1 + 3 + width + 1;
+        let title = self.title.chars().count();
+        writeln!(
+            f,
+            "\t┌── {} {:─<2$}┐",
+            self.title,
+            "",
+            inner.saturating_sub(title + 4)
+        )?;
+        for (row, chunk) in self.bytes.chunks(width).enumerate() {
+            let offset = row * width;
+            if let Some(group) = self.group {
+                if offset != 0 && offset % group < width {
+                    writeln!(f, "\t├{:─<1$}┤", "", inner)?;
+                }
+            }
+            let address = self.base + offset;
+            write!(f, "\t│ 0x{address:0digits$x} │ ")?;
+            for (i, byte) in chunk.iter().enumerate() {
+                let separator = if i + 1 == chunk.len() { "" } else { " " };
+                let hex = self.paint(&format!("{byte:02x}"), address + i, *byte);
+                write!(f, "{hex}{separator}")?;
+            }
+            write!(f, "{:1$} │ ", "", (width - chunk.len()) * 3)?;
+            for (i, byte) in chunk.iter().enumerate() {
+                let character = match byte {
+                    0x20..=0x7e => char::from(*byte),
+                    _ => '.',
+                };
+                write!(
+                    f,
+                    "{}",
+                    self.paint(&character.to_string(), address + i, *byte)
+                )?;
+            }
+            writeln!(f, "{:1$} │", "", width - chunk.len())?;
+        }
+        writeln!(f, "\t└{:─<1$}┘", "", inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HexView;
+
+    #[test]
+    fn plain_layout() {
+        let bytes: Vec<u8> = (0x40..0x46).collect();
+        let view = HexView::new("Тест", &bytes)
+            .base(0x1fe)
+            .width(4)
+            .plain(true);
+        let expected = "\
+\t┌── Тест ─────────────────────┐
+\t│ 0x01fe │ 40 41 42 43 │ @ABC │
+\t│ 0x0202 │ 44 45       │ DE   │
+\t└─────────────────────────────┘
+";
+        assert_eq!(view.to_string(), expected);
+    }
+}
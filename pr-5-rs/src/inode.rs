@@ -0,0 +1,492 @@
+use crate::{
+    disk::{BlockDevice, BLOCK_SIZE},
+    perm::Mode,
+};
+use color_eyre::Result;
+use std::fmt;
+
+/// Bytes of an inode on disk.
+pub const INODE_SIZE: usize = 128;
+pub const INODES_PER_BLOCK: usize = BLOCK_SIZE / INODE_SIZE;
+/// Block pointers stored in the inode itself.
+pub const DIRECT_POINTERS: usize = 12;
+/// Block pointers in an indirect block.
+pub const POINTERS_PER_BLOCK: usize = BLOCK_SIZE / 4;
+/// Most data blocks a file can address: direct, then single, double and triple indirect.
+pub const MAX_BLOCKS: usize =
+    DIRECT_POINTERS + POINTERS_PER_BLOCK + POINTERS_PER_BLOCK.pow(2) + POINTERS_PER_BLOCK.pow(3);
+
+/// Bits of [`Inode::mode`] holding the type of the file.
+pub const S_IFMT: u16 = 0o170_000;
+pub const S_IFDIR: u16 = 0o040_000;
+pub const S_IFREG: u16 = 0o100_000;
+
+/// Unix-style index node: everything about a file except its name.
+///
+/// Block pointers are device block numbers, zero meaning "none" (block 0 is the superblock).
+/// Data blocks past the [`DIRECT_POINTERS`] are reached through indirect blocks of pointers:
+/// the single indirect block points at data, the double one at single indirect blocks,
+/// the triple one at double indirect blocks.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Inode {
+    pub(crate) mode: u16,
+    pub(crate) uid: u16,
+    pub(crate) gid: u16,
+    /// Names referring to the inode. An inode with no links is free.
+    pub(crate) links: u16,
+    /// Bytes of the contents.
+    pub(crate) size: u64,
+    /// Last access, modification and inode change time, in ticks of the filesystem clock.
+    pub(crate) atime: u64,
+    pub(crate) mtime: u64,
+    pub(crate) ctime: u64,
+    pub(crate) direct: [u32; DIRECT_POINTERS],
+    pub(crate) indirect: u32,
+    pub(crate) double_indirect: u32,
+    pub(crate) triple_indirect: u32,
+    /// Data blocks, kept in sync with the pointers so it doesn't have to be counted on every append.
+    pub(crate) block_count: u32,
+}
+
+#[allow(dead_code)]
+impl Inode {
+    /// Serialise the inode, little-endian, zero-padded to [`INODE_SIZE`].
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; INODE_SIZE] {
+        let mut bytes = Vec::with_capacity(INODE_SIZE);
+        for field in [self.mode, self.uid, self.gid, self.links] {
+            bytes.extend(field.to_le_bytes());
+        }
+        for field in [self.size, self.atime, self.mtime, self.ctime] {
+            bytes.extend(field.to_le_bytes());
+        }
+        let pointers = self.direct.iter().chain([
+            &self.indirect,
+            &self.double_indirect,
+            &self.triple_indirect,
+            &self.block_count,
+        ]);
+        for pointer in pointers {
+            bytes.extend(pointer.to_le_bytes());
+        }
+        bytes.resize(INODE_SIZE, 0);
+        bytes.try_into().unwrap_or([0; INODE_SIZE])
+    }
+
+    /// Deserialise an inode written by [`Inode::to_bytes`].
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8; INODE_SIZE]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([0, 1, 2, 3].map(|j| bytes[i + j]));
+        let u64_at = |i: usize| u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7].map(|j| bytes[i + j]));
+        let pointers = 8 + 4 * 8;
+        Self {
+            mode: u16_at(0),
+            uid: u16_at(2),
+            gid: u16_at(4),
+            links: u16_at(6),
+            size: u64_at(8),
+            atime: u64_at(16),
+            mtime: u64_at(24),
+            ctime: u64_at(32),
+            direct: std::array::from_fn(|i| u32_at(pointers + 4 * i)),
+            indirect: u32_at(pointers + 4 * DIRECT_POINTERS),
+            double_indirect: u32_at(pointers + 4 * (DIRECT_POINTERS + 1)),
+            triple_indirect: u32_at(pointers + 4 * (DIRECT_POINTERS + 2)),
+            block_count: u32_at(pointers + 4 * (DIRECT_POINTERS + 3)),
+        }
+    }
+
+    /// Whether no name refers to the inode, so it can be given to a new file.
+    #[must_use]
+    pub const fn is_free(&self) -> bool {
+        self.links == 0
+    }
+
+    #[must_use]
+    pub const fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Permission bits of the mode, without the file type.
+    #[must_use]
+    pub const fn permissions(&self) -> u16 {
+        self.mode & !S_IFMT
+    }
+
+    /// Number of data blocks.
+    #[must_use]
+    pub fn blocks(&self) -> usize {
+        self.block_count as usize
+    }
+
+    /// Number of indirect blocks the inode uses for its data blocks.
+    #[must_use]
+    pub fn metadata_blocks(&self) -> usize {
+        metadata_blocks(self.blocks())
+    }
+
+    /// Device block holding data block `index` of the file.
+    pub fn block(&self, disk: &BlockDevice, index: usize) -> Result<usize> {
+        eyre::ensure!(
+            index < self.blocks(),
+            "Block {index} is past the end of a {}-block file",
+            self.blocks()
+        );
+        let (mut pointer, path) = self.path(index);
+        for slot in path {
+            pointer = read_pointer(disk, pointer as usize, slot)?;
+        }
+        Ok(pointer as usize)
+    }
+
+    /// Device blocks holding the data of the file, in order.
+    pub fn data_blocks(&self, disk: &BlockDevice) -> Result<Vec<usize>> {
+        (0..self.blocks()).map(|i| self.block(disk, i)).collect()
+    }
+
+    /// Indirect blocks of the file, each listed before the blocks it points at.
+    pub fn indirect_blocks(&self, disk: &BlockDevice) -> Result<Vec<usize>> {
+        let mut blocks = vec![];
+        for (root, depth) in [
+            (self.indirect, 1),
+            (self.double_indirect, 2),
+            (self.triple_indirect, 3),
+        ] {
+            collect_indirect(disk, root, depth, &mut blocks)?;
+        }
+        Ok(blocks)
+    }
+
+    /// Append data block `block` to the file, allocating the indirect blocks it needs from `disk`.
+    pub fn push_block(&mut self, disk: &mut BlockDevice, block: usize) -> Result<()> {
+        let index = self.blocks();
+        eyre::ensure!(
+            index < MAX_BLOCKS,
+            "File is too big: an inode addresses at most {MAX_BLOCKS} blocks"
+        );
+        let block = pointer(block)?;
+        if index < DIRECT_POINTERS {
+            self.direct[index] = block;
+            self.block_count += 1;
+            return Ok(());
+        }
+        let (_, path) = self.path(index);
+        let root = match path.len() {
+            1 => &mut self.indirect,
+            2 => &mut self.double_indirect,
+            _ => &mut self.triple_indirect,
+        };
+        if *root == 0 {
+            *root = new_indirect_block(disk)?;
+        }
+        let mut current = *root as usize;
+        for (depth, &slot) in path.iter().enumerate() {
+            if depth + 1 == path.len() {
+                write_pointer(disk, current, slot, block)?;
+                break;
+            }
+            let mut next = read_pointer(disk, current, slot)?;
+            if next == 0 {
+                next = new_indirect_block(disk)?;
+                write_pointer(disk, current, slot, next)?;
+            }
+            current = next as usize;
+        }
+        self.block_count += 1;
+        Ok(())
+    }
+
+    /// Remove the last data block of the file and free it, along with the indirect blocks
+    /// it leaves empty. Returns the freed data block.
+    pub fn pop_block(&mut self, disk: &mut BlockDevice) -> Result<usize> {
+        eyre::ensure!(self.blocks() > 0, "File has no blocks to remove");
+        let index = self.blocks() - 1;
+        let block = self.block(disk, index)?;
+        let (root, path) = self.path(index);
+        if path.is_empty() {
+            self.direct[index] = 0;
+        } else {
+            // Indirect blocks on the way to the data block, from the one in the inode down.
+            let mut chain = vec![root as usize];
+            for &slot in &path[..path.len() - 1] {
+                let next = read_pointer(disk, chain[chain.len() - 1], slot)?;
+                chain.push(next as usize);
+            }
+            write_pointer(disk, chain[chain.len() - 1], path[path.len() - 1], 0)?;
+            // An indirect block is empty once the pointer in its first slot is gone.
+            for depth in (0..path.len()).rev() {
+                if path[depth] != 0 {
+                    break;
+                }
+                disk.free(chain[depth])?;
+                if depth > 0 {
+                    write_pointer(disk, chain[depth - 1], path[depth - 1], 0)?;
+                } else {
+                    match path.len() {
+                        1 => self.indirect = 0,
+                        2 => self.double_indirect = 0,
+                        _ => self.triple_indirect = 0,
+                    }
+                }
+            }
+        }
+        disk.free(block)?;
+        self.block_count -= 1;
+        Ok(block)
+    }
+
+    /// The pointer in the inode that leads to data block `index`,
+    /// and the slots to follow in each indirect block on the way.
+    fn path(&self, index: usize) -> (u32, Vec<usize>) {
+        let p = POINTERS_PER_BLOCK;
+        if index < DIRECT_POINTERS {
+            return (self.direct[index], vec![]);
+        }
+        let index = index - DIRECT_POINTERS;
+        if index < p {
+            return (self.indirect, vec![index]);
+        }
+        let index = index - p;
+        if index < p * p {
+            return (self.double_indirect, vec![index / p, index % p]);
+        }
+        let index = index - p * p;
+        (
+            self.triple_indirect,
+            vec![index / (p * p), index / p % p, index % p],
+        )
+    }
+}
+
+impl fmt::Display for Inode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direct: Vec<_> = self.direct.iter().take_while(|&&p| p != 0).collect();
+        writeln!(
+            f,
+            "\t\tРежим: {}{} ({:o}), UID: {}, GID: {}, ссылок: {}, размер: {} байт",
+            if self.is_dir() { 'd' } else { '-' },
+            Mode(self.permissions()),
+            self.mode,
+            self.uid,
+            self.gid,
+            self.links,
+            self.size
+        )?;
+        writeln!(
+            f,
+            "\t\tВремя доступа: {}, изменения: {}, изменения inode: {}",
+            self.atime, self.mtime, self.ctime
+        )?;
+        writeln!(f, "\t\tПрямые указатели: {direct:?}")?;
+        write!(
+            f,
+            "\t\tКосвенные указатели: одинарный {}, двойной {}, тройной {}; блоков данных: {}, служебных: {}",
+            self.indirect,
+            self.double_indirect,
+            self.triple_indirect,
+            self.blocks(),
+            self.metadata_blocks()
+        )
+    }
+}
+
+/// Number of indirect blocks needed to address `data_blocks` blocks of a file.
+#[must_use]
+pub fn metadata_blocks(data_blocks: usize) -> usize {
+    let p = POINTERS_PER_BLOCK;
+    let rest = data_blocks.saturating_sub(DIRECT_POINTERS);
+    let single = rest.min(p);
+    let double = rest.saturating_sub(p).min(p * p);
+    let triple = rest.saturating_sub(p + p * p);
+    let mut count = usize::from(single > 0);
+    if double > 0 {
+        count += 1 + double.div_ceil(p);
+    }
+    if triple > 0 {
+        count += 1 + triple.div_ceil(p * p) + triple.div_ceil(p);
+    }
+    count
+}
+
+/// Fixed-size table of [`Inode`]s stored in consecutive blocks of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeTable {
+    /// First block of the table.
+    pub(crate) start: usize,
+    pub(crate) count: usize,
+}
+
+#[allow(dead_code)]
+impl InodeTable {
+    /// Blocks the table takes.
+    #[must_use]
+    pub const fn blocks(&self) -> usize {
+        self.count.div_ceil(INODES_PER_BLOCK)
+    }
+
+    pub fn read(&self, disk: &BlockDevice, number: usize) -> Result<Inode> {
+        let (block, offset) = self.locate(number)?;
+        let bytes = &disk.block(block)?.bytes[offset..offset + INODE_SIZE];
+        Ok(Inode::from_bytes(bytes.try_into()?))
+    }
+
+    pub fn write(&self, disk: &mut BlockDevice, number: usize, inode: &Inode) -> Result<()> {
+        let (block, offset) = self.locate(number)?;
+        disk.block_mut(block)?.bytes[offset..offset + INODE_SIZE]
+            .copy_from_slice(&inode.to_bytes());
+        Ok(())
+    }
+
+    /// Number of the first free inode. Inode 0 is never given out,
+    /// so that a zero inode number can mean "none".
+    pub fn find_free(&self, disk: &BlockDevice) -> Result<usize> {
+        for number in 1..self.count {
+            if self.read(disk, number)?.is_free() {
+                return Ok(number);
+            }
+        }
+        eyre::bail!("Out of inodes: all {} are in use", self.count)
+    }
+
+    /// Block and byte offset in it of inode `number`.
+    fn locate(&self, number: usize) -> Result<(usize, usize)> {
+        eyre::ensure!(
+            number < self.count,
+            "Inode {number} is out of a table of {}",
+            self.count
+        );
+        Ok((
+            self.start + number / INODES_PER_BLOCK,
+            number % INODES_PER_BLOCK * INODE_SIZE,
+        ))
+    }
+}
+
+fn pointer(block: usize) -> Result<u32> {
+    u32::try_from(block).map_err(|_| eyre::eyre!("Block {block} can't be addressed by an inode"))
+}
+
+fn read_pointer(disk: &BlockDevice, block: usize, slot: usize) -> Result<u32> {
+    let bytes = &disk.block(block)?.bytes[4 * slot..4 * slot + 4];
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn write_pointer(disk: &mut BlockDevice, block: usize, slot: usize, value: u32) -> Result<()> {
+    disk.block_mut(block)?.bytes[4 * slot..4 * slot + 4].copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+/// Push indirect block `pointer`, which is `depth` levels above the data,
+/// and every indirect block under it onto `blocks`.
+fn collect_indirect(
+    disk: &BlockDevice,
+    pointer: u32,
+    depth: usize,
+    blocks: &mut Vec<usize>,
+) -> Result<()> {
+    if pointer == 0 {
+        return Ok(());
+    }
+    blocks.push(pointer as usize);
+    if depth > 1 {
+        for slot in 0..POINTERS_PER_BLOCK {
+            collect_indirect(
+                disk,
+                read_pointer(disk, pointer as usize, slot)?,
+                depth - 1,
+                blocks,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Allocate an indirect block, which comes with every pointer cleared.
+fn new_indirect_block(disk: &mut BlockDevice) -> Result<u32> {
+    pointer(disk.allocate(1)?[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metadata_blocks, Inode, InodeTable, DIRECT_POINTERS, POINTERS_PER_BLOCK};
+    use crate::disk::BlockDevice;
+    use color_eyre::Result;
+
+    #[test]
+    fn metadata_per_size() {
+        let p = POINTERS_PER_BLOCK;
+        assert_eq!(metadata_blocks(DIRECT_POINTERS), 0);
+        assert_eq!(metadata_blocks(DIRECT_POINTERS + 1), 1);
+        assert_eq!(metadata_blocks(DIRECT_POINTERS + p), 1);
+        assert_eq!(metadata_blocks(DIRECT_POINTERS + p + 1), 3);
+        assert_eq!(metadata_blocks(DIRECT_POINTERS + p + p * p), 2 + p);
+        assert_eq!(metadata_blocks(DIRECT_POINTERS + p + p * p + 1), 2 + p + 3);
+    }
+
+    #[test]
+    fn pointers_through_indirect_blocks() -> Result<()> {
+        let p = POINTERS_PER_BLOCK;
+        let count = DIRECT_POINTERS + p + 3;
+        let mut disk = BlockDevice::new(count + 8);
+        disk.allocate(1)?;
+        let mut inode = Inode::default();
+        let mut data = vec![];
+        for _ in 0..count {
+            let block = disk.allocate(1)?[0];
+            inode.push_block(&mut disk, block)?;
+            data.push(block);
+        }
+        assert_eq!(inode.data_blocks(&disk)?, data);
+        assert_eq!(inode.metadata_blocks(), 3);
+        let indirect = inode.indirect_blocks(&disk)?;
+        assert_eq!(indirect.len(), 3);
+        assert_eq!(indirect[0], inode.indirect as usize);
+        assert_eq!(indirect[1], inode.double_indirect as usize);
+        assert_eq!(disk.free_count(), 8 - 1 - 3);
+        assert!(inode.block(&disk, count).is_err());
+
+        // Shrinking frees the indirect blocks as they empty out.
+        for block in data.iter().rev().take(3) {
+            assert_eq!(inode.pop_block(&mut disk)?, *block);
+        }
+        assert_eq!(inode.double_indirect, 0);
+        assert_eq!(inode.indirect_blocks(&disk)?.len(), 1);
+        assert_eq!(disk.free_count(), 4 + 3 + 2);
+        while inode.blocks() > 0 {
+            inode.pop_block(&mut disk)?;
+        }
+        assert_eq!(inode, Inode::default());
+        assert_eq!(disk.free_count(), count + 8 - 1);
+        Ok(())
+    }
+
+    #[test]
+    fn table_round_trips() -> Result<()> {
+        let mut disk = BlockDevice::new(4);
+        let table = InodeTable { start: 1, count: 8 };
+        for number in 0..table.count {
+            table.write(&mut disk, number, &Inode::default())?;
+        }
+        let inode = Inode {
+            mode: 0o644,
+            uid: 3,
+            gid: 4,
+            links: 1,
+            size: 70_000,
+            atime: 5,
+            mtime: 6,
+            ctime: 7,
+            direct: std::array::from_fn(|i| i as u32 + 10),
+            indirect: 30,
+            double_indirect: 31,
+            triple_indirect: 32,
+            block_count: 137,
+        };
+        table.write(&mut disk, 5, &inode)?;
+        assert_eq!(table.read(&disk, 5)?, inode);
+        assert_eq!(table.find_free(&disk)?, 1);
+        assert!(table.read(&disk, 8).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,471 @@
+//! A tiny instruction set for [`Process::instructions`](crate::process::Process).
+//!
+//! The machine has four 8-bit registers `r0`–`r3` and a program counter. A process sees its own
+//! bytes in its [`Page`] as addresses `0..len`: code and data share that space, and every
+//! instruction fetch, load and store is recorded as a [`Reference`] for the paging subsystem.
+//!
+//! | Encoding           | Mnemonic            | Effect                                   |
+//! |--------------------|---------------------|------------------------------------------|
+//! | `00`               | `halt`              | stop with exit code 0                    |
+//! | `1n lo hi`         | `load rn, [addr]`   | `rn = mem[addr]`                         |
+//! | `2n lo hi`         | `store rn, [addr]`  | `mem[addr] = rn`                         |
+//! | `3n imm`           | `li rn, imm`        | `rn = imm`                               |
+//! | `4(d<<2 \| s)`     | `add rd, rs`        | `rd = rd + rs`, wrapping                 |
+//! | `50 lo hi`         | `jmp addr`          | `pc = addr`                              |
+//! | `6n lo hi`         | `jnz rn, addr`      | `pc = addr` if `rn != 0`                 |
+//! | `70 n`             | `syscall n`         | 0: exit with code `r0`, 1: write `r0`    |
+//!
+//! Addresses are little-endian. In assembly, an address may be a number or a label,
+//! and `.byte a, b, ...` places raw bytes.
+
+use crate::{
+    page::Page,
+    trace::{parse_number, Op, Reference},
+};
+use color_eyre::Result;
+use std::{collections::HashMap, fmt};
+
+/// Number of general purpose registers.
+pub const REGISTER_COUNT: usize = 4;
+
+/// Default limit of instructions [`execute`] runs, so a looping program still stops.
+pub const DEFAULT_STEPS: usize = 1000;
+
+/// Syscall that stops the process with the exit code in `r0`.
+pub const SYS_EXIT: u8 = 0;
+/// Syscall that appends `r0` to the output of the process.
+pub const SYS_WRITE: u8 = 1;
+
+/// A decoded instruction. Register operands are register numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Halt,
+    Load { reg: u8, addr: u16 },
+    Store { reg: u8, addr: u16 },
+    LoadImmediate { reg: u8, value: u8 },
+    Add { dst: u8, src: u8 },
+    Jump { addr: u16 },
+    JumpIfNotZero { reg: u8, addr: u16 },
+    Syscall { number: u8 },
+}
+
+impl Instruction {
+    /// Size of the encoded instruction in bytes.
+    #[must_use]
+    pub const fn len(self) -> usize {
+        match self {
+            Self::Halt | Self::Add { .. } => 1,
+            Self::LoadImmediate { .. } | Self::Syscall { .. } => 2,
+            Self::Load { .. }
+            | Self::Store { .. }
+            | Self::Jump { .. }
+            | Self::JumpIfNotZero { .. } => 3,
+        }
+    }
+
+    /// Append the encoded instruction to `bytes`.
+    pub fn encode(self, bytes: &mut Vec<u8>) {
+        let [lo, hi] = match self {
+            Self::Load { addr, .. }
+            | Self::Store { addr, .. }
+            | Self::Jump { addr }
+            | Self::JumpIfNotZero { addr, .. } => addr.to_le_bytes(),
+            _ => [0, 0],
+        };
+        match self {
+            Self::Halt => bytes.push(0x00),
+            Self::Load { reg, .. } => bytes.extend([0x10 | reg, lo, hi]),
+            Self::Store { reg, .. } => bytes.extend([0x20 | reg, lo, hi]),
+            Self::LoadImmediate { reg, value } => bytes.extend([0x30 | reg, value]),
+            Self::Add { dst, src } => bytes.push(0x40 | dst << 2 | src),
+            Self::Jump { .. } => bytes.extend([0x50, lo, hi]),
+            Self::JumpIfNotZero { reg, .. } => bytes.extend([0x60 | reg, lo, hi]),
+            Self::Syscall { number } => bytes.extend([0x70, number]),
+        }
+    }
+
+    /// Decode the instruction at the start of `bytes`, returning `None` if it's invalid
+    /// or doesn't fit.
+    #[must_use]
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let &opcode = bytes.first()?;
+        let reg = opcode & 0x0F;
+        let addr = || Some(u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]));
+        let valid_reg = usize::from(reg) < REGISTER_COUNT;
+        Some(match opcode >> 4 {
+            0x0 if opcode == 0 => Self::Halt,
+            0x1 if valid_reg => Self::Load { reg, addr: addr()? },
+            0x2 if valid_reg => Self::Store { reg, addr: addr()? },
+            0x3 if valid_reg => Self::LoadImmediate {
+                reg,
+                value: *bytes.get(1)?,
+            },
+            0x4 => Self::Add {
+                dst: reg >> 2,
+                src: reg & 0b11,
+            },
+            0x5 if reg == 0 => Self::Jump { addr: addr()? },
+            0x6 if valid_reg => Self::JumpIfNotZero { reg, addr: addr()? },
+            0x7 if reg == 0 => Self::Syscall {
+                number: *bytes.get(1)?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Halt => write!(f, "halt"),
+            Self::Load { reg, addr } => write!(f, "load r{reg}, [{addr:#04x}]"),
+            Self::Store { reg, addr } => write!(f, "store r{reg}, [{addr:#04x}]"),
+            Self::LoadImmediate { reg, value } => write!(f, "li r{reg}, {value}"),
+            Self::Add { dst, src } => write!(f, "add r{dst}, r{src}"),
+            Self::Jump { addr } => write!(f, "jmp {addr:#04x}"),
+            Self::JumpIfNotZero { reg, addr } => write!(f, "jnz r{reg}, {addr:#04x}"),
+            Self::Syscall { number } => write!(f, "syscall {number}"),
+        }
+    }
+}
+
+fn register(s: &str) -> Result<u8> {
+    s.strip_prefix('r')
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| usize::from(n) < REGISTER_COUNT)
+        .ok_or(eyre::eyre!(
+            "expected a register r0-r{}, got '{s}'",
+            REGISTER_COUNT - 1
+        ))
+}
+
+/// A line of assembly after the first pass: either an instruction whose address operand may still
+/// be a label, or raw bytes.
+enum Item<'a> {
+    Instruction(Instruction, Option<&'a str>),
+    Bytes(Vec<u8>),
+}
+
+impl Item<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Instruction(instruction, _) => instruction.len(),
+            Self::Bytes(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// Parse an address operand, optionally in brackets: a number, or a label to be resolved
+/// in the second pass.
+fn address(operand: &str) -> Result<(u16, Option<&str>)> {
+    let operand = operand
+        .strip_prefix('[')
+        .and_then(|operand| operand.strip_suffix(']'))
+        .unwrap_or(operand);
+    if operand.starts_with(|c: char| c.is_ascii_digit()) {
+        Ok((parse_number("number", operand)?, None))
+    } else {
+        Ok((0, Some(operand)))
+    }
+}
+
+/// Parse the statement of a line, leaving labels in address operands unresolved.
+fn parse_statement(statement: &str) -> Result<Item<'_>> {
+    let (mnemonic, operands) = statement
+        .split_once(char::is_whitespace)
+        .unwrap_or((statement, ""));
+    let operands: Vec<&str> = operands
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect();
+    let instruction = |instruction| Ok(Item::Instruction(instruction, None));
+    match (mnemonic.to_lowercase().as_str(), &operands[..]) {
+        ("halt", []) => instruction(Instruction::Halt),
+        ("load", [reg, addr]) => {
+            let (addr, label) = address(addr)?;
+            let reg = register(reg)?;
+            Ok(Item::Instruction(Instruction::Load { reg, addr }, label))
+        }
+        ("store", [reg, addr]) => {
+            let (addr, label) = address(addr)?;
+            let reg = register(reg)?;
+            Ok(Item::Instruction(Instruction::Store { reg, addr }, label))
+        }
+        ("li", [reg, value]) => instruction(Instruction::LoadImmediate {
+            reg: register(reg)?,
+            value: parse_number("number", value)?,
+        }),
+        ("add", [dst, src]) => instruction(Instruction::Add {
+            dst: register(dst)?,
+            src: register(src)?,
+        }),
+        ("jmp", [addr]) => {
+            let (addr, label) = address(addr)?;
+            Ok(Item::Instruction(Instruction::Jump { addr }, label))
+        }
+        ("jnz", [reg, addr]) => {
+            let (addr, label) = address(addr)?;
+            let reg = register(reg)?;
+            Ok(Item::Instruction(
+                Instruction::JumpIfNotZero { reg, addr },
+                label,
+            ))
+        }
+        ("syscall", [n]) => instruction(Instruction::Syscall {
+            number: parse_number("number", n)?,
+        }),
+        (".byte", bytes) if !bytes.is_empty() => Ok(Item::Bytes(
+            bytes
+                .iter()
+                .map(|&b| parse_number("number", b))
+                .collect::<Result<_>>()?,
+        )),
+        ("halt" | "load" | "store" | "li" | "add" | "jmp" | "jnz" | "syscall" | ".byte", _) => {
+            eyre::bail!("wrong number of operands for '{mnemonic}'")
+        }
+        _ => eyre::bail!("unknown mnemonic '{mnemonic}'"),
+    }
+}
+
+/// Assemble a program, one statement per line, optionally preceded by a `label:`.
+/// Everything after a `;` is a comment.
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let mut labels: HashMap<&str, u16> = HashMap::new();
+    let mut items = vec![];
+    let mut addr = 0;
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            eyre::ensure!(
+                !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '_'),
+                "Assembly line {number}: invalid label '{label}'"
+            );
+            let here = u16::try_from(addr)
+                .map_err(|_| eyre::eyre!("Assembly line {number}: program is too big"))?;
+            if labels.insert(label, here).is_some() {
+                eyre::bail!("Assembly line {number}: label '{label}' is already defined");
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let item = parse_statement(line).map_err(|e| eyre::eyre!("Assembly line {number}: {e}"))?;
+        addr += item.len();
+        items.push((number, item));
+    }
+
+    let mut bytes = vec![];
+    for (number, item) in items {
+        match item {
+            Item::Bytes(data) => bytes.extend(data),
+            Item::Instruction(mut instruction, label) => {
+                if let Some(label) = label {
+                    let target = *labels.get(label).ok_or(eyre::eyre!(
+                        "Assembly line {number}: unknown label '{label}'"
+                    ))?;
+                    match &mut instruction {
+                        Instruction::Load { addr, .. }
+                        | Instruction::Store { addr, .. }
+                        | Instruction::Jump { addr }
+                        | Instruction::JumpIfNotZero { addr, .. } => *addr = target,
+                        _ => {}
+                    }
+                }
+                instruction.encode(&mut bytes);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Result of [`execute`]ing a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    /// Exit code, or `None` if the process was still running when the step limit was reached.
+    pub exit: Option<u8>,
+    /// Instructions executed.
+    pub steps: usize,
+    pub registers: [u8; REGISTER_COUNT],
+    /// Bytes written with [`SYS_WRITE`].
+    pub output: Vec<u8>,
+    /// Every memory reference the process made, in order, as virtual addresses of the process.
+    pub trace: Vec<Reference>,
+}
+
+/// Run process `pid` out of `page`, where it must be loaded, for at most `max_steps` instructions.
+/// Fails on an invalid instruction or an access outside of the process.
+pub fn execute(page: &Page, pid: u16, max_steps: usize) -> Result<Execution> {
+    let region = page.map.get(&pid).cloned().ok_or(eyre::eyre!(
+        "Cannot execute: process with pid {pid} isn't loaded into page {}",
+        page.id
+    ))?;
+    let size = region.len();
+    let base = page.range.start + region.start;
+    let ram = &page.ram;
+    let mut execution = Execution {
+        exit: None,
+        steps: 0,
+        registers: [0; REGISTER_COUNT],
+        output: vec![],
+        trace: vec![],
+    };
+    let reference = |vaddr: usize, op| {
+        eyre::ensure!(
+            vaddr < size,
+            "Segmentation fault: pid {pid} accessed {vaddr:#04x}, but it only has {size} bytes"
+        );
+        Ok(Reference { pid, vaddr, op })
+    };
+    let mut pc = 0;
+    while execution.steps < max_steps {
+        execution.trace.push(reference(pc, Op::Read)?);
+        let bytes = ram.fetch(base + pc, (size - pc).min(3))?;
+        let instruction = Instruction::decode(&bytes).ok_or(eyre::eyre!(
+            "Illegal instruction {:#04x} at {pc:#04x} in pid {pid}",
+            bytes[0]
+        ))?;
+        execution.steps += 1;
+        let registers = &mut execution.registers;
+        pc += instruction.len();
+        match instruction {
+            Instruction::Halt => {
+                execution.exit = Some(0);
+                break;
+            }
+            Instruction::Load { reg, addr } => {
+                execution.trace.push(reference(addr.into(), Op::Read)?);
+                registers[usize::from(reg)] = ram.read_u8(base + usize::from(addr))?;
+            }
+            Instruction::Store { reg, addr } => {
+                execution.trace.push(reference(addr.into(), Op::Write)?);
+                ram.write_u8(base + usize::from(addr), registers[usize::from(reg)])?;
+            }
+            Instruction::LoadImmediate { reg, value } => registers[usize::from(reg)] = value,
+            Instruction::Add { dst, src } => {
+                let sum = registers[usize::from(dst)].wrapping_add(registers[usize::from(src)]);
+                registers[usize::from(dst)] = sum;
+            }
+            Instruction::Jump { addr } => pc = addr.into(),
+            Instruction::JumpIfNotZero { reg, addr } => {
+                if registers[usize::from(reg)] != 0 {
+                    pc = addr.into();
+                }
+            }
+            Instruction::Syscall { number: SYS_EXIT } => {
+                execution.exit = Some(registers[0]);
+                break;
+            }
+            Instruction::Syscall { number: SYS_WRITE } => execution.output.push(registers[0]),
+            Instruction::Syscall { number } => {
+                eyre::bail!("Unknown syscall {number} at {:#04x} in pid {pid}", pc - 2)
+            }
+        }
+    }
+    Ok(execution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, execute, Instruction};
+    use crate::{
+        config::MemoryConfig,
+        page::Page,
+        page_table::PageTableKind,
+        process::Process,
+        ram::Ram,
+        replacement::PolicyKind,
+        trace::{self, Op},
+    };
+    use color_eyre::Result;
+    use std::rc::Rc;
+
+    /// Count down from 3, writing every value, then exit with the value at `result`.
+    const COUNTDOWN: &str = "
+        start:  li r0, 3
+                li r1, 0xFF     ; -1
+        loop:   syscall 1
+                add r0, r1
+                jnz r0, loop
+                store r1, [result]
+                load r0, [result]
+                syscall 0
+        result: .byte 0
+    ";
+
+    #[test]
+    fn encoding_round_trips() {
+        let instructions = [
+            Instruction::Halt,
+            Instruction::Load {
+                reg: 1,
+                addr: 0x1234,
+            },
+            Instruction::Store { reg: 3, addr: 7 },
+            Instruction::LoadImmediate { reg: 2, value: 9 },
+            Instruction::Add { dst: 3, src: 1 },
+            Instruction::Jump { addr: 2 },
+            Instruction::JumpIfNotZero { reg: 0, addr: 5 },
+            Instruction::Syscall { number: 1 },
+        ];
+        for instruction in instructions {
+            let mut bytes = vec![];
+            instruction.encode(&mut bytes);
+            assert_eq!(bytes.len(), instruction.len());
+            assert_eq!(Instruction::decode(&bytes), Some(instruction));
+        }
+        assert_eq!(Instruction::decode(&[0x14, 0, 0]), None);
+        assert_eq!(Instruction::decode(&[0x10, 0]), None);
+    }
+
+    #[test]
+    fn assembler_errors_name_the_line() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+        assert!(error("halt\nmul r0, r1").starts_with("Assembly line 2: unknown mnemonic"));
+        assert!(error("jmp nowhere").starts_with("Assembly line 1: unknown label 'nowhere'"));
+        assert!(error("li r4, 1").starts_with("Assembly line 1: expected a register"));
+        assert!(error("a: halt\na: halt").starts_with("Assembly line 2: label 'a'"));
+        assert!(error("add r0").starts_with("Assembly line 1: wrong number of operands"));
+    }
+
+    #[test]
+    fn runs_out_of_a_page() -> Result<()> {
+        let code = assemble(COUNTDOWN)?;
+        assert_eq!(code.len(), 19);
+        let ram = Rc::new(Ram::default());
+        let mut page = Page::new(1, &ram);
+        page.load_process(&Process::with_size(1, 8))?;
+        page.load_process(&Process::from_image(2, code))?;
+
+        let execution = execute(&page, 2, 100)?;
+        assert_eq!(execution.output, [3, 2, 1]);
+        assert_eq!(execution.exit, Some(0xFF));
+        assert_eq!(execution.steps, 2 + 3 * 3 + 3);
+        let writes: Vec<_> = execution
+            .trace
+            .iter()
+            .filter(|r| r.op == Op::Write)
+            .map(|r| r.vaddr)
+            .collect();
+        assert_eq!(writes, [18]);
+        // The trace can be replayed by the pager.
+        let report = trace::run(
+            &execution.trace,
+            MemoryConfig::default(),
+            None,
+            PolicyKind::Fifo,
+            PageTableKind::Flat,
+            None,
+        )?;
+        assert_eq!(report.stats.misses, 1);
+
+        assert_eq!(execute(&page, 2, 4)?.exit, None);
+        page.load_process(&Process::from_image(3, assemble("jmp 0x40")?))?;
+        assert!(execute(&page, 3, 10).is_err());
+        assert!(execute(&page, 9, 10).is_err());
+        Ok(())
+    }
+}
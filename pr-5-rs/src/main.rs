@@ -1,38 +1,315 @@
+mod alloc;
+mod bitmap;
+mod cli;
+mod config;
+mod cow;
+mod dir;
+mod disk;
 mod file;
+mod handle;
+mod hexview;
+mod inode;
+mod isa;
 mod page;
+mod page_table;
+mod pager;
+mod perm;
 mod process;
 mod ram;
+mod replacement;
+mod rng;
+mod scheduler;
+mod segment;
+mod snapshot;
+mod tlb;
+mod trace;
+mod workload;
+mod workset;
 
 use crate::{
-    page::{Page, PAGE_SIZE},
-    process::{Process, PROCESS_SIZE},
+    alloc::{AllocatorKind, ContiguousMemory, Fit},
+    cli::{Cli, Command, USAGE},
+    config::MemoryConfig,
+    cow::CowMemory,
+    page::Page,
+    page_table::PageTableKind,
+    pager::compare_policies,
+    process::Process,
     ram::Ram,
+    replacement::{Clock, Fifo, Lru, Optimal, PolicyKind, ReplacementPolicy},
+    rng::SEED_VAR,
+    scheduler::SchedulerKind,
+    segment::{SegmentKind, SegmentLayout, SegmentedMemory},
 };
-use color_eyre::{owo_colors::OwoColorize, Result};
-use file::{File, Filesystem};
-use page::MAX_PAGE_COUNT;
+use color_eyre::Result;
 use rand::Rng;
-use std::{fmt::Display, rc::Rc};
+use std::{collections::HashMap, fs, io, path::Path, rc::Rc};
+#[cfg(feature = "fs")]
+use {
+    colored::Colorize,
+    disk::{BLOCK_SIZE, DEFAULT_BLOCK_COUNT},
+    file::{File, Filesystem},
+    handle::OpenFlags,
+    pager::Pager,
+    perm::Credentials,
+    ram::AccessKind,
+    std::{cell::RefCell, fmt::Display, io::SeekFrom},
+};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
+    let cli = Cli::parse(std::env::args().skip(1))?;
+    let seed = rng::init(cli.seed)?;
+    if cli.plain {
+        colored::control::set_override(false);
+    }
+    hexview::set_row_width(cli.hex_width);
+    eprintln!("\t\tЗерно генератора: {seed} (повторить: --seed {seed} или {SEED_VAR}={seed})");
+
+    match cli.command {
+        Command::Demo { save } => demo(cli.memory, save.as_deref()),
+        Command::Inspect { path } => {
+            let file = fs::File::open(&path)
+                .map_err(|e| eyre::eyre!("Cannot open snapshot {}: {e}", path.display()))?;
+            let (ram, pages) = snapshot::load(io::BufReader::new(file))?;
+            for page in &pages {
+                let mut pids: Vec<_> = page.map.keys().collect();
+                pids.sort_unstable();
+                println!(
+                    "\t\tСтраница №{}: процессов {}, PID: {pids:?}",
+                    page.id, page.loaded_processes
+                );
+            }
+            println!("{ram}");
+            Ok(())
+        }
+        Command::Alloc { steps } => compare_allocators(cli.memory, steps),
+        Command::Schedule {
+            input,
+            algorithms,
+            quantum,
+            count,
+        } => {
+            let processes = match input {
+                Some(path) => workload::parse(
+                    &fs::read_to_string(&path)
+                        .map_err(|e| eyre::eyre!("Cannot read workload {}: {e}", path.display()))?,
+                    cli.memory,
+                )?,
+                None => scheduler::random_workload(count),
+            };
+            compare_schedulers(&processes, &algorithms, quantum)
+        }
+        Command::Run {
+            input,
+            steps,
+            emit_trace,
+        } => {
+            let source = fs::read_to_string(&input)
+                .map_err(|e| eyre::eyre!("Cannot read program {}: {e}", input.display()))?;
+            run_program(cli.memory, &source, steps, emit_trace)
+        }
+        Command::Mode { mode } => {
+            println!("\t\t{mode} ({:04o})", mode.0);
+            Ok(())
+        }
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        Command::Trace {
+            input,
+            policy,
+            frames,
+            page_tables,
+            tlb,
+            working_set,
+        } => {
+            let input = match input {
+                Some(path) => fs::read_to_string(&path)
+                    .map_err(|e| eyre::eyre!("Cannot read trace {}: {e}", path.display()))?,
+                None => io::read_to_string(io::stdin())?,
+            };
+            let trace = trace::parse(&input)?;
+            if let Some(working_set) = working_set {
+                let report = workset::run(&trace, cli.memory, frames, policy, working_set)?;
+                println!("{report}");
+                return Ok(());
+            }
+            if let [page_table] = page_tables[..] {
+                let report = trace::run(&trace, cli.memory, frames, policy, page_table, tlb)?;
+                println!("{report}");
+                return Ok(());
+            }
+            println!(
+                "\t\tСравнение таблиц страниц на {} обращениях...",
+                trace.len()
+            );
+            for page_table in page_tables {
+                let report = trace::run(&trace, cli.memory, frames, policy, page_table, tlb)?;
+                println!("\t\t{:>16}: {}", report.page_table, report.translation);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Assemble `source`, execute it out of a page of RAM and replay its memory references through
+/// a [`Pager`](pager::Pager), or only print them as a trace if `emit_trace`.
+fn run_program(config: MemoryConfig, source: &str, steps: usize, emit_trace: bool) -> Result<()> {
+    const PID: u16 = 1;
+
+    let ram = Rc::new(Ram::new(config));
+    let mut page = Page::new(0, &ram);
+    page.load_process(&Process::from_image(PID, isa::assemble(source)?))?;
+    let execution = isa::execute(&page, PID, steps)?;
+    if emit_trace {
+        for reference in &execution.trace {
+            println!("{reference}");
+        }
+        return Ok(());
+    }
+
+    println!("{page}");
+    println!(
+        "\t\tВыполнено инструкций: {}, обращений к памяти: {}",
+        execution.steps,
+        execution.trace.len()
+    );
+    println!("\t\tВывод программы: {:?}", execution.output);
+    match execution.exit {
+        Some(code) => println!("\t\tКод возврата: {code}"),
+        None => println!("\t\tПрограмма не завершилась за {steps} инструкций"),
+    }
+    println!("\t\tРегистры: {:?}", execution.registers);
+    let report = trace::run(
+        &execution.trace,
+        config,
+        None,
+        PolicyKind::default(),
+        PageTableKind::default(),
+        None,
+    )?;
+    println!("{report}");
+    Ok(())
+}
+
+/// Schedule `processes` with each of `algorithms`, then compare their average times.
+fn compare_schedulers(
+    processes: &[Process],
+    algorithms: &[SchedulerKind],
+    quantum: usize,
+) -> Result<()> {
+    println!("\t\tПроцессы (PID: прибытие, приоритет, интервалы CPU/ввода-вывода):");
+    for process in processes {
+        let bursts: Vec<_> = process.bursts.iter().map(ToString::to_string).collect();
+        println!(
+            "\t\t{:5}: {}, {}, {}",
+            process.pid,
+            process.arrival,
+            process.priority,
+            bursts.join(", ")
+        );
+    }
+    let schedules = algorithms
+        .iter()
+        .map(|&kind| scheduler::run(processes.to_vec(), kind, quantum))
+        .collect::<Result<Vec<_>>>()?;
+    for schedule in &schedules {
+        println!("{schedule}");
+    }
+    if let [_, _, ..] = schedules[..] {
+        println!("\n\t\tСравнение алгоритмов (ожидание / оборот / отклик):");
+        for schedule in &schedules {
+            println!(
+                "\t\t{:>10}: {:6.2} / {:6.2} / {:6.2}",
+                schedule.kind,
+                schedule.average_waiting(),
+                schedule.average_turnaround(),
+                schedule.average_response()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Load and unload random-sized processes with every contiguous allocator and report fragmentation.
+fn compare_allocators(config: MemoryConfig, steps: usize) -> Result<()> {
+    const MAX_PROCESS_SIZE: usize = 96;
+
+    // Generate the workload up front, so every allocator sees the same one.
+    let mut loaded: Vec<u16> = vec![];
+    let workload: Vec<(u16, Option<usize>)> = rng::with(|rng| {
+        (0..steps)
+            .map(|step| {
+                if !loaded.is_empty() && rng.gen_bool(0.35) {
+                    (loaded.swap_remove(rng.gen_range(0..loaded.len())), None)
+                } else {
+                    let pid = u16::try_from(step).unwrap_or(u16::MAX);
+                    loaded.push(pid);
+                    (pid, Some(rng.gen_range(1..=MAX_PROCESS_SIZE)))
+                }
+            })
+            .collect()
+    });
+    let processes: HashMap<u16, Process> = workload
+        .iter()
+        .filter_map(|&(pid, size)| Some((pid, Process::with_size(pid, size?))))
+        .collect();
+
+    for kind in AllocatorKind::ALL {
+        let ram = Rc::new(Ram::new(config));
+        // The buddy allocator can't work with every RAM size, the others still get compared.
+        let allocator = match kind.build(ram.size()) {
+            Ok(allocator) => allocator,
+            Err(e) => {
+                println!("\n\t\tАлгоритм пропущен: {e}");
+                continue;
+            }
+        };
+        let mut memory = ContiguousMemory::new(&ram, allocator)?;
+        let mut failures = 0;
+        for (pid, size) in &workload {
+            match size {
+                Some(_) => {
+                    if memory.load_process(&processes[pid]).is_err() {
+                        failures += 1;
+                    }
+                }
+                None => {
+                    let _ = memory.unload_process(*pid);
+                }
+            }
+        }
+        println!(
+            "\n\t\t{}: не удалось разместить процессов: {failures}",
+            memory.allocator.name()
+        );
+        println!("{}", memory.allocator.report());
+    }
+    Ok(())
+}
+
+/// Load random processes into RAM and, with the `fs` feature, play around with a file.
+/// The RAM is saved to `save` as a [`snapshot`] right after the processes are loaded.
+fn demo(config: MemoryConfig, save: Option<&Path>) -> Result<()> {
     #[cfg(feature = "ram")]
     {
-        let ram = Rc::new(Ram::default());
+        let ram = Rc::new(Ram::new(config));
         let mut pages: Vec<Page> = vec![];
-        for i in 0..MAX_PAGE_COUNT {
+        for i in 0..config.frame_count() {
             pages.push(Page::new(i, &ram));
             pages[i].id = i + 1;
 
-            let mut rng = rand::thread_rng();
-            let process_count: usize = rng.gen_range(1..PAGE_SIZE / PROCESS_SIZE);
+            let process_count: usize =
+                rng::with(|rng| rng.gen_range(1..config.slot_count().max(2)));
             println!(
                 "\t\tЗагрузка {process_count} процессов в RAM на страницу №{}...",
                 pages[i].id
             );
             for _ in 0..process_count {
-                let process = Process::with_pid(rng.gen());
+                let process = Process::with_size(rng::with(|rng| rng.gen()), config.process_size);
                 println!("{process}");
                 pages[i].load_process(&process)?;
                 println!(
@@ -42,6 +319,83 @@ fn main() -> Result<()> {
             }
             println!("{}", pages[i]);
         }
+        println!("{ram}");
+        if let Some(path) = save {
+            snapshot::save(&ram, &pages, fs::File::create(path)?)?;
+            println!("\t\tСнимок RAM сохранён в {}", path.display());
+        }
+
+        const PROCESS_COUNT: u16 = 4;
+        const REFERENCE_COUNT: usize = 16;
+        let processes: Vec<Process> = (0..PROCESS_COUNT)
+            .map(|pid| Process::with_size(pid, config.process_size))
+            .collect();
+        let refs: Vec<(u16, usize)> = rng::with(|rng| {
+            (0..REFERENCE_COUNT)
+                .map(|_| {
+                    (
+                        rng.gen_range(0..PROCESS_COUNT),
+                        rng.gen_range(0..config.process_size),
+                    )
+                })
+                .collect()
+        });
+        let future = refs
+            .iter()
+            .map(|&(pid, vaddr)| (pid, config.split_vaddr(vaddr).0))
+            .collect();
+        let policies: Vec<Box<dyn ReplacementPolicy>> = vec![
+            Box::<Fifo>::default(),
+            Box::<Lru>::default(),
+            Box::<Clock>::default(),
+            Box::new(Optimal::new(future)),
+        ];
+        let frames = config.frame_count();
+        println!("\t\tСравнение алгоритмов замещения страниц на {frames} кадрах...");
+        for (name, stats) in compare_policies(config, frames, &processes, &refs, policies)? {
+            println!("\t\t{name:>8}: {stats}");
+        }
+
+        let ram = Rc::new(Ram::new(config));
+        let mut memory =
+            SegmentedMemory::contiguous(&ram, AllocatorKind::Fit(Fit::First).build(ram.size())?)?;
+        let process = Process::with_size(rng::with(|rng| rng.gen()), config.process_size);
+        println!("\t\tЗагрузка процесса {} сегментами...", process.pid);
+        // Every segment takes a slot, so the layout scales with the geometry.
+        let layout = SegmentLayout {
+            data: config.process_size,
+            heap: config.process_size,
+            stack: config.process_size,
+        };
+        match memory.load_process(&process, &layout) {
+            Ok(()) => {
+                if let Some(table) = memory.table(process.pid) {
+                    println!("{table}");
+                }
+                if let Err(e) = memory.write(process.pid, SegmentKind::Code, 0, &[0]) {
+                    println!("\t\tЗапись в сегмент кода: {e}");
+                }
+                memory.unload_process(process.pid)?;
+            }
+            Err(e) => println!("\t\tНе удалось загрузить процесс сегментами: {e}"),
+        }
+
+        let ram = Rc::new(Ram::new(config));
+        let mut memory = CowMemory::new(&ram);
+        let parent = Process::with_size(rng::with(|rng| rng.gen()), config.page_size);
+        let child_pid = parent.pid.wrapping_add(1);
+        println!(
+            "\t\tРазвилка процесса {} в процесс {child_pid} с копированием при записи...",
+            parent.pid
+        );
+        memory.load_process(&parent)?;
+        memory.fork(parent.pid, child_pid)?;
+        println!("{memory}");
+        println!("\t\tЗапись процессом {child_pid} в свою страницу...");
+        match memory.write(child_pid, 0, &[0; 8]) {
+            Ok(()) => println!("{memory}"),
+            Err(e) => println!("\t\tЗапись не удалась: {e}"),
+        }
     }
 
     #[cfg(feature = "fs")]
@@ -49,31 +403,97 @@ fn main() -> Result<()> {
         const DISPLAY_BLOCK_COUNT: usize = 2;
 
         status_message(&"Создание файловой системы...");
-        let mut fs = Filesystem::default();
+        let mut fs = Filesystem::new(DEFAULT_BLOCK_COUNT)?;
+
+        status_message(&"Создание каталогов /src и /tmp, переход в /src...");
+        fs.mkdir("/src")?;
+        fs.mkdir("/tmp")?;
+        fs.chdir("/src")?;
 
         status_message(&"Создание файла...");
         let mut file = File::default();
 
         status_message(&"Переименование файла в main.rs...");
         file.rename(&"main.rs");
+        fs.add_file(&file)?;
 
         status_message(&format!(
             "Резервирование {DISPLAY_BLOCK_COUNT} блоков для файла {}...",
             file.name
         ));
-        file.reserve(DISPLAY_BLOCK_COUNT);
+        fs.reserve(&file.name, DISPLAY_BLOCK_COUNT)?;
 
-        file.show_blocks();
-        file.reserve(64 * 1024 / 512 - DISPLAY_BLOCK_COUNT);
-        fs.add_file(&file);
+        status_message(&format!(
+            "Запись исходного кода этой программы в файл {}...",
+            file.name
+        ));
+        let fd = fs.open(&file.name, OpenFlags::RW)?;
+        fs.write(fd, include_str!("main.rs").as_bytes())?;
+        fs.seek(fd, SeekFrom::Start(0))?;
+        let first_line = fs.read(fd, 64)?;
+        let first_line = String::from_utf8_lossy(&first_line);
+        status_message(&format!(
+            "Первая строка файла: {}",
+            first_line.lines().next().unwrap_or_default()
+        ));
+        fs.close(fd)?;
+        fs.show_blocks(&file.name, DISPLAY_BLOCK_COUNT)?;
+
+        status_message(&format!(
+            "Попытка удалить файл {} от имени пользователя guest...",
+            file.name
+        ));
+        fs.login(Credentials::new("guest", &["guest"]));
+        if let Err(e) = fs.delete(&file.name) {
+            status_message(&e.to_string());
+        }
+        fs.login(Credentials::root());
 
         status_message(&format!("Резервирование 64КБ для файла {}...", file.name));
+        let blocks = fs.stat(&file.name)?.blocks();
+        fs.reserve(&file.name, 64 * 1024 / BLOCK_SIZE - blocks)?;
         status_message(&format!("Файловая система использует {} байт.", fs.usage()));
+
+        let mut big = File::default();
+        big.rename(&"../tmp/big.bin");
+        fs.add_file(&big)?;
+        status_message(&format!("Резервирование 128КБ для файла {}...", big.name));
+        if let Err(e) = fs.reserve(&big.name, 128 * 1024 / BLOCK_SIZE) {
+            status_message(&e.to_string());
+        }
+        fs.reserve(&big.name, 16)?;
+        println!("{fs}");
+        if let Err(e) = fs.rmdir("/tmp") {
+            status_message(&e.to_string());
+        }
+        status_message(&format!("Удаление файла {} и каталога /tmp...", big.name));
+        fs.delete(&big.name)?;
+        fs.rmdir("/tmp")?;
+        println!("{fs}");
+
+        status_message(&format!(
+            "Отображение файла {} в память процесса с подкачкой по требованию...",
+            file.name
+        ));
+        let ram = Rc::new(Ram::new(config));
+        let mut pager = Pager::with_frames(&ram, 2, Box::<Lru>::default());
+        pager.map_file(1, Rc::new(RefCell::new(fs.image(&file.name)?)))?;
+        for vpn in 0..4 {
+            let kind = if vpn % 2 == 0 {
+                AccessKind::Write
+            } else {
+                AccessKind::Read
+            };
+            pager.access_as(1, vpn * config.page_size, kind)?;
+        }
+        pager.unload_process(1)?;
+        status_message(&format!("Подкачка: {}", pager.stats));
     }
 
     Ok(())
 }
 
-fn status_message(msg: &(impl Display + Clone)) {
-    println!("\t\t{}", msg.clone().italic());
+#[cfg(feature = "fs")]
+fn status_message(msg: &impl Display) {
+    println!("\t\t{}", msg.to_string().italic());
 }
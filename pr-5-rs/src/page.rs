@@ -1,74 +1,99 @@
-use crate::PROCESS_SIZE;
-use crate::{process::Process, ram::Ram};
+use crate::{bitmap::Bitmap, hexview::HexView, process::Process, ram::Ram};
 use color_eyre::Result;
-use colored::Colorize;
-use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+use std::{
+    cell::{Ref, RefMut},
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    rc::Rc,
+};
 
-pub const PAGE_DIM: usize = 16;
-pub const PAGE_SIZE: usize = PAGE_DIM.pow(2);
+/// Default page size, see [`MemoryConfig`](crate::config::MemoryConfig).
+pub const PAGE_SIZE: usize = 16usize.pow(2);
+/// Default number of page frames in [`Ram`].
 pub const MAX_PAGE_COUNT: usize = 2;
 
-/// RAM page model. Holds references to bytes owned by [`Ram`].
+/// RAM page model. A window onto the bytes owned by [`Ram`].
 #[allow(unused)]
 #[derive(Debug)]
 pub struct Page {
-    pub(crate) bytes: Rc<[RefCell<u8>; PAGE_SIZE]>,
+    pub(crate) ram: Rc<Ram>,
+    pub(crate) range: Range<usize>,
     pub(crate) loaded_processes: usize,
-    pub(crate) map: HashMap<u16, usize>,
+    /// Taken slots of the page, each [`MemoryConfig::process_size`](crate::config::MemoryConfig) bytes long.
+    pub(crate) slots: Bitmap,
+    /// Bytes of the page, relative to its start, occupied by each loaded process.
+    pub(crate) map: HashMap<u16, Range<usize>>,
     pub(crate) id: usize,
 }
 
 impl Page {
-    /// Creates a new RAM page.
+    /// Creates a view of the `index`-th page of `ram`.
     #[must_use]
     pub fn new(index: usize, ram: &Rc<Ram>) -> Self {
-        let bytes = ram.bytes.borrow()[index..index + PAGE_SIZE]
-            .iter()
-            .copied()
-            .map(RefCell::new)
-            .collect::<Vec<_>>();
-        let bytes = Rc::new(bytes.try_into().unwrap());
+        let page_size = ram.config.page_size;
+        let start = index * page_size;
         Self {
-            bytes,
+            ram: Rc::clone(ram),
+            range: start..start + page_size,
             loaded_processes: 0,
+            slots: Bitmap::new(ram.config.slot_count()),
             map: HashMap::new(),
             id: usize::default(),
         }
     }
 
-    /// Load a [`Process`] into memory.
+    /// Borrow the bytes of the page.
+    pub fn bytes(&self) -> Result<Ref<'_, [u8]>> {
+        Ok(Ref::map(self.ram.bytes.try_borrow()?, |bytes| {
+            &bytes[self.range.clone()]
+        }))
+    }
+
+    /// Mutably borrow the bytes of the page.
+    #[allow(dead_code)]
+    pub fn bytes_mut(&self) -> Result<RefMut<'_, [u8]>> {
+        Ok(RefMut::map(self.ram.bytes.try_borrow_mut()?, |bytes| {
+            &mut bytes[self.range.clone()]
+        }))
+    }
+
+    /// Load a [`Process`] into the first run of free slots big enough to hold it.
     #[allow(dead_code)]
     pub fn load_process(&mut self, process: &Process) -> Result<()> {
         eyre::ensure!(
-            self.loaded_processes < crate::page::PAGE_SIZE / PROCESS_SIZE,
-            "Not enough space in page to write another process"
+            !self.map.contains_key(&process.pid),
+            "Process with pid {} is already loaded into this page",
+            process.pid
         );
-        let start: usize = self.loaded_processes * PROCESS_SIZE;
-        for i in 0..PROCESS_SIZE {
-            let offset = i + start;
-            *self.bytes[offset].try_borrow_mut()? = process.instructions[i];
-        }
-        self.map.insert(process.pid, start);
+        let size = process.instructions.len();
+        let process_size = self.ram.config.process_size;
+        let slot_count = size.div_ceil(process_size).max(1);
+        let first_slot = self.slots.find_clear_run(slot_count).ok_or(eyre::eyre!(
+            "Not enough space in page to write another process: need {slot_count} free slot(s) in a row"
+        ))?;
+        let start = first_slot * process_size;
+        self.ram
+            .write(self.range.start + start, &process.instructions)?;
+        (first_slot..first_slot + slot_count).for_each(|slot| self.slots.set(slot, true));
+        self.map.insert(process.pid, start..start + size);
         self.loaded_processes += 1;
         Ok(())
     }
 
-    /// Unload a [`Process`] from memory.
+    /// Unload a [`Process`] from memory, freeing its slots.
     #[allow(dead_code)]
     pub fn unload_process(&mut self, pid: u16) -> Result<Process> {
-        let offset = self.map.remove(&pid).ok_or(eyre::eyre!(
+        let range = self.map.remove(&pid).ok_or(eyre::eyre!(
             "Cannot unload process: process with pid {pid} isn't loaded"
         ))?;
-        eyre::ensure!(
-            offset + PROCESS_SIZE <= (self.loaded_processes + 1) * PROCESS_SIZE,
-            "Cannot unload process from memory[{offset}-{}] not mapped to any process, only bytes [0-{}] are mapped",
-            offset + PROCESS_SIZE, (self.loaded_processes + 1) * PROCESS_SIZE
-        );
-        let mut process = Process::with_pid(pid);
-        for i in 0..PROCESS_SIZE {
-            process.instructions[i] = *self.bytes[offset].try_borrow()?;
-            *self.bytes[offset].try_borrow_mut()? = 0; // Zero out the memory
-        }
+        let start = self.range.start + range.start;
+        let process = Process::from_image(pid, self.ram.read(start, range.len())?);
+        self.ram.zero(start, range.len())?; // Zero out the memory
+        let process_size = self.ram.config.process_size;
+        let first_slot = range.start / process_size;
+        let slot_count = range.len().div_ceil(process_size).max(1);
+        (first_slot..first_slot + slot_count).for_each(|slot| self.slots.set(slot, false));
         self.loaded_processes -= 1;
         Ok(process)
     }
@@ -76,35 +101,26 @@ impl Page {
 
 impl fmt::Display for Page {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "\n\t┌── RAM Страница №{} ──────────────────────────────┐",
-            self.id
-        )?;
-        for (i, byte) in self.bytes.iter().enumerate() {
-            if i % PAGE_DIM == 0 {
-                write!(f, "\n\t│ ")?;
-            }
-            let mut tmp = format!("{:02x}", *byte.borrow()).bold().bright_black();
-            if *byte.borrow() != 0 {
-                tmp = tmp.blue();
-            }
-            write!(f, "{tmp} ")?;
-            if i % PAGE_DIM == PAGE_DIM - 1 {
-                write!(f, "│")?;
-            }
-        }
-        writeln!(f, "\n\t└─────────────────────────────────────────────────┘")?;
-        Ok(())
+        let bytes = self.bytes().map_err(|_| fmt::Error)?;
+        let base = self.range.start;
+        let view = HexView::new(format!("RAM Страница №{}", self.id), &bytes)
+            .base(base)
+            .width(self.ram.config.page_dim());
+        let view = self.map.iter().fold(view, |view, (&pid, range)| {
+            view.owner(base + range.start..base + range.end, pid)
+        });
+        write!(f, "\n{view}")
     }
 }
 
-impl Page {}
-
 #[cfg(test)]
 mod tests {
     use crate::Process;
-    use crate::{page::Page, ram::Ram};
+    use crate::{
+        page::{Page, PAGE_SIZE},
+        process::PROCESS_SIZE,
+        ram::Ram,
+    };
     use color_eyre::Result;
     use std::rc::Rc;
 
@@ -125,6 +141,71 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn writes_reach_ram() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut page = Page::new(1, &ram);
+        let process = Process::new();
+        page.load_process(&process)?;
+        assert_eq!(
+            &ram.bytes.borrow()[PAGE_SIZE..PAGE_SIZE + PROCESS_SIZE],
+            &process.instructions
+        );
+        assert!(ram.bytes.borrow()[..PAGE_SIZE].iter().all(|&b| b == 0));
+
+        let other_view = Page::new(1, &ram);
+        other_view.bytes_mut()?[0] = 0xff;
+        assert_eq!(page.bytes()?[0], 0xff);
+        Ok(())
+    }
+
+    #[test]
+    fn unload_from_middle() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut page = Page::new(0, &ram);
+        let processes: Vec<Process> = (1..=3).map(Process::with_pid).collect();
+        for process in &processes {
+            page.load_process(process)?;
+        }
+
+        let middle = page.unload_process(2)?;
+        assert_eq!(middle.instructions, processes[1].instructions);
+        let replacement = Process::with_pid(4);
+        page.load_process(&replacement)?;
+        assert_eq!(page.map[&4], PROCESS_SIZE..2 * PROCESS_SIZE);
+
+        for process in [&processes[0], &processes[2], &replacement] {
+            let range = page.map[&process.pid].clone();
+            assert_eq!(page.bytes()?[range], process.instructions[..]);
+        }
+        assert_eq!(
+            page.unload_process(3)?.instructions,
+            processes[2].instructions
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reload_round_trip() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut page = Page::new(0, &ram);
+        let small = Process::with_pid(1);
+        let large = Process::with_size(2, PROCESS_SIZE * 2 + 1);
+        page.load_process(&small)?;
+        page.load_process(&large)?;
+        assert_eq!(page.slots.count_ones(), 4);
+
+        page.unload_process(1)?;
+        let large = page.unload_process(2)?;
+        assert_eq!(page.slots.count_ones(), 0);
+        assert!(page.bytes()?.iter().all(|&b| b == 0));
+
+        page.load_process(&large)?;
+        assert_eq!(page.map[&2], 0..large.instructions.len());
+        assert_eq!(page.unload_process(2)?.instructions, large.instructions);
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn incorrect_unload() {
@@ -0,0 +1,717 @@
+use crate::config::MemoryConfig;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+/// Size of a page table entry (frame number and flags), in bytes.
+pub const PTE_SIZE: usize = 4;
+/// Size of an inverted page table entry (PID and page number), in bytes.
+pub const INVERTED_ENTRY_SIZE: usize = 8;
+/// Size of a hashed page table entry (PID, page number, frame and a link to the next entry), in bytes.
+pub const HASHED_ENTRY_SIZE: usize = 16;
+/// Size of a hash bucket (a link to the first entry), in bytes.
+pub const BUCKET_SIZE: usize = 8;
+
+/// Error raised when a virtual address can't be translated to a physical one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFault {
+    /// The process doesn't have a page table at all.
+    UnknownProcess { pid: u16 },
+    /// The process has a page table, but the page `vpn` that holds `vaddr` isn't mapped to any frame.
+    Unmapped { pid: u16, vaddr: usize, vpn: usize },
+}
+
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownProcess { pid } => {
+                write!(f, "Page fault: process with pid {pid} has no page table")
+            }
+            Self::Unmapped { pid, vaddr, vpn } => write!(
+                f,
+                "Page fault: pid {pid} accessed {vaddr:#06x} (page {vpn}), which isn't mapped"
+            ),
+        }
+    }
+}
+
+impl Error for PageFault {}
+
+/// Result of looking up a page in a [`PageTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lookup {
+    /// Frame the page is mapped to, if it is.
+    pub frame: Option<usize>,
+    /// Memory accesses it took to find out.
+    pub accesses: usize,
+}
+
+/// Structure that maps virtual pages of processes to physical frames,
+/// where a frame is the index of a [`Page`](crate::page::Page) in [`Ram`](crate::ram::Ram).
+pub trait PageTable: fmt::Debug {
+    /// Name of the structure, used in reports.
+    fn name(&self) -> &'static str;
+
+    /// Map the virtual page `vpn` of process `pid` to `frame`, returning the previously mapped frame, if any.
+    fn map(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<usize>;
+
+    /// Remove the mapping of the virtual page `vpn` of process `pid`, returning the frame it was mapped to.
+    fn unmap(&mut self, pid: u16, vpn: usize) -> Option<usize>;
+
+    /// Remove every mapping of process `pid`, along with any tables only it used.
+    fn remove_process(&mut self, pid: u16);
+
+    /// Find the frame the virtual page `vpn` of process `pid` is mapped to.
+    fn lookup(&self, pid: u16, vpn: usize) -> Lookup;
+
+    /// Every mapping as `(pid, vpn, frame)`, sorted.
+    fn mappings(&self) -> Vec<(u16, usize, usize)>;
+
+    /// Memory taken by the structure itself, in bytes.
+    fn overhead(&self) -> usize;
+}
+
+/// Single-level page table: one array per process, indexed by page number
+/// and long enough to hold its highest mapped page.
+///
+/// Only the mapped entries are actually stored, so a process touching a page far out doesn't
+/// take up memory for every page below it; the [`overhead`](PageTable::overhead) still counts
+/// the whole array.
+#[derive(Debug, Default, Clone)]
+pub struct FlatTable {
+    tables: HashMap<u16, FlatArray>,
+}
+
+/// Array of a [`FlatTable`]: its length and the entries that are set.
+#[derive(Debug, Default, Clone)]
+struct FlatArray {
+    len: usize,
+    frames: BTreeMap<usize, usize>,
+}
+
+impl PageTable for FlatTable {
+    fn name(&self) -> &'static str {
+        "Одноуровневая"
+    }
+
+    fn map(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<usize> {
+        let table = self.tables.entry(pid).or_default();
+        table.len = table.len.max(vpn.saturating_add(1));
+        table.frames.insert(vpn, frame)
+    }
+
+    fn unmap(&mut self, pid: u16, vpn: usize) -> Option<usize> {
+        self.tables.get_mut(&pid)?.frames.remove(&vpn)
+    }
+
+    fn remove_process(&mut self, pid: u16) {
+        self.tables.remove(&pid);
+    }
+
+    fn lookup(&self, pid: u16, vpn: usize) -> Lookup {
+        Lookup {
+            frame: self
+                .tables
+                .get(&pid)
+                .and_then(|table| table.frames.get(&vpn).copied()),
+            accesses: 1,
+        }
+    }
+
+    fn mappings(&self) -> Vec<(u16, usize, usize)> {
+        let mut mappings: Vec<_> = self
+            .tables
+            .iter()
+            .flat_map(|(&pid, table)| {
+                (table.frames.iter()).map(move |(&vpn, &frame)| (pid, vpn, frame))
+            })
+            .collect();
+        mappings.sort_unstable();
+        mappings
+    }
+
+    fn overhead(&self) -> usize {
+        self.tables.values().map(|table| table.len).sum::<usize>() * PTE_SIZE
+    }
+}
+
+/// Table of a [`MultiLevelTable`]. Every one takes up a page.
+#[derive(Debug, Clone)]
+enum Node {
+    Directory(Vec<Option<Node>>),
+    Leaf(Vec<Option<usize>>),
+}
+
+impl Node {
+    fn new(level: usize, entries: usize) -> Self {
+        if level == 0 {
+            Self::Leaf(vec![None; entries])
+        } else {
+            Self::Directory(vec![None; entries])
+        }
+    }
+
+    /// Number of entries in the subtree.
+    fn size(&self) -> usize {
+        match self {
+            Self::Leaf(leaf) => leaf.len(),
+            Self::Directory(children) => {
+                children.len() + children.iter().flatten().map(Self::size).sum::<usize>()
+            }
+        }
+    }
+
+    fn collect(&self, pid: u16, prefix: usize, out: &mut Vec<(u16, usize, usize)>) {
+        match self {
+            Self::Leaf(leaf) => out.extend(
+                (leaf.iter().enumerate())
+                    .filter_map(|(i, frame)| Some((pid, prefix * leaf.len() + i, (*frame)?))),
+            ),
+            Self::Directory(children) => {
+                for (i, child) in children.iter().enumerate() {
+                    if let Some(child) = child {
+                        child.collect(pid, prefix * children.len() + i, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Hierarchical page table: the page number is split into one index per level, and every level
+/// but the last holds directories of next-level tables, which are only allocated when needed.
+#[derive(Debug, Clone)]
+pub struct MultiLevelTable {
+    levels: usize,
+    /// Entries in a table of any level, so that a table takes up exactly one page.
+    entries: usize,
+    roots: HashMap<u16, Node>,
+}
+
+impl MultiLevelTable {
+    /// Create a table of `levels` levels for pages of `page_size` bytes.
+    #[must_use]
+    pub fn new(levels: usize, page_size: usize) -> Self {
+        Self {
+            levels: levels.max(1),
+            entries: (page_size / PTE_SIZE).max(2),
+            roots: HashMap::new(),
+        }
+    }
+
+    /// Index into the table of each level for page `vpn`, root first.
+    /// The root takes all the bits left over, growing past a page if it has to.
+    fn indices(&self, vpn: usize) -> Vec<usize> {
+        (0..self.levels)
+            .rev()
+            .map(|level| {
+                let span = u32::try_from(level)
+                    .ok()
+                    .and_then(|level| self.entries.checked_pow(level));
+                let index = span.map_or(0, |span| vpn / span);
+                if level + 1 == self.levels {
+                    index
+                } else {
+                    index % self.entries
+                }
+            })
+            .collect()
+    }
+
+    /// The slot of page `vpn` of process `pid`, allocating the tables on the way if `create` is set.
+    fn slot(&mut self, pid: u16, vpn: usize, create: bool) -> Option<&mut Option<usize>> {
+        let (levels, entries) = (self.levels, self.entries);
+        let indices = self.indices(vpn);
+        let mut node = if create {
+            self.roots
+                .entry(pid)
+                .or_insert_with(|| Node::new(levels - 1, entries))
+        } else {
+            self.roots.get_mut(&pid)?
+        };
+        let (&last, path) = indices.split_last()?;
+        for (depth, &index) in path.iter().enumerate() {
+            let Node::Directory(children) = node else {
+                return None;
+            };
+            node = if create {
+                if children.len() <= index {
+                    children.resize(index + 1, None);
+                }
+                children[index].get_or_insert_with(|| Node::new(levels - 2 - depth, entries))
+            } else {
+                children.get_mut(index)?.as_mut()?
+            };
+        }
+        let Node::Leaf(leaf) = node else {
+            return None;
+        };
+        if create && leaf.len() <= last {
+            leaf.resize(last + 1, None);
+        }
+        leaf.get_mut(last)
+    }
+}
+
+impl PageTable for MultiLevelTable {
+    fn name(&self) -> &'static str {
+        match self.levels {
+            1 => "Одноуровневая",
+            2 => "Двухуровневая",
+            3 => "Трёхуровневая",
+            _ => "Многоуровневая",
+        }
+    }
+
+    fn map(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<usize> {
+        self.slot(pid, vpn, true)?.replace(frame)
+    }
+
+    fn unmap(&mut self, pid: u16, vpn: usize) -> Option<usize> {
+        self.slot(pid, vpn, false)?.take()
+    }
+
+    fn remove_process(&mut self, pid: u16) {
+        self.roots.remove(&pid);
+    }
+
+    fn lookup(&self, pid: u16, vpn: usize) -> Lookup {
+        let mut accesses = 0;
+        let mut node = self.roots.get(&pid);
+        for index in self.indices(vpn) {
+            let Some(current) = node else { break };
+            accesses += 1;
+            node = match current {
+                Node::Leaf(leaf) => {
+                    return Lookup {
+                        frame: leaf.get(index).copied().flatten(),
+                        accesses,
+                    }
+                }
+                Node::Directory(children) => children.get(index).and_then(Option::as_ref),
+            };
+        }
+        Lookup {
+            frame: None,
+            accesses: accesses.max(1),
+        }
+    }
+
+    fn mappings(&self) -> Vec<(u16, usize, usize)> {
+        let mut mappings = vec![];
+        for (&pid, root) in &self.roots {
+            root.collect(pid, 0, &mut mappings);
+        }
+        mappings.sort_unstable();
+        mappings
+    }
+
+    fn overhead(&self) -> usize {
+        self.roots.values().map(Node::size).sum::<usize>() * PTE_SIZE
+    }
+}
+
+/// Inverted page table: a single table with one entry per frame, holding the page that's in it.
+/// Finding a page means searching the table.
+#[derive(Debug, Clone)]
+pub struct InvertedTable {
+    frames: Vec<Option<(u16, usize)>>,
+}
+
+impl InvertedTable {
+    /// Create a table for `frame_count` frames.
+    #[must_use]
+    pub fn new(frame_count: usize) -> Self {
+        Self {
+            frames: vec![None; frame_count],
+        }
+    }
+}
+
+impl PageTable for InvertedTable {
+    fn name(&self) -> &'static str {
+        "Инвертированная"
+    }
+
+    fn map(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<usize> {
+        let previous = self.unmap(pid, vpn);
+        if self.frames.len() <= frame {
+            self.frames.resize(frame + 1, None);
+        }
+        self.frames[frame] = Some((pid, vpn));
+        previous
+    }
+
+    fn unmap(&mut self, pid: u16, vpn: usize) -> Option<usize> {
+        let frame = self
+            .frames
+            .iter()
+            .position(|&entry| entry == Some((pid, vpn)))?;
+        self.frames[frame] = None;
+        Some(frame)
+    }
+
+    fn remove_process(&mut self, pid: u16) {
+        for entry in &mut self.frames {
+            if entry.is_some_and(|(owner, _)| owner == pid) {
+                *entry = None;
+            }
+        }
+    }
+
+    fn lookup(&self, pid: u16, vpn: usize) -> Lookup {
+        let frame = self
+            .frames
+            .iter()
+            .position(|&entry| entry == Some((pid, vpn)));
+        Lookup {
+            frame,
+            accesses: frame.map_or(self.frames.len(), |frame| frame + 1).max(1),
+        }
+    }
+
+    fn mappings(&self) -> Vec<(u16, usize, usize)> {
+        let mut mappings: Vec<_> = (self.frames.iter().enumerate())
+            .filter_map(|(frame, entry)| entry.map(|(pid, vpn)| (pid, vpn, frame)))
+            .collect();
+        mappings.sort_unstable();
+        mappings
+    }
+
+    fn overhead(&self) -> usize {
+        self.frames.len() * INVERTED_ENTRY_SIZE
+    }
+}
+
+/// Hashed page table: a hash of the PID and page number picks a bucket,
+/// which chains every entry that hashes to it.
+#[derive(Debug, Clone)]
+pub struct HashedTable {
+    buckets: Vec<Vec<(u16, usize, usize)>>,
+}
+
+impl HashedTable {
+    /// Create a table with a bucket per frame, rounded up to a power of two.
+    #[must_use]
+    pub fn new(frame_count: usize) -> Self {
+        Self {
+            buckets: vec![vec![]; frame_count.max(1).next_power_of_two()],
+        }
+    }
+
+    fn bucket(&self, pid: u16, vpn: usize) -> usize {
+        let key = (vpn as u64) ^ (u64::from(pid) << 32);
+        let hash = key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        hash as usize % self.buckets.len()
+    }
+
+    fn chain_mut(&mut self, pid: u16, vpn: usize) -> &mut Vec<(u16, usize, usize)> {
+        let bucket = self.bucket(pid, vpn);
+        &mut self.buckets[bucket]
+    }
+}
+
+impl PageTable for HashedTable {
+    fn name(&self) -> &'static str {
+        "Хешированная"
+    }
+
+    fn map(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<usize> {
+        let chain = self.chain_mut(pid, vpn);
+        match chain.iter_mut().find(|(p, v, _)| (*p, *v) == (pid, vpn)) {
+            Some((_, _, mapped)) => Some(std::mem::replace(mapped, frame)),
+            None => {
+                chain.push((pid, vpn, frame));
+                None
+            }
+        }
+    }
+
+    fn unmap(&mut self, pid: u16, vpn: usize) -> Option<usize> {
+        let chain = self.chain_mut(pid, vpn);
+        let index = chain.iter().position(|&(p, v, _)| (p, v) == (pid, vpn))?;
+        Some(chain.remove(index).2)
+    }
+
+    fn remove_process(&mut self, pid: u16) {
+        for chain in &mut self.buckets {
+            chain.retain(|&(owner, _, _)| owner != pid);
+        }
+    }
+
+    fn lookup(&self, pid: u16, vpn: usize) -> Lookup {
+        let chain = &self.buckets[self.bucket(pid, vpn)];
+        let position = chain.iter().position(|&(p, v, _)| (p, v) == (pid, vpn));
+        Lookup {
+            frame: position.map(|i| chain[i].2),
+            // The bucket itself, then every entry of the chain up to the one we want.
+            accesses: 1 + position.map_or(chain.len(), |i| i + 1),
+        }
+    }
+
+    fn mappings(&self) -> Vec<(u16, usize, usize)> {
+        let mut mappings: Vec<_> = self.buckets.iter().flatten().copied().collect();
+        mappings.sort_unstable();
+        mappings
+    }
+
+    fn overhead(&self) -> usize {
+        let entries: usize = self.buckets.iter().map(Vec::len).sum();
+        self.buckets.len() * BUCKET_SIZE + entries * HASHED_ENTRY_SIZE
+    }
+}
+
+/// Names of the available [`PageTable`] implementations, for selecting one at runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageTableKind {
+    #[default]
+    Flat,
+    /// Hierarchical table with this many levels.
+    MultiLevel(usize),
+    Inverted,
+    Hashed,
+}
+
+impl PageTableKind {
+    pub const ALL: [Self; 5] = [
+        Self::Flat,
+        Self::MultiLevel(2),
+        Self::MultiLevel(3),
+        Self::Inverted,
+        Self::Hashed,
+    ];
+
+    /// Build the table for memory of the `config` geometry.
+    #[must_use]
+    pub fn build(self, config: MemoryConfig) -> Box<dyn PageTable> {
+        match self {
+            Self::Flat => Box::<FlatTable>::default(),
+            Self::MultiLevel(levels) => Box::new(MultiLevelTable::new(levels, config.page_size)),
+            Self::Inverted => Box::new(InvertedTable::new(config.frame_count())),
+            Self::Hashed => Box::new(HashedTable::new(config.frame_count())),
+        }
+    }
+}
+
+impl FromStr for PageTableKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "flat" => Ok(Self::Flat),
+            "2-level" => Ok(Self::MultiLevel(2)),
+            "3-level" => Ok(Self::MultiLevel(3)),
+            "inverted" => Ok(Self::Inverted),
+            "hashed" => Ok(Self::Hashed),
+            _ => Err(eyre::eyre!(
+                "Unknown page table '{s}', expected one of: flat, 2-level, 3-level, inverted, hashed"
+            )),
+        }
+    }
+}
+
+/// Counters collected by the [`Mmu`] while translating addresses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TranslationStats {
+    /// Page table lookups.
+    pub lookups: usize,
+    /// Memory accesses those lookups took.
+    pub accesses: usize,
+    /// Most memory the page table ever took, in bytes.
+    pub peak_overhead: usize,
+}
+
+impl fmt::Display for TranslationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "поисков в таблице: {}, обращений к памяти: {}, наибольший размер таблицы: {} байт",
+            self.lookups, self.accesses, self.peak_overhead
+        )
+    }
+}
+
+/// Memory management unit. Translates addresses through a [`PageTable`].
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct Mmu {
+    pub(crate) config: MemoryConfig,
+    pub(crate) table: Box<dyn PageTable>,
+    /// Processes that have been given a page table.
+    processes: HashSet<u16>,
+    pub(crate) stats: TranslationStats,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new(MemoryConfig::default())
+    }
+}
+
+#[allow(dead_code)]
+impl Mmu {
+    /// Create an MMU with a [`FlatTable`] that translates addresses into pages of the `config` geometry.
+    #[must_use]
+    pub fn new(config: MemoryConfig) -> Self {
+        Self::with_table(config, PageTableKind::Flat)
+    }
+
+    /// Create an MMU that keeps its mappings in a `kind` of [`PageTable`].
+    #[must_use]
+    pub fn with_table(config: MemoryConfig, kind: PageTableKind) -> Self {
+        Self {
+            config,
+            table: kind.build(config),
+            processes: HashSet::new(),
+            stats: TranslationStats::default(),
+        }
+    }
+
+    /// Map the virtual page `vpn` of process `pid` to `frame`, creating the page table if needed.
+    pub fn map(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<usize> {
+        self.processes.insert(pid);
+        let previous = self.table.map(pid, vpn, frame);
+        self.stats.peak_overhead = self.stats.peak_overhead.max(self.table.overhead());
+        previous
+    }
+
+    /// Remove the mapping of the virtual page `vpn` of process `pid`.
+    pub fn unmap(&mut self, pid: u16, vpn: usize) -> Option<usize> {
+        self.table.unmap(pid, vpn)
+    }
+
+    /// Drop the whole page table of process `pid`.
+    pub fn remove_process(&mut self, pid: u16) {
+        self.processes.remove(&pid);
+        self.table.remove_process(pid);
+    }
+
+    /// Translate the virtual address `vaddr` of process `pid` into a physical address in [`Ram`](crate::ram::Ram).
+    pub fn translate(&mut self, pid: u16, vaddr: usize) -> Result<usize, PageFault> {
+        if !self.processes.contains(&pid) {
+            return Err(PageFault::UnknownProcess { pid });
+        }
+        let (vpn, offset) = self.config.split_vaddr(vaddr);
+        let lookup = self.table.lookup(pid, vpn);
+        self.stats.lookups += 1;
+        self.stats.accesses += lookup.accesses;
+        let frame = lookup
+            .frame
+            .ok_or(PageFault::Unmapped { pid, vaddr, vpn })?;
+        Ok(frame * self.config.page_size + offset)
+    }
+}
+
+impl fmt::Display for Mmu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mappings = self.table.mappings();
+        let mut pids: Vec<_> = self.processes.iter().copied().collect();
+        pids.sort_unstable();
+        for pid in pids {
+            writeln!(f, "\t┌── Таблица страниц PID {pid:5} ──┐")?;
+            for (_, vpn, frame) in mappings.iter().filter(|(owner, _, _)| *owner == pid) {
+                writeln!(f, "\t│ {vpn:>6} → {frame:<6}{:15}│", "")?;
+            }
+            writeln!(f, "\t└{:─<31}┘", "")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mmu, PageFault, PageTableKind};
+    use crate::{config::MemoryConfig, page::PAGE_SIZE};
+
+    #[test]
+    fn translate_mapped() {
+        let mut mmu = Mmu::default();
+        mmu.map(1, 0, 1);
+        mmu.map(1, 1, 0);
+        assert_eq!(mmu.translate(1, 0x10), Ok(PAGE_SIZE + 0x10));
+        assert_eq!(mmu.translate(1, PAGE_SIZE + 3), Ok(3));
+    }
+
+    #[test]
+    fn translate_faults() {
+        let mut mmu = Mmu::default();
+        assert_eq!(
+            mmu.translate(7, 0),
+            Err(PageFault::UnknownProcess { pid: 7 })
+        );
+        mmu.map(7, 0, 0);
+        let vaddr = 2 * PAGE_SIZE;
+        assert_eq!(
+            mmu.translate(7, vaddr),
+            Err(PageFault::Unmapped {
+                pid: 7,
+                vaddr,
+                vpn: 2
+            })
+        );
+        mmu.unmap(7, 0);
+        assert_eq!(
+            mmu.translate(7, 0),
+            Err(PageFault::Unmapped {
+                pid: 7,
+                vaddr: 0,
+                vpn: 0
+            })
+        );
+    }
+
+    #[test]
+    fn every_kind_agrees() {
+        // 64 entries per table with the default 256-byte pages.
+        let config = MemoryConfig::default();
+        let pages = [(1, 0, 0), (1, 70, 1), (2, 5000, 2), (2, 1, 3)];
+        for kind in PageTableKind::ALL {
+            let mut table = kind.build(config);
+            for (pid, vpn, frame) in pages {
+                assert_eq!(table.map(pid, vpn, frame), None, "{kind:?}");
+            }
+            assert_eq!(table.map(1, 70, 1), Some(1), "{kind:?}");
+            let mut expected = pages.to_vec();
+            expected.sort_unstable();
+            assert_eq!(table.mappings(), expected, "{kind:?}");
+            assert_eq!(table.lookup(2, 5000).frame, Some(2), "{kind:?}");
+            assert_eq!(table.unmap(1, 70), Some(1), "{kind:?}");
+            assert_eq!(table.lookup(1, 70).frame, None, "{kind:?}");
+            table.remove_process(2);
+            assert_eq!(table.mappings(), [(1, 0, 0)], "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn lookup_costs_and_overhead() {
+        let config = MemoryConfig::default();
+        let costs: Vec<_> = PageTableKind::ALL
+            .into_iter()
+            .map(|kind| {
+                let mut table = kind.build(config);
+                table.map(1, 0, 0);
+                table.map(1, 100, 1);
+                (table.lookup(1, 100).accesses, table.overhead())
+            })
+            .collect();
+        assert_eq!(
+            costs[..4],
+            [
+                // 101 entries.
+                (1, 101 * 4),
+                // A directory and two leaves.
+                (2, 3 * 256),
+                // A root, a directory and two leaves.
+                (3, 4 * 256),
+                // Page 100 is in the second of two frames.
+                (2, 2 * 8),
+            ]
+        );
+        // Two buckets and two entries, and at least the bucket and the entry to read.
+        assert_eq!(costs[4].1, 2 * 8 + 2 * 16);
+        assert!(costs[4].0 >= 2);
+    }
+}
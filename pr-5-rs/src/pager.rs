@@ -0,0 +1,536 @@
+use crate::{
+    config::MemoryConfig,
+    page_table::{Mmu, PageFault, PageTableKind},
+    process::Process,
+    ram::{AccessKind, Ram},
+    replacement::{PageKey, ReplacementPolicy},
+    tlb::Tlb,
+};
+use color_eyre::Result;
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+/// Simulated swap area. Holds the contents of pages that aren't resident in [`Ram`].
+#[derive(Debug, Default)]
+pub struct Swap {
+    pub(crate) pages: HashMap<PageKey, Box<[u8]>>,
+}
+
+/// Storage a process image is paged in from on demand, e.g. a [`FileImage`](crate::file::FileImage).
+pub trait BackingStore: fmt::Debug {
+    /// Size of the image in bytes.
+    fn len(&self) -> usize;
+
+    /// Fill `buf` with the bytes starting at `offset`. Bytes past the end of the image read as zeros.
+    fn read_at(&self, offset: usize, buf: &mut [u8]);
+
+    /// Write `data` starting at `offset`. Bytes past the end of the image are dropped.
+    fn write_at(&mut self, offset: usize, data: &[u8]);
+}
+
+/// Counters collected by the [`Pager`] while serving references.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PagingStats {
+    /// The referenced page was resident.
+    pub hits: usize,
+    /// The referenced page wasn't resident and had to be paged in.
+    pub misses: usize,
+    /// Misses that found every frame taken, so a victim page had to be evicted to swap.
+    pub evictions: usize,
+    /// Dirty pages written back to their [`BackingStore`].
+    pub writebacks: usize,
+}
+
+impl fmt::Display for PagingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "попаданий: {}, промахов: {}, замещений: {}, записей в файл: {}",
+            self.hits, self.misses, self.evictions, self.writebacks
+        )
+    }
+}
+
+/// Result of a single reference served by [`Pager::access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessOutcome {
+    /// Physical address the reference resolved to.
+    pub paddr: usize,
+    /// Frame that holds the referenced page.
+    pub frame: usize,
+    /// Whether the page had to be brought in from swap.
+    pub fault: bool,
+    /// Page that was evicted to make room, if any.
+    pub evicted: Option<PageKey>,
+    /// Whether the translation was found in the [`Tlb`].
+    pub tlb_hit: bool,
+}
+
+/// Virtual memory manager. Keeps process pages in the frames of [`Ram`], moving them
+/// to and from [`Swap`] according to a [`ReplacementPolicy`] when memory runs out.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct Pager {
+    ram: Rc<Ram>,
+    pub(crate) mmu: Mmu,
+    pub(crate) frames: Vec<Option<PageKey>>,
+    /// Whether the page in each frame was written to since it was paged in.
+    pub(crate) dirty: Vec<bool>,
+    pub(crate) swap: Swap,
+    /// Images of processes mapped with [`Pager::map_file`].
+    backing: HashMap<u16, Rc<RefCell<dyn BackingStore>>>,
+    policy: Box<dyn ReplacementPolicy>,
+    pub(crate) stats: PagingStats,
+    /// Translation cache consulted before the [`Mmu`], if there is one.
+    pub(crate) tlb: Option<Tlb>,
+}
+
+#[allow(dead_code)]
+impl Pager {
+    /// Create a pager that uses every frame of `ram`.
+    #[must_use]
+    pub fn new(ram: &Rc<Ram>, policy: Box<dyn ReplacementPolicy>) -> Self {
+        Self::with_frames(ram, ram.config.frame_count(), policy)
+    }
+
+    /// Create a pager that only uses the first `frame_count` frames of `ram`.
+    #[must_use]
+    pub fn with_frames(
+        ram: &Rc<Ram>,
+        frame_count: usize,
+        policy: Box<dyn ReplacementPolicy>,
+    ) -> Self {
+        Self {
+            ram: Rc::clone(ram),
+            mmu: Mmu::new(ram.config),
+            frames: vec![None; frame_count.min(ram.config.frame_count())],
+            dirty: vec![false; frame_count.min(ram.config.frame_count())],
+            swap: Swap::default(),
+            backing: HashMap::new(),
+            policy,
+            stats: PagingStats::default(),
+            tlb: None,
+        }
+    }
+
+    /// Keep the mappings in a `kind` of [`PageTable`](crate::page_table::PageTable).
+    /// Must be called before any process is registered.
+    #[must_use]
+    pub fn with_page_table(mut self, kind: PageTableKind) -> Self {
+        self.mmu = Mmu::with_table(self.ram.config, kind);
+        self
+    }
+
+    /// Cache translations in `tlb`.
+    #[must_use]
+    pub fn with_tlb(mut self, tlb: Tlb) -> Self {
+        self.tlb = Some(tlb);
+        self
+    }
+
+    /// Name of the replacement policy in use.
+    #[must_use]
+    pub fn policy_name(&self) -> &'static str {
+        self.policy.name()
+    }
+
+    /// Place the image of a [`Process`] into swap without loading any of it into [`Ram`].
+    pub fn register_process(&mut self, process: &Process) -> Result<()> {
+        eyre::ensure!(
+            !self.owns_pages(process.pid),
+            "Process with pid {} is already registered",
+            process.pid
+        );
+        for (vpn, chunk) in process.instructions.chunks(self.page_size()).enumerate() {
+            let mut page = vec![0; self.page_size()].into_boxed_slice();
+            page[..chunk.len()].copy_from_slice(chunk);
+            self.swap.pages.insert((process.pid, vpn), page);
+        }
+        Ok(())
+    }
+
+    /// Give process `pid` a zeroed page in swap for every page number in `vpns`,
+    /// without loading any of them into [`Ram`].
+    pub fn allocate(&mut self, pid: u16, vpns: impl IntoIterator<Item = usize>) -> Result<()> {
+        eyre::ensure!(
+            !self.owns_pages(pid),
+            "Process with pid {pid} is already registered"
+        );
+        for vpn in vpns {
+            let page = vec![0; self.page_size()].into_boxed_slice();
+            self.swap.pages.insert((pid, vpn), page);
+        }
+        Ok(())
+    }
+
+    /// Map the image of process `pid` from `store` without loading any of it into [`Ram`].
+    /// Pages are read from the store on first access, and dirty ones are written back to it
+    /// when evicted or when the process is unloaded.
+    pub fn map_file(&mut self, pid: u16, store: Rc<RefCell<dyn BackingStore>>) -> Result<()> {
+        eyre::ensure!(
+            !self.owns_pages(pid),
+            "Process with pid {pid} is already registered"
+        );
+        self.backing.insert(pid, store);
+        Ok(())
+    }
+
+    /// Load a [`Process`] into [`Ram`], evicting other pages if there's no free frame.
+    pub fn load_process(&mut self, process: &Process) -> Result<()> {
+        self.register_process(process)?;
+        for vpn in 0..process.instructions.len().div_ceil(self.page_size()) {
+            self.page_in((process.pid, vpn))?;
+        }
+        Ok(())
+    }
+
+    /// Remove every page of process `pid` from both [`Ram`] and [`Swap`].
+    pub fn unload_process(&mut self, pid: u16) -> Result<()> {
+        eyre::ensure!(
+            self.owns_pages(pid),
+            "Cannot unload process: process with pid {pid} isn't loaded"
+        );
+        for frame in 0..self.frames.len() {
+            if let Some(key @ (owner, _)) = self.frames[frame] {
+                if owner == pid {
+                    self.write_back(frame)?;
+                    self.policy.remove(key);
+                    self.frames[frame] = None;
+                    self.ram.zero(frame * self.page_size(), self.page_size())?;
+                }
+            }
+        }
+        self.swap.pages.retain(|&(owner, _), _| owner != pid);
+        self.backing.remove(&pid);
+        self.mmu.remove_process(pid);
+        if let Some(tlb) = &mut self.tlb {
+            tlb.invalidate_process(pid);
+        }
+        Ok(())
+    }
+
+    /// Move every resident page of process `pid` out of [`Ram`], returning how many there were.
+    pub fn swap_out(&mut self, pid: u16) -> Result<usize> {
+        let resident: Vec<PageKey> = self
+            .frames
+            .iter()
+            .flatten()
+            .filter(|&&(owner, _)| owner == pid)
+            .copied()
+            .collect();
+        for &key in &resident {
+            self.evict(key)?;
+        }
+        Ok(resident.len())
+    }
+
+    /// Serve a read of the virtual address `vaddr` of process `pid`,
+    /// paging the page in from [`Swap`] or its [`BackingStore`] if it isn't resident.
+    pub fn access(&mut self, pid: u16, vaddr: usize) -> Result<AccessOutcome> {
+        self.access_as(pid, vaddr, AccessKind::Read)
+    }
+
+    /// Serve a `kind` of reference to the virtual address `vaddr` of process `pid` like
+    /// [`access`](Self::access) does. Writes mark the page dirty.
+    pub fn access_as(&mut self, pid: u16, vaddr: usize, kind: AccessKind) -> Result<AccessOutcome> {
+        let outcome = self.serve(pid, vaddr)?;
+        if kind == AccessKind::Write {
+            self.dirty[outcome.frame] = true;
+        }
+        Ok(outcome)
+    }
+
+    fn serve(&mut self, pid: u16, vaddr: usize) -> Result<AccessOutcome> {
+        let (vpn, offset) = self.ram.config.split_vaddr(vaddr);
+        let key = (pid, vpn);
+        let cached = self.tlb.as_mut().and_then(|tlb| {
+            tlb.switch_to(pid);
+            tlb.lookup(pid, vpn)
+        });
+        if let Some(frame) = cached {
+            self.stats.hits += 1;
+            self.policy.access(key);
+            return Ok(AccessOutcome {
+                paddr: frame * self.page_size() + offset,
+                frame,
+                fault: false,
+                evicted: None,
+                tlb_hit: true,
+            });
+        }
+        let mut translated = self.mmu.translate(pid, vaddr);
+        let (fault, evicted) = if translated.is_ok() {
+            self.stats.hits += 1;
+            (false, None)
+        } else {
+            if !self.swap.pages.contains_key(&key) && !self.is_backed(key) {
+                return Err(PageFault::Unmapped { pid, vaddr, vpn }.into());
+            }
+            self.stats.misses += 1;
+            let evicted = self.page_in(key)?;
+            if evicted.is_some() {
+                self.stats.evictions += 1;
+            }
+            // The faulting access is restarted and walks the table again.
+            translated = self.mmu.translate(pid, vaddr);
+            (true, evicted)
+        };
+        self.policy.access(key);
+        let paddr = translated?;
+        let frame = paddr / self.page_size();
+        if let Some(tlb) = &mut self.tlb {
+            tlb.insert(pid, vpn, frame);
+        }
+        Ok(AccessOutcome {
+            paddr,
+            frame,
+            fault,
+            evicted,
+            tlb_hit: false,
+        })
+    }
+
+    /// Bring a page from [`Swap`] or its [`BackingStore`] into a frame,
+    /// returning the page evicted to make room.
+    fn page_in(&mut self, key: PageKey) -> Result<Option<PageKey>> {
+        let page = match self.swap.pages.remove(&key) {
+            Some(page) => page,
+            None if self.is_backed(key) => {
+                let mut page = vec![0; self.page_size()].into_boxed_slice();
+                self.backing[&key.0]
+                    .borrow()
+                    .read_at(key.1 * self.page_size(), &mut page);
+                page
+            }
+            None => eyre::bail!("Page {key:?} isn't in swap"),
+        };
+        let (frame, evicted) = match self.frames.iter().position(Option::is_none) {
+            Some(frame) => (frame, None),
+            None => {
+                let victim = self
+                    .policy
+                    .victim()
+                    .ok_or(eyre::eyre!("No frames to place page {key:?} into"))?;
+                (self.evict(victim)?, Some(victim))
+            }
+        };
+        self.ram.write(frame * self.page_size(), &page)?;
+        self.frames[frame] = Some(key);
+        self.dirty[frame] = false;
+        self.mmu.map(key.0, key.1, frame);
+        self.policy.insert(key);
+        Ok(evicted)
+    }
+
+    /// Move a resident page to [`Swap`], returning the frame it occupied. Pages of mapped files
+    /// don't go to swap: they are written back to the file if dirty and dropped otherwise.
+    fn evict(&mut self, key: PageKey) -> Result<usize> {
+        let frame = self
+            .frames
+            .iter()
+            .position(|&k| k == Some(key))
+            .ok_or(eyre::eyre!("Cannot evict page {key:?}: it isn't resident"))?;
+        let start = frame * self.page_size();
+        if self.backing.contains_key(&key.0) {
+            self.write_back(frame)?;
+        } else {
+            let page = self.ram.read(start, self.page_size())?;
+            self.swap.pages.insert(key, page.into_boxed_slice());
+        }
+        self.ram.zero(start, self.page_size())?;
+        self.frames[frame] = None;
+        self.mmu.unmap(key.0, key.1);
+        self.policy.remove(key);
+        if let Some(tlb) = &mut self.tlb {
+            tlb.invalidate(key.0, key.1);
+        }
+        Ok(frame)
+    }
+
+    /// Write the page in `frame` back to its [`BackingStore`] if it has one and is dirty.
+    fn write_back(&mut self, frame: usize) -> Result<()> {
+        let Some((pid, vpn)) = self.frames[frame] else {
+            return Ok(());
+        };
+        if let Some(store) = self.backing.get(&pid).filter(|_| self.dirty[frame]) {
+            let page = self.ram.read(frame * self.page_size(), self.page_size())?;
+            store.borrow_mut().write_at(vpn * self.page_size(), &page);
+            self.dirty[frame] = false;
+            self.stats.writebacks += 1;
+        }
+        Ok(())
+    }
+
+    fn page_size(&self) -> usize {
+        self.ram.config.page_size
+    }
+
+    /// Whether `key` is a page of a mapped file.
+    fn is_backed(&self, (pid, vpn): PageKey) -> bool {
+        self.backing
+            .get(&pid)
+            .is_some_and(|store| vpn < store.borrow().len().div_ceil(self.page_size()))
+    }
+
+    fn owns_pages(&self, pid: u16) -> bool {
+        self.frames.iter().flatten().any(|&(owner, _)| owner == pid)
+            || self.swap.pages.keys().any(|&(owner, _)| owner == pid)
+            || self.backing.contains_key(&pid)
+    }
+}
+
+/// Replay the same reference string against every policy, each in a fresh [`Ram`] of the `config` geometry
+/// with `frame_count` frames.
+///
+/// Every process referenced is registered in swap beforehand, so the first reference to each page is a miss.
+#[allow(dead_code)]
+pub fn compare_policies(
+    config: MemoryConfig,
+    frame_count: usize,
+    processes: &[Process],
+    refs: &[(u16, usize)],
+    policies: Vec<Box<dyn ReplacementPolicy>>,
+) -> Result<Vec<(&'static str, PagingStats)>> {
+    policies
+        .into_iter()
+        .map(|policy| {
+            let ram = Rc::new(Ram::new(config));
+            let mut pager = Pager::with_frames(&ram, frame_count, policy);
+            for process in processes {
+                pager.register_process(process)?;
+            }
+            for &(pid, vaddr) in refs {
+                pager.access(pid, vaddr)?;
+            }
+            Ok((pager.policy_name(), pager.stats))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_policies, BackingStore, Pager};
+    use crate::{
+        config::MemoryConfig,
+        file::Filesystem,
+        handle::OpenFlags,
+        page::PAGE_SIZE,
+        process::Process,
+        ram::{AccessKind, Ram},
+        replacement::{Clock, Fifo, Lru, Optimal},
+    };
+    use color_eyre::Result;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn eviction_round_trips_through_swap() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut pager = Pager::with_frames(&ram, 2, Box::<Fifo>::default());
+        let processes = [
+            Process::with_pid(1),
+            Process::with_pid(2),
+            Process::with_pid(3),
+        ];
+        for process in &processes {
+            pager.load_process(process)?;
+        }
+        assert!(pager.swap.pages.contains_key(&(1, 0)));
+
+        let outcome = pager.access(1, 4)?;
+        assert!(outcome.fault);
+        assert_eq!(outcome.evicted, Some((2, 0)));
+        let base = outcome.paddr - 4;
+        let bytes = ram.bytes.borrow();
+        assert_eq!(
+            &bytes[base..base + processes[0].instructions.len()],
+            &processes[0].instructions[..]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn policies_on_same_reference_string() -> Result<()> {
+        let processes = [
+            Process::with_pid(1),
+            Process::with_pid(2),
+            Process::with_pid(3),
+        ];
+        // Pages of A, B, C: A B A C A B
+        let refs = [(1, 0), (2, 0), (1, 1), (3, 0), (1, 2), (2, 3)];
+        let future = refs
+            .iter()
+            .map(|&(pid, vaddr)| (pid, vaddr / PAGE_SIZE))
+            .collect();
+        let results = compare_policies(
+            MemoryConfig::default(),
+            2,
+            &processes,
+            &refs,
+            vec![
+                Box::<Fifo>::default(),
+                Box::<Lru>::default(),
+                Box::<Clock>::default(),
+                Box::new(Optimal::new(future)),
+            ],
+        )?;
+        let summary: Vec<_> = results
+            .iter()
+            .map(|(name, s)| (*name, s.hits, s.misses, s.evictions))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("FIFO", 1, 5, 3),
+                ("LRU", 2, 4, 2),
+                ("Clock", 1, 5, 3),
+                ("Optimal", 2, 4, 2),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn demand_paging_from_file() -> Result<()> {
+        let ram = Rc::new(Ram::new(MemoryConfig::new(64, 16, 16)?));
+        let mut pager = Pager::with_frames(&ram, 2, Box::<Fifo>::default());
+        let mut fs = Filesystem::new(20)?;
+        let fd = fs.open("image", OpenFlags::WRITE | OpenFlags::CREATE)?;
+        fs.write(fd, &(0..=255).cycle().take(512).collect::<Vec<u8>>())?;
+        fs.close(fd)?;
+        let file = Rc::new(RefCell::new(fs.image("image")?));
+        let mut original = vec![0; 64];
+        file.borrow().read_at(0, &mut original);
+        pager.map_file(1, file.clone())?;
+        assert!(pager.frames.iter().all(Option::is_none));
+
+        let outcome = pager.access_as(1, 16 + 3, AccessKind::Write)?;
+        assert!(outcome.fault);
+        assert_eq!(ram.read(outcome.paddr - 3, 16)?, original[16..32]);
+        ram.write(outcome.paddr, &[0xAA])?;
+        pager.access(1, 0)?;
+        // Evicting the clean page 0 doesn't touch the file, evicting the dirty page 1 does.
+        pager.access(1, 32)?;
+        assert_eq!(pager.stats.writebacks, 1);
+        pager.access(1, 48)?;
+        assert_eq!(pager.stats.writebacks, 1);
+        let mut byte = [0];
+        file.borrow().read_at(19, &mut byte);
+        assert_eq!(byte, [0xAA]);
+        assert!(pager.access(1, 512).is_err());
+
+        let outcome = pager.access_as(1, 50, AccessKind::Write)?;
+        ram.write(outcome.paddr, &[0xBB])?;
+        pager.unload_process(1)?;
+        assert_eq!(pager.stats.writebacks, 2);
+        file.borrow().read_at(50, &mut byte);
+        assert_eq!(byte, [0xBB]);
+
+        // The blocks of a mapped file can't be freed and handed to another file.
+        assert!(fs
+            .open("image", OpenFlags::WRITE | OpenFlags::TRUNCATE)
+            .is_err());
+        assert!(fs.delete("image").is_err());
+        drop(file);
+        fs.delete("image")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{file::Owner, ram::Protection};
+use color_eyre::Result;
+use std::{fmt, str::FromStr};
+
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+/// On a directory: only the owner of an entry, of the directory or root may remove the entry.
+pub const S_ISVTX: u16 = 0o1000;
+
+/// Permission bits of a file: `rwx` for its owner, group and everybody else,
+/// plus the setuid, setgid and sticky bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mode(pub(crate) u16);
+
+/// Who a clause of a symbolic mode applies to, as a mask of the `rwx` bits of each class.
+const USER: u16 = 0o700;
+const GROUP: u16 = 0o070;
+const OTHER: u16 = 0o007;
+
+#[allow(dead_code)]
+impl Mode {
+    /// Mask of every permission bit.
+    pub const MASK: u16 = 0o7777;
+
+    /// Rights of the owner, the group or others, picked by `class`: 2 for the owner, 1 for the
+    /// group and 0 for others.
+    #[must_use]
+    pub fn rights(self, class: u32) -> Protection {
+        let bits = (self.0 >> (3 * class)) & 0o7;
+        let flag = |bit: u16, right: Protection| {
+            if bits & bit != 0 {
+                right
+            } else {
+                Protection::NONE
+            }
+        };
+        flag(0o4, Protection::READ) | flag(0o2, Protection::WRITE) | flag(0o1, Protection::EXECUTE)
+    }
+
+    /// Apply a change to the mode, like `chmod` does: an absolute mode in octal (`750`)
+    /// or as a string (`rwxr-x---`), or comma-separated symbolic clauses (`u+x,go-w,a=r`).
+    pub fn apply(self, change: &str) -> Result<Self> {
+        if let Ok(mode) = change.parse() {
+            return Ok(mode);
+        }
+        change
+            .split(',')
+            .try_fold(self, |mode, clause| mode.apply_clause(clause))
+    }
+
+    /// Apply one `[ugoa]*[+-=][rwxst]*` clause.
+    fn apply_clause(self, clause: &str) -> Result<Self> {
+        let split = clause.find(['+', '-', '=']).ok_or(eyre::eyre!(
+            "Invalid mode change '{clause}': expected +, - or ="
+        ))?;
+        let (who, rest) = clause.split_at(split);
+        let (op, perms) = rest.split_at(1);
+        let mut classes = 0;
+        for c in who.chars() {
+            classes |= match c {
+                'u' => USER,
+                'g' => GROUP,
+                'o' => OTHER,
+                'a' => USER | GROUP | OTHER,
+                _ => eyre::bail!("Invalid mode change '{clause}': unknown class '{c}'"),
+            };
+        }
+        if classes == 0 {
+            classes = USER | GROUP | OTHER;
+        }
+        let mut bits = 0;
+        for c in perms.chars() {
+            bits |= match c {
+                'r' => 0o444 & classes,
+                'w' => 0o222 & classes,
+                'x' => 0o111 & classes,
+                's' => {
+                    (if classes & USER != 0 { S_ISUID } else { 0 })
+                        | (if classes & GROUP != 0 { S_ISGID } else { 0 })
+                }
+                't' => S_ISVTX,
+                _ => eyre::bail!("Invalid mode change '{clause}': unknown permission '{c}'"),
+            };
+        }
+        Ok(Self(match op {
+            "+" => self.0 | bits,
+            "-" => self.0 & !bits,
+            _ => {
+                let mut cleared = classes;
+                if classes & USER != 0 {
+                    cleared |= S_ISUID;
+                }
+                if classes & GROUP != 0 {
+                    cleared |= S_ISGID;
+                }
+                self.0 & !cleared | bits
+            }
+        }))
+    }
+}
+
+impl FromStr for Mode {
+    type Err = eyre::Report;
+
+    /// Parse an absolute mode: up to four octal digits or nine `rwx` characters,
+    /// with `s`/`S` and `t`/`T` in place of the execute bits for setuid, setgid and sticky.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_empty() && s.len() <= 4 && s.chars().all(|c| c.is_digit(8)) {
+            return Ok(Self(u16::from_str_radix(s, 8)?));
+        }
+        let chars: Vec<char> = s.chars().collect();
+        eyre::ensure!(
+            chars.len() == 9,
+            "Invalid mode '{s}': expected octal digits or 9 characters like 'rwxr-x---'"
+        );
+        let special = [S_ISUID, S_ISGID, S_ISVTX];
+        let mut mode = 0;
+        for (class, triple) in chars.chunks(3).enumerate() {
+            let shift = 3 * (2 - class);
+            let [r, w, x] = [triple[0], triple[1], triple[2]];
+            let bit = |c: char, set: char, bit: u16| match c {
+                '-' => Ok(0),
+                c if c == set => Ok(bit),
+                _ => Err(eyre::eyre!("Invalid mode '{s}': unexpected '{c}'")),
+            };
+            mode |= bit(r, 'r', 0o4 << shift)? | bit(w, 'w', 0o2 << shift)?;
+            let (execute, marker) = if class == 2 { ('t', 'T') } else { ('s', 'S') };
+            mode |= match x {
+                '-' => 0,
+                'x' => 0o1 << shift,
+                c if c == execute => 0o1 << shift | special[class],
+                c if c == marker => special[class],
+                c => eyre::bail!("Invalid mode '{s}': unexpected '{c}'"),
+            };
+        }
+        Ok(Self(mode))
+    }
+}
+
+impl fmt::Display for Mode {
+    /// Format the mode like `ls -l` does, e.g. `rwsr-x--T`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (class, special) in [S_ISUID, S_ISGID, S_ISVTX].into_iter().enumerate() {
+            let bits = (self.0 >> (3 * (2 - class))) & 0o7;
+            let flag = |bit: u16, c: char| if bits & bit != 0 { c } else { '-' };
+            let (execute, marker) = if class == 2 { ('t', 'T') } else { ('s', 'S') };
+            let x = match (bits & 0o1 != 0, self.0 & special != 0) {
+                (true, true) => execute,
+                (false, true) => marker,
+                (true, false) => 'x',
+                (false, false) => '-',
+            };
+            write!(f, "{}{}{x}", flag(0o4, 'r'), flag(0o2, 'w'))?;
+        }
+        Ok(())
+    }
+}
+
+/// Who is calling a [`Filesystem`](crate::file::Filesystem) operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub(crate) user: String,
+    /// Groups of the user, the first one being the group new files get.
+    pub(crate) groups: Vec<String>,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::root()
+    }
+}
+
+#[allow(dead_code)]
+impl Credentials {
+    /// The superuser, who may do anything but execute a file nobody may execute.
+    #[must_use]
+    pub fn root() -> Self {
+        Self::new("root", &["root"])
+    }
+
+    #[must_use]
+    pub fn new(user: &str, groups: &[&str]) -> Self {
+        Self {
+            user: user.to_string(),
+            groups: groups.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_root(&self) -> bool {
+        self.user == "root"
+    }
+
+    /// The group new files of the user get.
+    #[must_use]
+    pub fn group(&self) -> &str {
+        self.groups.first().map_or("", String::as_str)
+    }
+
+    /// The owner of the files the user creates.
+    #[must_use]
+    pub fn owner(&self) -> Owner {
+        Owner {
+            name: self.user.clone(),
+            group: self.group().to_string(),
+        }
+    }
+
+    /// Whether the user is in `group`.
+    #[must_use]
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g == group)
+    }
+
+    /// Whether these credentials may access a file of `owner` with `mode` in every way `wanted`
+    /// asks for. Only the rights of the first matching class count: an owner who denied
+    /// themselves something doesn't get it through the group.
+    #[must_use]
+    pub fn may(&self, owner: &Owner, mode: Mode, wanted: Protection, is_dir: bool) -> bool {
+        if self.is_root() {
+            return is_dir || !wanted.contains(Protection::EXECUTE) || mode.0 & 0o111 != 0;
+        }
+        let class = if self.user == owner.name {
+            2
+        } else if self.in_group(&owner.group) {
+            1
+        } else {
+            0
+        };
+        mode.rights(class).contains(wanted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Credentials, Mode};
+    use crate::{file::Owner, ram::Protection};
+    use color_eyre::Result;
+
+    #[test]
+    fn symbolic_modes() -> Result<()> {
+        let mode: Mode = "rwxr-x---".parse()?;
+        assert_eq!(mode, Mode(0o750));
+        assert_eq!("750".parse::<Mode>()?, mode);
+        assert_eq!(mode.apply("u-w,go+r")?, Mode(0o554));
+        assert_eq!(mode.apply("a=r")?, Mode(0o444));
+        assert_eq!(mode.apply("+x")?, Mode(0o751));
+        assert_eq!(mode.apply("644")?, Mode(0o644));
+        assert_eq!(mode.apply("u+s,g+s,+t")?, Mode(0o7750));
+        assert_eq!(Mode(0o7750).to_string(), "rwsr-s--T");
+        assert_eq!(Mode(0o1777).to_string(), "rwxrwxrwt");
+        assert_eq!("rwSr-x--t".parse::<Mode>()?, Mode(0o5651));
+        assert_eq!(Mode(0o4644).apply("u=rw")?, Mode(0o644));
+        assert!(mode.apply("u*x").is_err());
+        assert!(mode.apply("z+x").is_err());
+        assert!(mode.apply("u+q").is_err());
+        assert!("rwxr-x--".parse::<Mode>().is_err());
+        assert!("12345".parse::<Mode>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn classes() {
+        let alice = Credentials::new("alice", &["staff"]);
+        let bob = Credentials::new("bob", &["users", "staff"]);
+        let owner = alice.owner();
+        let mode = Mode(0o460);
+        assert!(alice.may(&owner, mode, Protection::READ, false));
+        // The owner class matches first, so alice can't write even though her group can.
+        assert!(!alice.may(&owner, mode, Protection::WRITE, false));
+        assert!(bob.may(&owner, mode, Protection::RW, false));
+        let wheel = Owner {
+            group: String::from("wheel"),
+            ..owner.clone()
+        };
+        assert!(!bob.may(&wheel, mode, Protection::READ, false));
+        let root = Credentials::root();
+        assert!(root.may(&owner, Mode(0), Protection::RW, false));
+        assert!(!root.may(&owner, Mode(0o644), Protection::EXECUTE, false));
+        assert!(root.may(&owner, Mode(0), Protection::EXECUTE, true));
+    }
+}
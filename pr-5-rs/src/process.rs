@@ -1,26 +1,144 @@
-use colored::Colorize;
-use rand::{self, Rng};
+use crate::{hexview::HexView, rng};
+use color_eyre::Result;
+use colored::Color;
+use rand::Rng;
 use std::fmt;
 
+/// Default process size, see [`MemoryConfig`](crate::config::MemoryConfig).
 pub const PROCESS_SIZE: usize = 32;
 
-#[derive(Debug)]
+/// Stage of the lifecycle of a [`Process`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProcessState {
+    /// Created, but not admitted to the ready queue yet.
+    #[default]
+    New,
+    /// Waiting for the CPU.
+    Ready,
+    Running,
+    /// Waiting for I/O to complete.
+    Blocked,
+    Terminated,
+}
+
+impl ProcessState {
+    /// Whether a process may go from `self` straight to `to`.
+    #[must_use]
+    pub const fn can_become(self, to: Self) -> bool {
+        matches!(
+            (self, to),
+            (Self::New | Self::Blocked | Self::Running, Self::Ready)
+                | (Self::Ready, Self::Running)
+                | (Self::Running, Self::Blocked | Self::Terminated)
+        )
+    }
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::New => "новый",
+            Self::Ready => "готов",
+            Self::Running => "выполняется",
+            Self::Blocked => "заблокирован",
+            Self::Terminated => "завершён",
+        })
+    }
+}
+
+/// A stretch of time a [`Process`] spends either computing or waiting for I/O.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Burst {
+    Cpu(usize),
+    Io(usize),
+}
+
+impl fmt::Display for Burst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cpu(time) => write!(f, "CPU {time}"),
+            Self::Io(time) => write!(f, "В/В {time}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Process {
     pub(crate) pid: u16,
-    pub(crate) instructions: [u8; PROCESS_SIZE],
+    pub(crate) instructions: Vec<u8>,
+    pub(crate) state: ProcessState,
+    /// Scheduling priority. Lower values are more important.
+    pub(crate) priority: u8,
+    /// Time the process arrives at the scheduler.
+    pub(crate) arrival: usize,
+    /// Bursts the process goes through, in order. Starts and ends with [`Burst::Cpu`].
+    pub(crate) bursts: Vec<Burst>,
 }
 
+#[allow(dead_code)]
 impl Process {
-    /// Generate a [`Process`] with a random `pid` and random `instructions`.
+    /// Generate a [`Process`] with a random `pid` and [`PROCESS_SIZE`] bytes of random `instructions`.
     #[must_use]
     pub fn new() -> Self {
-        let mut rng = rand::thread_rng();
+        let pid = rng::with(|rng| rng.gen());
+        Self::with_size(pid, PROCESS_SIZE)
+    }
+
+    /// Generate a [`Process`] with a set `pid` and `size` bytes of random `instructions`.
+    #[must_use]
+    pub fn with_size(pid: u16, size: usize) -> Self {
+        let mut instructions = vec![0; size];
+        rng::with(|rng| rng.fill(&mut instructions[..]));
+        Self::from_image(pid, instructions)
+    }
+
+    /// Create a [`Process`] out of a set `pid` and `instructions`, arriving at time zero with
+    /// the highest priority and no bursts to run.
+    #[must_use]
+    pub const fn from_image(pid: u16, instructions: Vec<u8>) -> Self {
         Self {
-            pid: rng.gen(),
-            instructions: rng.gen(),
+            pid,
+            instructions,
+            state: ProcessState::New,
+            priority: 0,
+            arrival: 0,
+            bursts: vec![],
         }
     }
 
+    /// Set when the process arrives, its priority and the bursts it runs through.
+    #[must_use]
+    pub fn with_schedule(mut self, arrival: usize, priority: u8, bursts: Vec<Burst>) -> Self {
+        self.arrival = arrival;
+        self.priority = priority;
+        self.bursts = bursts;
+        self
+    }
+
+    /// Move the process to the `to` state, if its lifecycle allows it.
+    pub fn transition(&mut self, to: ProcessState) -> Result<()> {
+        eyre::ensure!(
+            self.state.can_become(to),
+            "Process with pid {} cannot go from {:?} to {to:?}",
+            self.pid,
+            self.state
+        );
+        self.state = to;
+        Ok(())
+    }
+
+    /// Total CPU time the process needs.
+    #[must_use]
+    pub fn cpu_time(&self) -> usize {
+        self.bursts
+            .iter()
+            .map(|burst| match burst {
+                Burst::Cpu(time) => *time,
+                Burst::Io(_) => 0,
+            })
+            .sum()
+    }
+
     /// Generate a [`Process`] with a set `pid` and random `instructions`.
     #[must_use]
     pub fn with_pid(pid: u16) -> Self {
@@ -31,25 +149,39 @@ impl Process {
 }
 
 impl fmt::Display for Process {
-    #[allow(dead_code)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f)?;
-        write!(
-            f,
-            "\t┌── Процесс с PID {:5} ──────────────────────────┐",
-            self.pid
-        )?;
-        for (i, byte) in self.instructions.iter().enumerate() {
-            if i % (PROCESS_SIZE / 2) == 0 {
-                write!(f, "\n\t│ ")?;
-            }
-            let formatted_byte = format!("{byte:02x}").red();
-            write!(f, "{formatted_byte} ")?;
-            if i % (PROCESS_SIZE / 2) == (PROCESS_SIZE / 2) - 1 {
-                write!(f, "│")?;
-            }
+        let view = HexView::new(format!("Процесс с PID {:5}", self.pid), &self.instructions)
+            .width(PROCESS_SIZE / 2)
+            .color(Color::Red);
+        write!(f, "\n{view}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Burst, Process, ProcessState};
+    use color_eyre::Result;
+
+    #[test]
+    fn lifecycle() -> Result<()> {
+        let mut process = Process::from_image(1, vec![]).with_schedule(
+            0,
+            0,
+            vec![Burst::Cpu(2), Burst::Io(3), Burst::Cpu(1)],
+        );
+        assert_eq!(process.cpu_time(), 3);
+        assert!(process.transition(ProcessState::Running).is_err());
+        for state in [
+            ProcessState::Ready,
+            ProcessState::Running,
+            ProcessState::Blocked,
+            ProcessState::Ready,
+            ProcessState::Running,
+            ProcessState::Terminated,
+        ] {
+            process.transition(state)?;
         }
-        writeln!(f, "\n\t└─────────────────────────────────────────────────┘")?;
+        assert!(process.transition(ProcessState::Ready).is_err());
         Ok(())
     }
 }
@@ -1,20 +1,413 @@
-use crate::page::{MAX_PAGE_COUNT, PAGE_SIZE};
-use std::cell::RefCell;
+use crate::{
+    config::MemoryConfig,
+    hexview::HexView,
+    page::{MAX_PAGE_COUNT, PAGE_SIZE},
+};
+use std::{cell::RefCell, error::Error, fmt, ops::Range};
 
+/// Size of a [`Ram`] with the default [`MemoryConfig`].
+#[allow(dead_code)]
 pub const RAM_SIZE: usize = PAGE_SIZE * MAX_PAGE_COUNT;
 
+/// Access rights of a region of [`Ram`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protection(u8);
+
+#[allow(dead_code)]
+impl Protection {
+    pub const NONE: Self = Self(0);
+    pub const READ: Self = Self(0b001);
+    pub const WRITE: Self = Self(0b010);
+    pub const EXECUTE: Self = Self(0b100);
+    pub const RW: Self = Self(0b011);
+    pub const RX: Self = Self(0b101);
+    pub const RWX: Self = Self(0b111);
+
+    /// Whether every right in `other` is granted by `self`.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for Protection {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |right: Self, c: char| if self.contains(right) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(Self::READ, 'r'),
+            flag(Self::WRITE, 'w'),
+            flag(Self::EXECUTE, 'x')
+        )
+    }
+}
+
+/// Kind of a [`Ram`] access, checked against the [`Protection`] of the region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessKind {
+    pub(crate) const fn required(self) -> Protection {
+        match self {
+            Self::Read => Protection::READ,
+            Self::Write => Protection::WRITE,
+            Self::Execute => Protection::EXECUTE,
+        }
+    }
+}
+
+/// Byte order of multi-byte values read from and written to [`Ram`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Error raised by a [`Ram`] access that isn't allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessViolation {
+    /// Some of the bytes `addr..addr + len` are past the end of a RAM of `size` bytes.
+    OutOfBounds {
+        addr: usize,
+        len: usize,
+        size: usize,
+    },
+    /// The byte at `addr` doesn't allow this kind of access.
+    Protection {
+        addr: usize,
+        kind: AccessKind,
+        allowed: Protection,
+    },
+}
+
+impl fmt::Display for AccessViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfBounds { addr, len, size } => write!(
+                f,
+                "Access violation: {len} byte(s) at {addr:#06x} are out of {size} bytes of RAM"
+            ),
+            Self::Protection {
+                addr,
+                kind,
+                allowed,
+            } => write!(
+                f,
+                "Access violation: {kind:?} at {addr:#06x}, but the region is {allowed}"
+            ),
+        }
+    }
+}
+
+impl Error for AccessViolation {}
+
 /// RAM model. Owns the bytes.
+///
+/// Memory that hasn't been given a [`Protection`] with [`Ram::protect`] is readable,
+/// writable and executable.
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Ram {
-    pub(crate) bytes: RefCell<[u8; RAM_SIZE]>,
+    pub(crate) bytes: RefCell<Box<[u8]>>,
+    pub(crate) config: MemoryConfig,
+    /// Protected regions, sorted and not overlapping. Only regions that aren't
+    /// [`Protection::RWX`] are kept, so the list never outgrows what's actually protected.
+    regions: RefCell<Vec<(Range<usize>, Protection)>>,
 }
 
 impl Default for Ram {
-    /// Creates a default [`Ram`] object.
+    /// Creates a [`Ram`] object with the default [`MemoryConfig`].
     fn default() -> Self {
+        Self::new(MemoryConfig::default())
+    }
+}
+
+#[allow(dead_code)]
+impl Ram {
+    /// Creates zeroed [`Ram`] with the geometry of `config`.
+    #[must_use]
+    pub fn new(config: MemoryConfig) -> Self {
         Self {
-            bytes: RefCell::new([0; RAM_SIZE]),
+            bytes: RefCell::new(vec![0; config.ram_size].into_boxed_slice()),
+            config,
+            regions: RefCell::default(),
+        }
+    }
+
+    /// Size of the RAM in bytes.
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.config.ram_size
+    }
+
+    /// Set the protection of the bytes in `range`, replacing whatever protection they had.
+    pub fn protect(
+        &self,
+        range: Range<usize>,
+        protection: Protection,
+    ) -> Result<(), AccessViolation> {
+        self.check_bounds(range.start, range.len())?;
+        if range.is_empty() {
+            return Ok(());
+        }
+        let mut regions = self.regions.borrow_mut();
+        let mut updated = Vec::with_capacity(regions.len() + 2);
+        for (region, allowed) in regions.drain(..) {
+            if region.end <= range.start || range.end <= region.start {
+                updated.push((region, allowed));
+                continue;
+            }
+            // Keep the parts of an overlapped region on either side of `range`.
+            if region.start < range.start {
+                updated.push((region.start..range.start, allowed));
+            }
+            if range.end < region.end {
+                updated.push((range.end..region.end, allowed));
+            }
+        }
+        if protection != Protection::RWX {
+            updated.push((range, protection));
+        }
+        updated.sort_unstable_by_key(|(region, _)| region.start);
+        for (region, allowed) in updated {
+            match regions.last_mut() {
+                Some((last, last_allowed))
+                    if last.end == region.start && *last_allowed == allowed =>
+                {
+                    last.end = region.end;
+                }
+                _ => regions.push((region, allowed)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Protection of the byte at `addr`.
+    #[must_use]
+    pub fn protection(&self, addr: usize) -> Protection {
+        let regions = self.regions.borrow();
+        let index = regions.partition_point(|(range, _)| range.end <= addr);
+        regions
+            .get(index)
+            .filter(|(range, _)| range.contains(&addr))
+            .map_or(Protection::RWX, |&(_, protection)| protection)
+    }
+
+    /// Make sure `len` bytes at `addr` exist and allow `kind` of access.
+    pub fn check(&self, addr: usize, len: usize, kind: AccessKind) -> Result<(), AccessViolation> {
+        self.check_bounds(addr, len)?;
+        match (addr..addr + len).find(|&a| !self.protection(a).contains(kind.required())) {
+            Some(addr) => Err(AccessViolation::Protection {
+                addr,
+                kind,
+                allowed: self.protection(addr),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Read `len` bytes starting at `addr`.
+    pub fn read(&self, addr: usize, len: usize) -> Result<Vec<u8>, AccessViolation> {
+        self.check(addr, len, AccessKind::Read)?;
+        Ok(self.bytes.borrow()[addr..addr + len].to_vec())
+    }
+
+    /// Fetch `len` bytes of instructions starting at `addr`.
+    pub fn fetch(&self, addr: usize, len: usize) -> Result<Vec<u8>, AccessViolation> {
+        self.check(addr, len, AccessKind::Execute)?;
+        Ok(self.bytes.borrow()[addr..addr + len].to_vec())
+    }
+
+    /// Write `data` starting at `addr`.
+    pub fn write(&self, addr: usize, data: &[u8]) -> Result<(), AccessViolation> {
+        self.check(addr, data.len(), AccessKind::Write)?;
+        self.bytes.borrow_mut()[addr..addr + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Set `len` bytes starting at `addr` to zero.
+    pub fn zero(&self, addr: usize, len: usize) -> Result<(), AccessViolation> {
+        self.check(addr, len, AccessKind::Write)?;
+        self.bytes.borrow_mut()[addr..addr + len].fill(0);
+        Ok(())
+    }
+
+    pub fn read_u8(&self, addr: usize) -> Result<u8, AccessViolation> {
+        Ok(self.read_array::<1>(addr)?[0])
+    }
+
+    pub fn read_u16(&self, addr: usize, endianness: Endianness) -> Result<u16, AccessViolation> {
+        let bytes = self.read_array(addr)?;
+        Ok(match endianness {
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn read_u32(&self, addr: usize, endianness: Endianness) -> Result<u32, AccessViolation> {
+        let bytes = self.read_array(addr)?;
+        Ok(match endianness {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn read_u64(&self, addr: usize, endianness: Endianness) -> Result<u64, AccessViolation> {
+        let bytes = self.read_array(addr)?;
+        Ok(match endianness {
+            Endianness::Little => u64::from_le_bytes(bytes),
+            Endianness::Big => u64::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn write_u8(&self, addr: usize, value: u8) -> Result<(), AccessViolation> {
+        self.write(addr, &[value])
+    }
+
+    pub fn write_u16(
+        &self,
+        addr: usize,
+        value: u16,
+        endianness: Endianness,
+    ) -> Result<(), AccessViolation> {
+        match endianness {
+            Endianness::Little => self.write(addr, &value.to_le_bytes()),
+            Endianness::Big => self.write(addr, &value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_u32(
+        &self,
+        addr: usize,
+        value: u32,
+        endianness: Endianness,
+    ) -> Result<(), AccessViolation> {
+        match endianness {
+            Endianness::Little => self.write(addr, &value.to_le_bytes()),
+            Endianness::Big => self.write(addr, &value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_u64(
+        &self,
+        addr: usize,
+        value: u64,
+        endianness: Endianness,
+    ) -> Result<(), AccessViolation> {
+        match endianness {
+            Endianness::Little => self.write(addr, &value.to_le_bytes()),
+            Endianness::Big => self.write(addr, &value.to_be_bytes()),
+        }
+    }
+
+    fn read_array<const N: usize>(&self, addr: usize) -> Result<[u8; N], AccessViolation> {
+        self.check(addr, N, AccessKind::Read)?;
+        let mut array = [0; N];
+        array.copy_from_slice(&self.bytes.borrow()[addr..addr + N]);
+        Ok(array)
+    }
+
+    const fn check_bounds(&self, addr: usize, len: usize) -> Result<(), AccessViolation> {
+        match addr.checked_add(len) {
+            Some(end) if end <= self.size() => Ok(()),
+            _ => Err(AccessViolation::OutOfBounds {
+                addr,
+                len,
+                size: self.size(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Ram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.bytes.try_borrow().map_err(|_| fmt::Error)?;
+        let view = HexView::new(format!("RAM ({} байт)", self.size()), &bytes[..])
+            .width(self.config.page_dim())
+            .group(self.config.page_size);
+        write!(f, "\n{view}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessKind, AccessViolation, Endianness, Protection, Ram, RAM_SIZE};
+
+    #[test]
+    fn typed_access() -> Result<(), AccessViolation> {
+        let ram = Ram::default();
+        ram.write_u32(8, 0x1234_5678, Endianness::Big)?;
+        assert_eq!(ram.read(8, 4)?, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(ram.read_u32(8, Endianness::Little)?, 0x7856_3412);
+        assert_eq!(ram.read_u16(9, Endianness::Big)?, 0x3456);
+        ram.write_u64(RAM_SIZE - 8, u64::MAX, Endianness::Little)?;
+        assert_eq!(ram.read_u8(RAM_SIZE - 1)?, 0xff);
+        Ok(())
+    }
+
+    #[test]
+    fn violations() {
+        let ram = Ram::default();
+        assert_eq!(
+            ram.read_u16(RAM_SIZE - 1, Endianness::Little),
+            Err(AccessViolation::OutOfBounds {
+                addr: RAM_SIZE - 1,
+                len: 2,
+                size: RAM_SIZE
+            })
+        );
+        ram.protect(16..32, Protection::RX).unwrap();
+        ram.protect(24..28, Protection::NONE).unwrap();
+        assert!(ram.read(16, 8).is_ok());
+        assert_eq!(
+            ram.write(14, &[1, 2, 3]),
+            Err(AccessViolation::Protection {
+                addr: 16,
+                kind: AccessKind::Write,
+                allowed: Protection::RX
+            })
+        );
+        assert_eq!(
+            ram.fetch(20, 8).unwrap_err().to_string(),
+            "Access violation: Execute at 0x0018, but the region is ---"
+        );
+        assert_eq!(ram.read_u8(14), Ok(0));
+
+        // Protecting the same memory over and over doesn't pile up regions.
+        for _ in 0..100 {
+            ram.protect(0..16, Protection::RX).unwrap();
+            ram.protect(0..16, Protection::RWX).unwrap();
         }
+        ram.protect(28..40, Protection::RX).unwrap();
+        assert_eq!(
+            *ram.regions.borrow(),
+            [
+                (16..24, Protection::RX),
+                (24..28, Protection::NONE),
+                (28..40, Protection::RX)
+            ]
+        );
+        ram.protect(20..36, Protection::RWX).unwrap();
+        assert_eq!(
+            *ram.regions.borrow(),
+            [(16..20, Protection::RX), (36..40, Protection::RX)]
+        );
+        assert_eq!(ram.protection(20), Protection::RWX);
+        assert_eq!(ram.protection(39), Protection::RX);
     }
 }
@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+};
+
+/// A virtual page of a specific process: `(pid, vpn)`.
+pub type PageKey = (u16, usize);
+
+/// Page replacement algorithm. Decides which resident page gets evicted when every frame is taken.
+///
+/// The [`Pager`](crate::pager::Pager) calls [`insert`](ReplacementPolicy::insert) when a page is
+/// brought into a frame, [`access`](ReplacementPolicy::access) on every reference to a resident
+/// page (including the one that caused it to be loaded) and [`remove`](ReplacementPolicy::remove)
+/// when a page leaves its frame.
+pub trait ReplacementPolicy: fmt::Debug {
+    /// Name of the algorithm, used in reports.
+    fn name(&self) -> &'static str;
+
+    /// A resident page has been referenced.
+    fn access(&mut self, key: PageKey);
+
+    /// A page has been brought into a frame.
+    fn insert(&mut self, key: PageKey);
+
+    /// A page has left its frame.
+    fn remove(&mut self, key: PageKey);
+
+    /// Pick a resident page to evict, or [`None`] if nothing is resident.
+    fn victim(&mut self) -> Option<PageKey>;
+}
+
+/// First-in, first-out: evicts the page that has been resident the longest.
+#[derive(Debug, Default)]
+pub struct Fifo {
+    queue: VecDeque<PageKey>,
+}
+
+impl ReplacementPolicy for Fifo {
+    fn name(&self) -> &'static str {
+        "FIFO"
+    }
+
+    fn access(&mut self, _key: PageKey) {}
+
+    fn insert(&mut self, key: PageKey) {
+        self.queue.push_back(key);
+    }
+
+    fn remove(&mut self, key: PageKey) {
+        self.queue.retain(|&k| k != key);
+    }
+
+    fn victim(&mut self) -> Option<PageKey> {
+        self.queue.front().copied()
+    }
+}
+
+/// Least recently used: evicts the page whose last reference is the oldest.
+#[derive(Debug, Default)]
+pub struct Lru {
+    clock: u64,
+    last_used: HashMap<PageKey, u64>,
+}
+
+impl ReplacementPolicy for Lru {
+    fn name(&self) -> &'static str {
+        "LRU"
+    }
+
+    fn access(&mut self, key: PageKey) {
+        self.clock += 1;
+        self.last_used.insert(key, self.clock);
+    }
+
+    fn insert(&mut self, key: PageKey) {
+        self.last_used.insert(key, self.clock);
+    }
+
+    fn remove(&mut self, key: PageKey) {
+        self.last_used.remove(&key);
+    }
+
+    fn victim(&mut self) -> Option<PageKey> {
+        self.last_used
+            .iter()
+            .min_by_key(|(&key, &time)| (time, key))
+            .map(|(&key, _)| key)
+    }
+}
+
+/// Clock (second chance): sweeps a circular list of frames, clearing reference bits,
+/// and evicts the first page whose bit is already clear.
+#[derive(Debug, Default)]
+pub struct Clock {
+    ring: Vec<Option<(PageKey, bool)>>,
+    hand: usize,
+}
+
+impl ReplacementPolicy for Clock {
+    fn name(&self) -> &'static str {
+        "Clock"
+    }
+
+    fn access(&mut self, key: PageKey) {
+        if let Some((_, referenced)) = self.ring.iter_mut().flatten().find(|(k, _)| *k == key) {
+            *referenced = true;
+        }
+    }
+
+    fn insert(&mut self, key: PageKey) {
+        match self.ring.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some((key, false)),
+            None => self.ring.push(Some((key, false))),
+        }
+    }
+
+    fn remove(&mut self, key: PageKey) {
+        if let Some(slot) = self
+            .ring
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|(k, _)| k == key))
+        {
+            *slot = None;
+        }
+    }
+
+    fn victim(&mut self) -> Option<PageKey> {
+        if self.ring.iter().all(Option::is_none) {
+            return None;
+        }
+        loop {
+            let index = self.hand;
+            self.hand = (self.hand + 1) % self.ring.len();
+            match &mut self.ring[index] {
+                Some((_, referenced @ true)) => *referenced = false,
+                Some((key, false)) => return Some(*key),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Belady's optimal algorithm: evicts the page that won't be used for the longest time.
+/// Needs to know the whole reference string in advance, so it's only usable in simulations.
+#[derive(Debug, Default)]
+pub struct Optimal {
+    future: Vec<PageKey>,
+    position: usize,
+    resident: Vec<PageKey>,
+}
+
+impl Optimal {
+    /// Create the policy for a known reference string.
+    #[must_use]
+    pub fn new(future: Vec<PageKey>) -> Self {
+        Self {
+            future,
+            ..Default::default()
+        }
+    }
+}
+
+impl ReplacementPolicy for Optimal {
+    fn name(&self) -> &'static str {
+        "Optimal"
+    }
+
+    fn access(&mut self, _key: PageKey) {
+        self.position += 1;
+    }
+
+    fn insert(&mut self, key: PageKey) {
+        self.resident.push(key);
+    }
+
+    fn remove(&mut self, key: PageKey) {
+        self.resident.retain(|&k| k != key);
+    }
+
+    fn victim(&mut self) -> Option<PageKey> {
+        let upcoming = &self.future[self.position.min(self.future.len())..];
+        // Pages that are never used again are the best victims; ties go to the oldest page.
+        self.resident
+            .iter()
+            .enumerate()
+            .max_by_key(|(age, key)| {
+                let next_use = upcoming
+                    .iter()
+                    .position(|k| k == *key)
+                    .unwrap_or(usize::MAX);
+                (next_use, std::cmp::Reverse(*age))
+            })
+            .map(|(_, &key)| key)
+    }
+}
+
+/// Names of the available [`ReplacementPolicy`] implementations, for selecting one at runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PolicyKind {
+    #[default]
+    Fifo,
+    Lru,
+    Clock,
+    Optimal,
+}
+
+impl PolicyKind {
+    /// Build the policy. `future` is the reference string, only used by [`Optimal`].
+    #[must_use]
+    pub fn build(self, future: Vec<PageKey>) -> Box<dyn ReplacementPolicy> {
+        match self {
+            Self::Fifo => Box::<Fifo>::default(),
+            Self::Lru => Box::<Lru>::default(),
+            Self::Clock => Box::<Clock>::default(),
+            Self::Optimal => Box::new(Optimal::new(future)),
+        }
+    }
+}
+
+impl FromStr for PolicyKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" => Ok(Self::Fifo),
+            "lru" => Ok(Self::Lru),
+            "clock" => Ok(Self::Clock),
+            "optimal" | "opt" => Ok(Self::Optimal),
+            _ => Err(eyre::eyre!(
+                "Unknown replacement policy '{s}', expected one of: fifo, lru, clock, optimal"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, Fifo, Lru, Optimal, ReplacementPolicy};
+
+    /// Feed a reference string to a policy with `frames` frames, returning the evicted pages.
+    fn run(policy: &mut dyn ReplacementPolicy, frames: usize, refs: &[usize]) -> Vec<usize> {
+        let mut resident = vec![];
+        let mut evicted = vec![];
+        for &vpn in refs {
+            let key = (1, vpn);
+            if !resident.contains(&key) {
+                if resident.len() == frames {
+                    let victim = policy.victim().unwrap();
+                    policy.remove(victim);
+                    resident.retain(|&k| k != victim);
+                    evicted.push(victim.1);
+                }
+                policy.insert(key);
+                resident.push(key);
+            }
+            policy.access(key);
+        }
+        evicted
+    }
+
+    const REFS: [usize; 12] = [1, 2, 3, 4, 1, 2, 5, 1, 2, 3, 4, 5];
+
+    #[test]
+    fn fifo_belady_anomaly() {
+        assert_eq!(run(&mut Fifo::default(), 3, &REFS).len() + 3, 9);
+        assert_eq!(run(&mut Fifo::default(), 4, &REFS).len() + 4, 10);
+    }
+
+    #[test]
+    fn textbook_fault_counts() {
+        assert_eq!(run(&mut Lru::default(), 3, &REFS).len() + 3, 10);
+        assert_eq!(run(&mut Clock::default(), 3, &REFS).len() + 3, 9);
+        let future = REFS.iter().map(|&vpn| (1, vpn)).collect();
+        assert_eq!(run(&mut Optimal::new(future), 3, &REFS).len() + 3, 7);
+    }
+}
@@ -0,0 +1,43 @@
+use color_eyre::Result;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{cell::RefCell, env};
+
+/// Environment variable the seed is read from when `--seed` isn't passed.
+pub const SEED_VAR: &str = "MIREA_SEED";
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Seed the shared generator with `seed`, falling back to [`SEED_VAR`] and then to a random seed.
+/// Returns the seed that was used, so the run can be reproduced.
+pub fn init(seed: Option<u64>) -> Result<u64> {
+    let seed = match (seed, env::var(SEED_VAR)) {
+        (Some(seed), _) => seed,
+        (None, Ok(seed)) => seed
+            .parse()
+            .map_err(|e| eyre::eyre!("Invalid {SEED_VAR} '{seed}': {e}"))?,
+        (None, Err(_)) => rand::random(),
+    };
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+    Ok(seed)
+}
+
+/// Run `f` with the shared generator. Every random choice of the simulation must go through here.
+pub fn with<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init, with};
+    use rand::Rng;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        init(Some(42)).unwrap();
+        let first: [u8; 16] = with(|rng| rng.gen());
+        init(Some(42)).unwrap();
+        assert_eq!(first, with(|rng| rng.gen::<[u8; 16]>()));
+    }
+}
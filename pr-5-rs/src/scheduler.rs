@@ -0,0 +1,548 @@
+use crate::{
+    process::{Burst, Process, ProcessState, PROCESS_SIZE},
+    rng,
+};
+use color_eyre::Result;
+use rand::Rng;
+use std::{collections::VecDeque, fmt, ops::Range, str::FromStr};
+
+/// Time slice of round-robin scheduling and of the top level of the multilevel feedback queue.
+pub const DEFAULT_QUANTUM: usize = 2;
+
+/// Number of queues of the multilevel feedback queue. Queue `i` has a quantum of
+/// `quantum << i`, except the last one, which runs processes until their burst ends.
+pub const MLFQ_LEVELS: usize = 3;
+
+/// CPU scheduling algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchedulerKind {
+    /// First come, first served.
+    #[default]
+    Fcfs,
+    /// Shortest job (CPU burst) first.
+    Sjf,
+    /// Shortest remaining time first: SJF that preempts the running process.
+    Srtf,
+    RoundRobin,
+    Priority,
+    /// Priority scheduling that preempts the running process for a more important one.
+    PreemptivePriority,
+    /// Multilevel feedback queue: processes that use up their quantum drop to a lower queue.
+    Mlfq,
+}
+
+impl SchedulerKind {
+    pub const ALL: [Self; 7] = [
+        Self::Fcfs,
+        Self::Sjf,
+        Self::Srtf,
+        Self::RoundRobin,
+        Self::Priority,
+        Self::PreemptivePriority,
+        Self::Mlfq,
+    ];
+
+    /// Name of the algorithm, used in reports.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Fcfs => "FCFS",
+            Self::Sjf => "SJF",
+            Self::Srtf => "SRTF",
+            Self::RoundRobin => "RR",
+            Self::Priority => "Priority",
+            Self::PreemptivePriority => "Priority-P",
+            Self::Mlfq => "MLFQ",
+        }
+    }
+
+    /// Time slice of a process in queue `level`, if the algorithm has one.
+    const fn quantum(self, quantum: usize, level: usize) -> Option<usize> {
+        match self {
+            Self::RoundRobin => Some(quantum),
+            Self::Mlfq if level + 1 < MLFQ_LEVELS => Some(quantum << level),
+            _ => None,
+        }
+    }
+
+    /// Key ready processes are picked by, lowest first. Ties go to whoever has waited the longest.
+    fn key(self, job: &Job) -> usize {
+        match self {
+            Self::Fcfs | Self::RoundRobin => 0,
+            Self::Sjf | Self::Srtf => job.remaining,
+            Self::Priority | Self::PreemptivePriority => usize::from(job.process.priority),
+            Self::Mlfq => job.level,
+        }
+    }
+
+    /// Whether `candidate` takes the CPU away from `current` as soon as it's ready.
+    fn preempts(self, candidate: &Job, current: &Job) -> bool {
+        match self {
+            Self::Srtf | Self::PreemptivePriority | Self::Mlfq => {
+                self.key(candidate) < self.key(current)
+            }
+            Self::Fcfs | Self::Sjf | Self::RoundRobin | Self::Priority => false,
+        }
+    }
+}
+
+impl fmt::Display for SchedulerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for SchedulerKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fcfs" => Ok(Self::Fcfs),
+            "sjf" => Ok(Self::Sjf),
+            "srtf" => Ok(Self::Srtf),
+            "rr" => Ok(Self::RoundRobin),
+            "priority" => Ok(Self::Priority),
+            "priority-p" => Ok(Self::PreemptivePriority),
+            "mlfq" => Ok(Self::Mlfq),
+            _ => Err(eyre::eyre!(
+                "Unknown scheduler '{s}', expected one of: fcfs, sjf, srtf, rr, priority, priority-p, mlfq"
+            )),
+        }
+    }
+}
+
+/// A [`Process`] along with the scheduler's bookkeeping.
+#[derive(Debug)]
+struct Job {
+    process: Process,
+    /// Index of the current burst.
+    burst: usize,
+    /// Time left in the current burst.
+    remaining: usize,
+    /// Queue of the multilevel feedback queue the process is in.
+    level: usize,
+    /// Time the process has run since it last got the CPU.
+    used: usize,
+    first_run: Option<usize>,
+    completion: Option<usize>,
+    waiting: usize,
+}
+
+impl Job {
+    fn new(process: Process) -> Self {
+        let remaining = burst_time(process.bursts[0]);
+        Self {
+            process,
+            burst: 0,
+            remaining,
+            level: 0,
+            used: 0,
+            first_run: None,
+            completion: None,
+            waiting: 0,
+        }
+    }
+
+    /// Move on to the next burst, returning `false` if there is none.
+    fn advance(&mut self) -> bool {
+        self.burst += 1;
+        match self.process.bursts.get(self.burst) {
+            Some(&burst) => {
+                self.remaining = burst_time(burst);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+const fn burst_time(burst: Burst) -> usize {
+    match burst {
+        Burst::Cpu(time) | Burst::Io(time) => time,
+    }
+}
+
+/// Timing of a single process in a [`Schedule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessTimes {
+    pub pid: u16,
+    pub arrival: usize,
+    pub priority: u8,
+    pub cpu_time: usize,
+    pub completion: usize,
+    /// Time spent in the ready queue.
+    pub waiting: usize,
+    /// Time from arrival to completion.
+    pub turnaround: usize,
+    /// Time from arrival to the first time on the CPU.
+    pub response: usize,
+}
+
+/// Result of [`run`]ning a workload through a scheduler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub kind: SchedulerKind,
+    pub quantum: usize,
+    /// PID of the process on the CPU during every unit of time, `None` if the CPU was idle.
+    pub timeline: Vec<Option<u16>>,
+    /// Processes sorted by PID.
+    pub processes: Vec<ProcessTimes>,
+}
+
+impl Schedule {
+    /// Runs of the same process on the CPU, for a Gantt chart.
+    #[must_use]
+    pub fn segments(&self) -> Vec<(Range<usize>, Option<u16>)> {
+        let mut segments: Vec<(Range<usize>, Option<u16>)> = vec![];
+        for (time, &pid) in self.timeline.iter().enumerate() {
+            match segments.last_mut() {
+                Some((range, last)) if *last == pid => range.end = time + 1,
+                _ => segments.push((time..time + 1, pid)),
+            }
+        }
+        segments
+    }
+
+    #[must_use]
+    pub fn average_waiting(&self) -> f64 {
+        self.average(|times| times.waiting)
+    }
+
+    #[must_use]
+    pub fn average_turnaround(&self) -> f64 {
+        self.average(|times| times.turnaround)
+    }
+
+    #[must_use]
+    pub fn average_response(&self) -> f64 {
+        self.average(|times| times.response)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn average(&self, f: impl Fn(&ProcessTimes) -> usize) -> f64 {
+        match self.processes.len() {
+            0 => 0.0,
+            count => self.processes.iter().map(f).sum::<usize>() as f64 / count as f64,
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quantum = match self.kind {
+            SchedulerKind::RoundRobin | SchedulerKind::Mlfq => format!(", квант {}", self.quantum),
+            _ => String::new(),
+        };
+        writeln!(f, "\n\t\tПланировщик: {}{quantum}\n", self.kind.name())?;
+
+        let (mut bar, mut times) = (String::from("│"), String::from("0"));
+        let mut column = 0;
+        for (range, pid) in self.segments() {
+            let label = pid.map_or_else(|| "—".to_string(), |pid| format!("P{pid}"));
+            let width = (label.chars().count() + 2).max(range.len() * 2);
+            bar.push_str(&format!("{label:^width$}│"));
+            column += width + 1;
+            let padding = column.saturating_sub(times.chars().count());
+            times.push_str(&format!("{:padding$}{}", "", range.end));
+        }
+        writeln!(f, "\t\tДиаграмма Ганта:\n\t\t{bar}\n\t\t{times}\n")?;
+
+        writeln!(
+            f,
+            "\t┌───────┬──────────┬───────────┬─────┬────────────┬──────────┬────────┬────────┐"
+        )?;
+        writeln!(
+            f,
+            "\t│ {:>5} │ {:>8} │ {:>9} │ {:>3} │ {:>10} │ {:>8} │ {:>6} │ {:>6} │",
+            "PID", "прибытие", "приоритет", "CPU", "завершение", "ожидание", "оборот", "отклик"
+        )?;
+        writeln!(
+            f,
+            "\t├───────┼──────────┼───────────┼─────┼────────────┼──────────┼────────┼────────┤"
+        )?;
+        for times in &self.processes {
+            writeln!(
+                f,
+                "\t│ {:>5} │ {:>8} │ {:>9} │ {:>3} │ {:>10} │ {:>8} │ {:>6} │ {:>6} │",
+                times.pid,
+                times.arrival,
+                times.priority,
+                times.cpu_time,
+                times.completion,
+                times.waiting,
+                times.turnaround,
+                times.response
+            )?;
+        }
+        writeln!(
+            f,
+            "\t└───────┴──────────┴───────────┴─────┴────────────┴──────────┴────────┴────────┘"
+        )?;
+        write!(
+            f,
+            "\t\tСреднее время ожидания: {:.2}, оборота: {:.2}, отклика: {:.2}",
+            self.average_waiting(),
+            self.average_turnaround(),
+            self.average_response()
+        )
+    }
+}
+
+/// Make sure the bursts of `process` alternate between CPU and I/O,
+/// start and end on the CPU and all take some time.
+fn validate(process: &Process) -> Result<()> {
+    let pid = process.pid;
+    eyre::ensure!(
+        matches!(process.bursts.first(), Some(Burst::Cpu(_)))
+            && matches!(process.bursts.last(), Some(Burst::Cpu(_))),
+        "Process with pid {pid} must start and end with a CPU burst"
+    );
+    for (i, pair) in process.bursts.windows(2).enumerate() {
+        eyre::ensure!(
+            matches!(
+                pair,
+                [Burst::Cpu(_), Burst::Io(_)] | [Burst::Io(_), Burst::Cpu(_)]
+            ),
+            "Process with pid {pid} has two bursts of the same kind in a row at burst {}",
+            i + 2
+        );
+    }
+    eyre::ensure!(
+        process.bursts.iter().all(|&burst| burst_time(burst) > 0),
+        "Process with pid {pid} has an empty burst"
+    );
+    Ok(())
+}
+
+/// Simulate scheduling `processes` on a single CPU with the `kind` algorithm, one unit of time
+/// at a time. I/O of different processes proceeds in parallel.
+///
+/// Processes that arrive, finish their I/O or use up their quantum at the same time join the
+/// ready queue in that order.
+pub fn run(processes: Vec<Process>, kind: SchedulerKind, quantum: usize) -> Result<Schedule> {
+    eyre::ensure!(quantum > 0, "Quantum must be positive");
+    for process in &processes {
+        validate(process)?;
+        eyre::ensure!(
+            processes.iter().filter(|p| p.pid == process.pid).count() == 1,
+            "Pid {} is used by more than one process",
+            process.pid
+        );
+    }
+    let mut jobs: Vec<Job> = processes.into_iter().map(Job::new).collect();
+    jobs.sort_by_key(|job| (job.process.arrival, job.process.pid));
+
+    let mut ready: VecDeque<usize> = VecDeque::new();
+    let mut running: Option<usize> = None;
+    let mut expired: Option<usize> = None;
+    let mut timeline = vec![];
+    let mut time = 0;
+    while jobs
+        .iter()
+        .any(|job| job.process.state != ProcessState::Terminated)
+    {
+        for (i, job) in jobs.iter_mut().enumerate() {
+            let arrived = job.process.state == ProcessState::New && job.process.arrival <= time;
+            let unblocked = job.process.state == ProcessState::Blocked && job.remaining == 0;
+            if arrived || (unblocked && job.advance()) {
+                job.process.transition(ProcessState::Ready)?;
+                ready.push_back(i);
+            }
+        }
+        ready.extend(expired.take());
+
+        if let Some(current) = running {
+            if ready
+                .iter()
+                .any(|&i| kind.preempts(&jobs[i], &jobs[current]))
+            {
+                jobs[current].process.transition(ProcessState::Ready)?;
+                jobs[current].used = 0;
+                ready.push_back(current);
+                running = None;
+            }
+        }
+        if running.is_none() {
+            let next = ready
+                .iter()
+                .enumerate()
+                .min_by_key(|&(position, &i)| (kind.key(&jobs[i]), position))
+                .map(|(position, _)| position);
+            if let Some(i) = next.and_then(|position| ready.remove(position)) {
+                jobs[i].process.transition(ProcessState::Running)?;
+                jobs[i].first_run.get_or_insert(time);
+                running = Some(i);
+            }
+        }
+
+        timeline.push(running.map(|i| jobs[i].process.pid));
+        for &i in &ready {
+            jobs[i].waiting += 1;
+        }
+        for job in &mut jobs {
+            if job.process.state == ProcessState::Blocked {
+                job.remaining = job.remaining.saturating_sub(1);
+            }
+        }
+        time += 1;
+
+        let Some(i) = running else {
+            continue;
+        };
+        let job = &mut jobs[i];
+        job.remaining -= 1;
+        job.used += 1;
+        if job.remaining == 0 {
+            job.used = 0;
+            running = None;
+            if job.advance() {
+                job.process.transition(ProcessState::Blocked)?;
+            } else {
+                job.process.transition(ProcessState::Terminated)?;
+                job.completion = Some(time);
+            }
+        } else if kind.quantum(quantum, job.level) == Some(job.used) {
+            job.used = 0;
+            job.level = (job.level + 1).min(MLFQ_LEVELS - 1);
+            job.process.transition(ProcessState::Ready)?;
+            expired = Some(i);
+            running = None;
+        }
+    }
+
+    let mut processes: Vec<ProcessTimes> = jobs
+        .iter()
+        .map(|job| {
+            let arrival = job.process.arrival;
+            let completion = job.completion.unwrap_or(time);
+            ProcessTimes {
+                pid: job.process.pid,
+                arrival,
+                priority: job.process.priority,
+                cpu_time: job.process.cpu_time(),
+                completion,
+                waiting: job.waiting,
+                turnaround: completion - arrival,
+                response: job.first_run.unwrap_or(time) - arrival,
+            }
+        })
+        .collect();
+    processes.sort_by_key(|times| times.pid);
+    Ok(Schedule {
+        kind,
+        quantum,
+        timeline,
+        processes,
+    })
+}
+
+/// Generate `count` processes with random arrival times, priorities and bursts.
+#[must_use]
+pub fn random_workload(count: u16) -> Vec<Process> {
+    (1..=count)
+        .map(|pid| {
+            let (arrival, priority, bursts) = rng::with(|rng| {
+                let cpu_bursts = rng.gen_range(1..=3);
+                let mut bursts = vec![];
+                for i in 0..cpu_bursts {
+                    if i > 0 {
+                        bursts.push(Burst::Io(rng.gen_range(1..=4)));
+                    }
+                    bursts.push(Burst::Cpu(rng.gen_range(1..=6)));
+                }
+                (rng.gen_range(0..=8), rng.gen_range(0..=4), bursts)
+            });
+            Process::with_size(pid, PROCESS_SIZE).with_schedule(arrival, priority, bursts)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, SchedulerKind};
+    use crate::process::{Burst, Process};
+    use color_eyre::Result;
+
+    fn process(pid: u16, arrival: usize, priority: u8, bursts: &[Burst]) -> Process {
+        Process::from_image(pid, vec![]).with_schedule(arrival, priority, bursts.to_vec())
+    }
+
+    fn cpu_bound(jobs: &[(usize, usize)]) -> Vec<Process> {
+        (1..)
+            .zip(jobs)
+            .map(|(pid, &(arrival, time))| process(pid, arrival, 0, &[Burst::Cpu(time)]))
+            .collect()
+    }
+
+    fn waits(kind: SchedulerKind, quantum: usize, jobs: &[(usize, usize)]) -> Result<Vec<usize>> {
+        let schedule = run(cpu_bound(jobs), kind, quantum)?;
+        Ok(schedule.processes.iter().map(|t| t.waiting).collect())
+    }
+
+    #[test]
+    fn textbook_examples() -> Result<()> {
+        let convoy = [(0, 24), (0, 3), (0, 3)];
+        assert_eq!(waits(SchedulerKind::Fcfs, 1, &convoy)?, [0, 24, 27]);
+        assert_eq!(waits(SchedulerKind::Sjf, 1, &convoy)?, [6, 0, 3]);
+        assert_eq!(waits(SchedulerKind::RoundRobin, 4, &convoy)?, [6, 4, 7]);
+
+        let staggered = [(0, 8), (1, 4), (2, 9), (3, 5)];
+        assert_eq!(waits(SchedulerKind::Srtf, 1, &staggered)?, [9, 0, 15, 2]);
+        let schedule = run(cpu_bound(&staggered), SchedulerKind::Srtf, 1)?;
+        assert!((schedule.average_waiting() - 6.5).abs() < f64::EPSILON);
+        let segments: Vec<_> = schedule
+            .segments()
+            .into_iter()
+            .map(|(r, _)| r.end)
+            .collect();
+        assert_eq!(segments, [1, 5, 10, 17, 26]);
+        Ok(())
+    }
+
+    #[test]
+    fn priorities() -> Result<()> {
+        let processes = || {
+            vec![
+                process(1, 0, 3, &[Burst::Cpu(4)]),
+                process(2, 1, 1, &[Burst::Cpu(2)]),
+                process(3, 2, 2, &[Burst::Cpu(1)]),
+            ]
+        };
+        let schedule = run(processes(), SchedulerKind::Priority, 1)?;
+        assert_eq!(schedule.timeline, [1, 1, 1, 1, 2, 2, 3].map(Some));
+        let schedule = run(processes(), SchedulerKind::PreemptivePriority, 1)?;
+        assert_eq!(schedule.timeline, [1, 2, 2, 3, 1, 1, 1].map(Some));
+        assert_eq!(schedule.processes[0].response, 0);
+        assert_eq!(schedule.processes[2].response, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn io_blocks_and_mlfq_demotes() -> Result<()> {
+        let processes = vec![
+            process(1, 0, 0, &[Burst::Cpu(2), Burst::Io(3), Burst::Cpu(1)]),
+            process(2, 0, 0, &[Burst::Cpu(4)]),
+        ];
+        let schedule = run(processes, SchedulerKind::Fcfs, 1)?;
+        assert_eq!(schedule.timeline, [1, 1, 2, 2, 2, 2, 1].map(Some));
+        assert_eq!(schedule.processes[0].waiting, 1);
+
+        let processes = cpu_bound(&[(0, 10), (3, 1)]);
+        let schedule = run(processes, SchedulerKind::Mlfq, 2)?;
+        let segments: Vec<_> = schedule
+            .segments()
+            .into_iter()
+            .map(|(r, p)| (r.end, p))
+            .collect();
+        assert_eq!(segments, [(3, Some(1)), (4, Some(2)), (11, Some(1))]);
+
+        let idle = cpu_bound(&[(2, 1)]);
+        assert_eq!(
+            run(idle, SchedulerKind::Fcfs, 1)?.timeline,
+            [None, None, Some(1)]
+        );
+        let invalid = vec![process(1, 0, 0, &[Burst::Cpu(1), Burst::Io(1)])];
+        assert!(run(invalid, SchedulerKind::Fcfs, 1).is_err());
+        Ok(())
+    }
+}
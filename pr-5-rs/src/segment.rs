@@ -0,0 +1,683 @@
+use crate::{
+    alloc::Allocator,
+    pager::Pager,
+    process::Process,
+    ram::{AccessKind, Protection, Ram},
+};
+use color_eyre::Result;
+use std::{collections::HashMap, error::Error, fmt, rc::Rc, str::FromStr};
+
+/// Logical segment of a process. The discriminant is the segment number used in logical addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SegmentKind {
+    Code = 0,
+    Data = 1,
+    Heap = 2,
+    Stack = 3,
+}
+
+impl SegmentKind {
+    pub const ALL: [Self; 4] = [Self::Code, Self::Data, Self::Heap, Self::Stack];
+
+    /// Number of the segment in a [`SegmentTable`].
+    #[must_use]
+    pub const fn number(self) -> usize {
+        self as usize
+    }
+
+    /// Rights the segment gets unless told otherwise: code is read-only and executable,
+    /// everything else is read-write.
+    #[must_use]
+    pub const fn default_protection(self) -> Protection {
+        match self {
+            Self::Code => Protection::RX,
+            Self::Data | Self::Heap | Self::Stack => Protection::RW,
+        }
+    }
+}
+
+impl fmt::Display for SegmentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Code => "code",
+            Self::Data => "data",
+            Self::Heap => "heap",
+            Self::Stack => "stack",
+        })
+    }
+}
+
+impl FromStr for SegmentKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s.to_lowercase())
+            .ok_or(eyre::eyre!(
+                "Unknown segment '{s}', expected one of: code, data, heap, stack"
+            ))
+    }
+}
+
+/// Entry of a [`SegmentTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// Address the segment starts at: physical with [`Backing::Contiguous`] memory,
+    /// linear (virtual) with [`Backing::Paged`] memory.
+    pub base: usize,
+    /// Size of the segment. Valid offsets are `0..limit`.
+    pub limit: usize,
+    pub protection: Protection,
+}
+
+/// Segments of a single process, indexed by segment number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentTable {
+    pub(crate) segments: Vec<Option<Segment>>,
+}
+
+impl SegmentTable {
+    /// Segment number `number`, if the process has one.
+    #[must_use]
+    pub fn get(&self, number: usize) -> Option<&Segment> {
+        self.segments.get(number)?.as_ref()
+    }
+
+    /// Put `segment` into the slot of its [`SegmentKind`], returning the segment it replaced.
+    pub fn insert(&mut self, segment: Segment) -> Option<Segment> {
+        let number = segment.kind.number();
+        if self.segments.len() <= number {
+            self.segments.resize(number + 1, None);
+        }
+        self.segments[number].replace(segment)
+    }
+
+    /// Every present segment with its number.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Segment)> {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(number, segment)| Some((number, segment.as_ref()?)))
+    }
+}
+
+impl fmt::Display for SegmentTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\t\t┌────┬─────────┬────────┬────────┬───────┐")?;
+        writeln!(f, "\t\t│ №  │ сегмент │  база  │ предел │ права │")?;
+        writeln!(f, "\t\t├────┼─────────┼────────┼────────┼───────┤")?;
+        for (number, segment) in self.iter() {
+            writeln!(
+                f,
+                "\t\t│ {number:<2} │ {:<7} │ {:#06x} │ {:>6} │  {}  │",
+                segment.kind.to_string(),
+                segment.base,
+                segment.limit,
+                segment.protection
+            )?;
+        }
+        write!(f, "\t\t└────┴─────────┴────────┴────────┴───────┘")
+    }
+}
+
+/// Error raised when a logical `(segment, offset)` address can't be translated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentViolation {
+    /// The process doesn't have a segment table at all.
+    UnknownProcess { pid: u16 },
+    /// The process has no segment with this number.
+    NoSegment { pid: u16, segment: usize },
+    /// `len` bytes at `offset` don't fit below the limit of the segment.
+    Limit {
+        pid: u16,
+        segment: SegmentKind,
+        offset: usize,
+        len: usize,
+        limit: usize,
+    },
+    /// The segment doesn't allow this kind of access.
+    Protection {
+        pid: u16,
+        segment: SegmentKind,
+        kind: AccessKind,
+        allowed: Protection,
+    },
+}
+
+impl fmt::Display for SegmentViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownProcess { pid } => write!(
+                f,
+                "Segment violation: process with pid {pid} has no segment table"
+            ),
+            Self::NoSegment { pid, segment } => {
+                write!(f, "Segment violation: pid {pid} has no segment {segment}")
+            }
+            Self::Limit {
+                pid,
+                segment,
+                offset,
+                len,
+                limit,
+            } => write!(
+                f,
+                "Segment violation: pid {pid} accessed {len} byte(s) at offset {offset:#06x} \
+                 of its {segment} segment, which is only {limit} bytes long"
+            ),
+            Self::Protection {
+                pid,
+                segment,
+                kind,
+                allowed,
+            } => write!(
+                f,
+                "Segment violation: pid {pid} made a {kind:?} access to its {segment} segment, \
+                 which is {allowed}"
+            ),
+        }
+    }
+}
+
+impl Error for SegmentViolation {}
+
+/// Sizes of the segments a process gets besides its code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentLayout {
+    pub data: usize,
+    pub heap: usize,
+    pub stack: usize,
+}
+
+impl Default for SegmentLayout {
+    fn default() -> Self {
+        Self {
+            data: 32,
+            heap: 32,
+            stack: 32,
+        }
+    }
+}
+
+impl SegmentLayout {
+    /// Size of each segment of `process`, in segment number order. Empty segments are left out.
+    fn sizes(&self, process: &Process) -> impl Iterator<Item = (SegmentKind, usize)> {
+        SegmentKind::ALL
+            .into_iter()
+            .zip([process.instructions.len(), self.data, self.heap, self.stack])
+            .filter(|&(_, size)| size > 0)
+    }
+}
+
+/// Where segments live.
+#[derive(Debug)]
+pub enum Backing {
+    /// Every segment is a contiguous region of [`Ram`] handed out by an [`Allocator`].
+    Contiguous(Box<dyn Allocator>),
+    /// Segments are laid out one after another, page-aligned, in the linear address space of
+    /// the process, which is paged in and out by a [`Pager`].
+    Paged(Box<Pager>),
+}
+
+/// Memory manager that gives every process a [`SegmentTable`] and translates
+/// logical `(segment, offset)` addresses.
+#[derive(Debug)]
+pub struct SegmentedMemory {
+    ram: Rc<Ram>,
+    pub(crate) backing: Backing,
+    pub(crate) tables: HashMap<u16, SegmentTable>,
+}
+
+#[allow(dead_code)]
+impl SegmentedMemory {
+    /// Place segments in `ram` with `allocator` (pure segmentation).
+    /// The allocator's capacity must not exceed the RAM size.
+    pub fn contiguous(ram: &Rc<Ram>, allocator: Box<dyn Allocator>) -> Result<Self> {
+        eyre::ensure!(
+            allocator.capacity() <= ram.size(),
+            "Allocator manages {} bytes, but RAM only has {}",
+            allocator.capacity(),
+            ram.size()
+        );
+        Ok(Self {
+            ram: Rc::clone(ram),
+            backing: Backing::Contiguous(allocator),
+            tables: HashMap::new(),
+        })
+    }
+
+    /// Back segments with pages managed by `pager` (segmented paging).
+    /// `ram` must be the [`Ram`] the pager was created with.
+    #[must_use]
+    pub fn paged(ram: &Rc<Ram>, pager: Pager) -> Self {
+        Self {
+            ram: Rc::clone(ram),
+            backing: Backing::Paged(Box::new(pager)),
+            tables: HashMap::new(),
+        }
+    }
+
+    /// Segment table of process `pid`.
+    #[must_use]
+    pub fn table(&self, pid: u16) -> Option<&SegmentTable> {
+        self.tables.get(&pid)
+    }
+
+    /// Create the segments of a [`Process`] and copy its instructions into the code segment.
+    pub fn load_process(&mut self, process: &Process, layout: &SegmentLayout) -> Result<()> {
+        eyre::ensure!(
+            !self.tables.contains_key(&process.pid),
+            "Process with pid {} is already loaded",
+            process.pid
+        );
+        let mut table = SegmentTable::default();
+        match &mut self.backing {
+            Backing::Contiguous(allocator) => {
+                if let Err(e) =
+                    Self::place(&self.ram, allocator.as_mut(), &mut table, process, layout)
+                {
+                    // Release whatever got allocated, so a failed load leaks nothing.
+                    for (_, segment) in table.iter() {
+                        let range = segment.base..segment.base + segment.limit;
+                        self.ram.protect(range.clone(), Protection::RWX)?;
+                        self.ram.zero(range.start, range.len())?;
+                        allocator.free(segment.base)?;
+                    }
+                    return Err(e);
+                }
+            }
+            Backing::Paged(pager) => {
+                let page_size = self.ram.config.page_size;
+                let mut base = 0;
+                for (kind, size) in layout.sizes(process) {
+                    table.insert(Segment {
+                        kind,
+                        base,
+                        limit: size,
+                        protection: kind.default_protection(),
+                    });
+                    base += size.next_multiple_of(page_size);
+                }
+                // The linear image: code at the start, every other segment zeroed.
+                let mut image = process.instructions.clone();
+                image.resize(base, 0);
+                pager.register_process(&Process::from_image(process.pid, image))?;
+            }
+        }
+        self.tables.insert(process.pid, table);
+        Ok(())
+    }
+
+    /// Allocate the segments of a [`Process`] in [`Ram`], adding each one to `table` as soon as
+    /// it's allocated, then zero them, copy in the code and protect them.
+    fn place(
+        ram: &Ram,
+        allocator: &mut dyn Allocator,
+        table: &mut SegmentTable,
+        process: &Process,
+        layout: &SegmentLayout,
+    ) -> Result<()> {
+        for (kind, size) in layout.sizes(process) {
+            let allocation = allocator.allocate(size)?;
+            table.insert(Segment {
+                kind,
+                base: allocation.addr,
+                limit: size,
+                protection: kind.default_protection(),
+            });
+        }
+        for (_, segment) in table.iter() {
+            let range = segment.base..segment.base + segment.limit;
+            ram.zero(range.start, range.len())?;
+            if segment.kind == SegmentKind::Code {
+                ram.write(segment.base, &process.instructions)?;
+            }
+            ram.protect(range, segment.protection)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the code of a [`Process`] back out, then release all of its segments.
+    pub fn unload_process(&mut self, pid: u16) -> Result<Process> {
+        let code = self
+            .table(pid)
+            .ok_or(eyre::eyre!(
+                "Cannot unload process: process with pid {pid} isn't loaded"
+            ))?
+            .get(SegmentKind::Code.number())
+            .copied();
+        let instructions = match code {
+            Some(code) => self.read(pid, SegmentKind::Code, 0, code.limit)?,
+            None => vec![],
+        };
+        let table = self.tables.remove(&pid).unwrap_or_default();
+        match &mut self.backing {
+            Backing::Contiguous(allocator) => {
+                for (_, segment) in table.iter() {
+                    let range = segment.base..segment.base + segment.limit;
+                    self.ram.protect(range.clone(), Protection::RWX)?;
+                    self.ram.zero(range.start, range.len())?;
+                    allocator.free(segment.base)?;
+                }
+            }
+            Backing::Paged(pager) => pager.unload_process(pid)?,
+        }
+        Ok(Process::from_image(pid, instructions))
+    }
+
+    /// Check that process `pid` may make a `kind` access to `len` bytes at `offset` of `segment`,
+    /// returning the address the access starts at: physical with [`Backing::Contiguous`] memory,
+    /// linear with [`Backing::Paged`] memory.
+    pub fn check(
+        &self,
+        pid: u16,
+        segment: usize,
+        offset: usize,
+        len: usize,
+        kind: AccessKind,
+    ) -> Result<usize, SegmentViolation> {
+        let entry = self
+            .tables
+            .get(&pid)
+            .ok_or(SegmentViolation::UnknownProcess { pid })?
+            .get(segment)
+            .ok_or(SegmentViolation::NoSegment { pid, segment })?;
+        if offset.checked_add(len).is_none_or(|end| end > entry.limit) {
+            return Err(SegmentViolation::Limit {
+                pid,
+                segment: entry.kind,
+                offset,
+                len,
+                limit: entry.limit,
+            });
+        }
+        if !entry.protection.contains(kind.required()) {
+            return Err(SegmentViolation::Protection {
+                pid,
+                segment: entry.kind,
+                kind,
+                allowed: entry.protection,
+            });
+        }
+        Ok(entry.base + offset)
+    }
+
+    /// Translate the logical address `(segment, offset)` of process `pid` into a physical one,
+    /// paging the page it's in into [`Ram`] if the segments are paged.
+    pub fn translate(
+        &mut self,
+        pid: u16,
+        segment: usize,
+        offset: usize,
+        kind: AccessKind,
+    ) -> Result<usize> {
+        let addr = self.check(pid, segment, offset, 1, kind)?;
+        self.resolve(pid, addr, kind)
+    }
+
+    /// Read `len` bytes at `offset` of `segment`.
+    pub fn read(
+        &mut self,
+        pid: u16,
+        segment: SegmentKind,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        self.chunks(
+            pid,
+            segment,
+            offset,
+            len,
+            AccessKind::Read,
+            |ram, paddr, range| {
+                bytes.extend(ram.read(paddr, range.len())?);
+                Ok(())
+            },
+        )?;
+        Ok(bytes)
+    }
+
+    /// Fetch `len` bytes of instructions at `offset` of `segment`.
+    pub fn fetch(
+        &mut self,
+        pid: u16,
+        segment: SegmentKind,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        self.chunks(
+            pid,
+            segment,
+            offset,
+            len,
+            AccessKind::Execute,
+            |ram, paddr, range| {
+                bytes.extend(ram.fetch(paddr, range.len())?);
+                Ok(())
+            },
+        )?;
+        Ok(bytes)
+    }
+
+    /// Write `data` at `offset` of `segment`.
+    pub fn write(
+        &mut self,
+        pid: u16,
+        segment: SegmentKind,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        self.chunks(
+            pid,
+            segment,
+            offset,
+            data.len(),
+            AccessKind::Write,
+            |ram, paddr, range| Ok(ram.write(paddr, &data[range])?),
+        )
+    }
+
+    /// Check a `kind` access to `len` bytes at `offset` of `segment`, then call `f` with the
+    /// physical address of every piece of it that's contiguous in [`Ram`] and the range of the
+    /// access that piece covers.
+    fn chunks(
+        &mut self,
+        pid: u16,
+        segment: SegmentKind,
+        offset: usize,
+        len: usize,
+        kind: AccessKind,
+        mut f: impl FnMut(&Ram, usize, std::ops::Range<usize>) -> Result<()>,
+    ) -> Result<()> {
+        let start = self.check(pid, segment.number(), offset, len, kind)?;
+        let mut done = 0;
+        while done < len {
+            let addr = start + done;
+            let size = match self.backing {
+                Backing::Contiguous(_) => len - done,
+                Backing::Paged(_) => {
+                    let page_size = self.ram.config.page_size;
+                    (page_size - addr % page_size).min(len - done)
+                }
+            };
+            let paddr = self.resolve(pid, addr, kind)?;
+            f(&self.ram, paddr, done..done + size)?;
+            done += size;
+        }
+        Ok(())
+    }
+
+    /// Turn an address returned by [`check`](Self::check) into a physical one for a `kind` access,
+    /// so that paged writes mark their page dirty.
+    fn resolve(&mut self, pid: u16, addr: usize, kind: AccessKind) -> Result<usize> {
+        match &mut self.backing {
+            Backing::Contiguous(_) => Ok(addr),
+            Backing::Paged(pager) => Ok(pager.access_as(pid, addr, kind)?.paddr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backing, SegmentKind, SegmentLayout, SegmentViolation, SegmentedMemory};
+    use crate::{
+        alloc::{Fit, FreeListAllocator},
+        config::MemoryConfig,
+        pager::Pager,
+        process::Process,
+        ram::{AccessKind, Protection, Ram},
+        replacement::Lru,
+    };
+    use color_eyre::Result;
+    use std::rc::Rc;
+
+    fn violation(result: Result<impl std::fmt::Debug>) -> SegmentViolation {
+        result
+            .expect_err("access should fail")
+            .downcast()
+            .expect("error should be a segment violation")
+    }
+
+    #[test]
+    fn contiguous_translation() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let allocator = Box::new(FreeListAllocator::new(Fit::First, ram.size()));
+        let mut memory = SegmentedMemory::contiguous(&ram, allocator)?;
+        let process = Process::with_size(7, 24);
+        memory.load_process(&process, &SegmentLayout::default())?;
+
+        let table = memory.table(7).expect("process is loaded").clone();
+        let data = *table.get(SegmentKind::Data.number()).expect("has data");
+        assert_eq!(data.limit, 32);
+        assert_eq!(
+            memory.translate(7, SegmentKind::Data.number(), 5, AccessKind::Write)?,
+            data.base + 5
+        );
+        memory.write(7, SegmentKind::Stack, 30, &[1, 2])?;
+        assert_eq!(memory.read(7, SegmentKind::Stack, 30, 2)?, [1, 2]);
+        assert_eq!(
+            memory.fetch(7, SegmentKind::Code, 0, 24)?,
+            process.instructions
+        );
+        // The code segment is also protected in RAM itself.
+        assert!(ram
+            .write(table.get(0).expect("has code").base, &[0])
+            .is_err());
+
+        assert_eq!(memory.unload_process(7)?.instructions, process.instructions);
+        assert!(ram.bytes.borrow().iter().all(|&byte| byte == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_load_frees_segments() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let allocator = Box::new(FreeListAllocator::new(Fit::First, ram.size()));
+        let mut memory = SegmentedMemory::contiguous(&ram, allocator)?;
+        let process = Process::with_size(7, 24);
+        // The code segment lands here, so zeroing it fails after every segment is allocated.
+        ram.protect(0..8, Protection::READ)?;
+        assert!(memory
+            .load_process(&process, &SegmentLayout::default())
+            .is_err());
+        assert!(memory.table(7).is_none());
+
+        memory.load_process(&process, &SegmentLayout::default())?;
+        let table = memory.table(7).expect("process is loaded");
+        assert_eq!(
+            table.get(SegmentKind::Code.number()).map(|s| s.base),
+            Some(0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn violations() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let allocator = Box::new(FreeListAllocator::new(Fit::First, ram.size()));
+        let mut memory = SegmentedMemory::contiguous(&ram, allocator)?;
+        let layout = SegmentLayout {
+            heap: 0,
+            ..SegmentLayout::default()
+        };
+        memory.load_process(&Process::with_size(1, 16), &layout)?;
+
+        assert_eq!(
+            violation(memory.read(2, SegmentKind::Data, 0, 1)),
+            SegmentViolation::UnknownProcess { pid: 2 }
+        );
+        assert_eq!(
+            violation(memory.read(1, SegmentKind::Heap, 0, 1)),
+            SegmentViolation::NoSegment { pid: 1, segment: 2 }
+        );
+        assert_eq!(
+            violation(memory.write(1, SegmentKind::Stack, 31, &[0, 0])),
+            SegmentViolation::Limit {
+                pid: 1,
+                segment: SegmentKind::Stack,
+                offset: 31,
+                len: 2,
+                limit: 32
+            }
+        );
+        assert!(matches!(
+            violation(memory.write(1, SegmentKind::Code, 0, &[0])),
+            SegmentViolation::Protection {
+                segment: SegmentKind::Code,
+                kind: AccessKind::Write,
+                ..
+            }
+        ));
+        assert!(matches!(
+            violation(memory.translate(1, 1, 0, AccessKind::Execute)),
+            SegmentViolation::Protection {
+                segment: SegmentKind::Data,
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn segmented_paging() -> Result<()> {
+        let ram = Rc::new(Ram::new(MemoryConfig::new(64, 16, 16)?));
+        let pager = Pager::with_frames(&ram, 2, Box::new(Lru::default()));
+        let mut memory = SegmentedMemory::paged(&ram, pager);
+        let process = Process::with_size(3, 20);
+        let layout = SegmentLayout {
+            data: 8,
+            heap: 0,
+            stack: 16,
+        };
+        memory.load_process(&process, &layout)?;
+
+        // Code takes pages 0 and 1, data page 2 and the stack page 3 of the linear space.
+        let stack = *memory.table(3).and_then(|t| t.get(3)).expect("has stack");
+        assert_eq!(stack.base, 48);
+        memory.write(3, SegmentKind::Stack, 12, &[9; 4])?;
+        memory.write(3, SegmentKind::Data, 0, &[5; 8])?;
+        let Backing::Paged(pager) = &memory.backing else {
+            unreachable!("memory is paged")
+        };
+        assert_eq!(pager.dirty, [true, true]);
+        // Only two frames: reading the code back evicts both pages written above.
+        assert_eq!(
+            memory.read(3, SegmentKind::Code, 0, 20)?,
+            process.instructions
+        );
+        assert_eq!(memory.read(3, SegmentKind::Stack, 12, 4)?, [9; 4]);
+        assert_eq!(memory.read(3, SegmentKind::Data, 0, 8)?, [5; 8]);
+        assert!(matches!(
+            violation(memory.read(3, SegmentKind::Data, 8, 1)),
+            SegmentViolation::Limit { limit: 8, .. }
+        ));
+
+        assert_eq!(memory.unload_process(3)?.instructions, process.instructions);
+        assert!(memory.table(3).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+//! Binary snapshots of [`Ram`] and the [`Page`]s laid over it.
+//!
+//! All integers are little-endian. A snapshot file is laid out as follows:
+//!
+//! | Field          | Type            | Notes                                         |
+//! |----------------|-----------------|-----------------------------------------------|
+//! | magic          | `[u8; 4]`       | Always `MRAM`                                 |
+//! | version        | `u16`           | [`SNAPSHOT_VERSION`]                          |
+//! | reserved       | `u16`           | Zero                                          |
+//! | RAM size       | `u32`           | See [`MemoryConfig`]                          |
+//! | page size      | `u32`           | Power of two that divides the RAM size        |
+//! | process size   | `u32`           | Size of a slot of a page                      |
+//! | page count     | `u32`           | Number of page records that follow            |
+//! | page records   | see below       |                                               |
+//! | RAM bytes      | `[u8; RAM size]`|                                               |
+//! | checksum       | `u32`           | CRC-32 (IEEE) of every preceding byte         |
+//!
+//! Each page record is:
+//!
+//! | Field          | Type            | Notes                                         |
+//! |----------------|-----------------|-----------------------------------------------|
+//! | frame          | `u32`           | Index of the page in RAM                      |
+//! | id             | `u32`           | [`Page::id`]                                  |
+//! | slot count     | `u32`           |                                               |
+//! | slot bitmap    | `[u8]`          | `ceil(slot count / 8)` bytes, LSB first       |
+//! | process count  | `u32`           | Number of [`Page::map`] entries that follow   |
+//! | processes      | `(u16, u32, u32)` | PID, start and end offset within the page, sorted by PID |
+
+use crate::{bitmap::Bitmap, config::MemoryConfig, page::Page, ram::Ram};
+use color_eyre::Result;
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"MRAM";
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// Write a snapshot of `ram` and the `pages` over it.
+pub fn save(ram: &Ram, pages: &[Page], mut writer: impl Write) -> Result<()> {
+    let config = ram.config;
+    let mut out = Vec::with_capacity(config.ram_size + 64);
+    out.extend_from_slice(&SNAPSHOT_MAGIC);
+    out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    put_u32(&mut out, config.ram_size)?;
+    put_u32(&mut out, config.page_size)?;
+    put_u32(&mut out, config.process_size)?;
+    put_u32(&mut out, pages.len())?;
+    for page in pages {
+        put_u32(&mut out, page.range.start / config.page_size)?;
+        put_u32(&mut out, page.id)?;
+        put_u32(&mut out, page.slots.len())?;
+        out.extend_from_slice(&page.slots.to_bytes());
+        put_u32(&mut out, page.map.len())?;
+        let mut entries: Vec<_> = page.map.iter().collect();
+        entries.sort_unstable_by_key(|(&pid, _)| pid);
+        for (pid, range) in entries {
+            out.extend_from_slice(&pid.to_le_bytes());
+            put_u32(&mut out, range.start)?;
+            put_u32(&mut out, range.end)?;
+        }
+    }
+    out.extend_from_slice(&ram.bytes.try_borrow()?[..]);
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+/// Read a snapshot written by [`save`], rebuilding the [`Ram`] and its [`Page`]s
+/// with the geometry they were saved with.
+///
+/// Pages are checked to be consistent, even when the checksum matches: every frame may have only
+/// one page, and the taken slots of a page must be exactly the ones its processes occupy.
+pub fn load(mut reader: impl Read) -> Result<(Rc<Ram>, Vec<Page>)> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    eyre::ensure!(data.len() >= 4, "Snapshot is truncated");
+    let (body, checksum) = data.split_at(data.len() - 4);
+    let expected = u32::from_le_bytes(checksum.try_into()?);
+    let actual = crc32(body);
+    eyre::ensure!(
+        expected == actual,
+        "Snapshot checksum mismatch: stored {expected:#010x}, computed {actual:#010x}"
+    );
+
+    let mut cursor = Cursor {
+        data: body,
+        position: 0,
+    };
+    eyre::ensure!(
+        cursor.take(4)? == SNAPSHOT_MAGIC,
+        "Not a RAM snapshot: bad magic"
+    );
+    let version = u16::from_le_bytes(cursor.take(2)?.try_into()?);
+    eyre::ensure!(
+        version == SNAPSHOT_VERSION,
+        "Unsupported snapshot version {version}, expected {SNAPSHOT_VERSION}"
+    );
+    cursor.take(2)?;
+    let config = MemoryConfig::new(cursor.u32()?, cursor.u32()?, cursor.u32()?)
+        .map_err(|e| eyre::eyre!("Snapshot has invalid geometry: {e}"))?;
+
+    let page_count = cursor.u32()?;
+    // Every page record takes at least its fixed fields and slot bitmap, so a header claiming
+    // more than the file holds is rejected before anything that big is allocated.
+    let needed = page_count
+        .saturating_mul(16 + config.slot_count().div_ceil(8))
+        .saturating_add(config.ram_size);
+    eyre::ensure!(
+        needed <= cursor.remaining(),
+        "Snapshot is truncated: {page_count} page(s) and {} bytes of RAM need at least {needed} more bytes, got {}",
+        config.ram_size,
+        cursor.remaining()
+    );
+    let ram = Rc::new(Ram::new(config));
+    let mut pages = Vec::with_capacity(page_count.min(config.frame_count()));
+    let mut frames = HashSet::new();
+    for _ in 0..page_count {
+        let frame = cursor.u32()?;
+        eyre::ensure!(
+            frame < config.frame_count(),
+            "Page record refers to frame {frame}, which is outside of RAM"
+        );
+        eyre::ensure!(
+            frames.insert(frame),
+            "Frame {frame} has more than one page record"
+        );
+        let mut page = Page::new(frame, &ram);
+        page.id = cursor.u32()?;
+        let slot_count = cursor.u32()?;
+        eyre::ensure!(
+            slot_count == config.slot_count(),
+            "Page in frame {frame} has {slot_count} slots, expected {}",
+            config.slot_count()
+        );
+        page.slots = Bitmap::from_bytes(slot_count, cursor.take(slot_count.div_ceil(8))?);
+        for _ in 0..cursor.u32()? {
+            let pid = u16::from_le_bytes(cursor.take(2)?.try_into()?);
+            let (start, end) = (cursor.u32()?, cursor.u32()?);
+            eyre::ensure!(
+                start <= end && end <= config.page_size,
+                "Process {pid} occupies {start}..{end}, which is outside of its page"
+            );
+            page.map.insert(pid, start..end);
+        }
+        check_slots(&page, config)
+            .map_err(|e| eyre::eyre!("Page in frame {frame} is inconsistent: {e}"))?;
+        page.loaded_processes = page.map.len();
+        pages.push(page);
+    }
+    ram.bytes.try_borrow_mut()?[..].copy_from_slice(cursor.take(config.ram_size)?);
+    eyre::ensure!(
+        cursor.position == body.len(),
+        "Snapshot has {} trailing bytes",
+        body.len() - cursor.position
+    );
+    Ok((ram, pages))
+}
+
+/// Check that the processes of `page` start on slot boundaries, don't share slots,
+/// and take up exactly the slots set in its bitmap, as [`Page::load_process`] would leave them.
+fn check_slots(page: &Page, config: MemoryConfig) -> Result<()> {
+    let mut expected = Bitmap::new(config.slot_count());
+    for (pid, range) in &page.map {
+        eyre::ensure!(
+            range.start % config.process_size == 0,
+            "process {pid} doesn't start on a slot boundary"
+        );
+        let first_slot = range.start / config.process_size;
+        let slot_count = range.len().div_ceil(config.process_size).max(1);
+        for slot in first_slot..first_slot + slot_count {
+            eyre::ensure!(
+                !expected.get(slot),
+                "slot {slot} of process {pid} is outside of the page or taken by another process"
+            );
+            expected.set(slot, true);
+        }
+    }
+    eyre::ensure!(
+        expected == page.slots,
+        "taken slots don't match the processes in the page"
+    );
+    Ok(())
+}
+
+fn put_u32(out: &mut Vec<u8>, value: usize) -> Result<()> {
+    out.extend_from_slice(&u32::try_from(value)?.to_le_bytes());
+    Ok(())
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or(eyre::eyre!(
+                "Snapshot is truncated at byte {}",
+                self.position
+            ))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?) as usize)
+    }
+}
+
+/// CRC-32 with the IEEE 802.3 polynomial, as used by zip and PNG.
+#[must_use]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(u32::MAX, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, load, save, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+    use crate::{bitmap::Bitmap, config::MemoryConfig, page::Page, process::Process, ram::Ram};
+    use color_eyre::Result;
+    use std::rc::Rc;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn save_load_round_trip() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut pages = vec![Page::new(0, &ram), Page::new(1, &ram)];
+        pages[1].id = 2;
+        pages[1].load_process(&Process::with_pid(7))?;
+        pages[1].load_process(&Process::with_size(9, 70))?;
+        pages[1].unload_process(7)?;
+
+        let mut file = vec![];
+        save(&ram, &pages, &mut file)?;
+        let (restored_ram, restored) = load(&file[..])?;
+        assert_eq!(*restored_ram.bytes.borrow(), *ram.bytes.borrow());
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[1].id, 2);
+        assert_eq!(restored[1].map, pages[1].map);
+        assert_eq!(restored[1].slots, pages[1].slots);
+        assert_eq!(restored[1].loaded_processes, 1);
+
+        let mut resumed = restored;
+        assert_eq!(
+            resumed[1].unload_process(9)?.instructions,
+            pages[1].unload_process(9)?.instructions
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn geometry_is_restored() -> Result<()> {
+        let config = MemoryConfig::new(4 * 512, 512, 64)?;
+        let ram = Rc::new(Ram::new(config));
+        let mut page = Page::new(3, &ram);
+        page.load_process(&Process::with_size(5, 100))?;
+
+        let mut file = vec![];
+        save(&ram, &[page], &mut file)?;
+        let (restored_ram, restored) = load(&file[..])?;
+        assert_eq!(restored_ram.config, config);
+        assert_eq!(restored[0].range, 3 * 512..4 * 512);
+        assert_eq!(restored[0].slots.count_ones(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn inconsistent_pages_are_rejected() -> Result<()> {
+        // `save` writes whatever it's given, so these snapshots have valid checksums.
+        let error = |pages: &[Page], ram: &Ram| -> Result<String> {
+            let mut file = vec![];
+            save(ram, pages, &mut file)?;
+            Ok(load(&file[..]).unwrap_err().to_string())
+        };
+        let ram = Rc::new(Ram::default());
+        let duplicate = [Page::new(1, &ram), Page::new(1, &ram)];
+        assert!(error(&duplicate, &ram)?.contains("more than one page record"));
+
+        let mut page = Page::new(0, &ram);
+        page.slots = Bitmap::new(3);
+        assert!(error(&[page], &ram)?.contains("slots, expected"));
+
+        let mut page = Page::new(0, &ram);
+        page.load_process(&Process::with_pid(7))?;
+        page.slots.set(0, false);
+        assert!(error(&[page], &ram)?.contains("don't match"));
+
+        let mut page = Page::new(0, &ram);
+        page.load_process(&Process::with_pid(7))?;
+        page.map.insert(8, 1..2);
+        assert!(error(&[page], &ram)?.contains("slot boundary"));
+
+        let mut page = Page::new(0, &ram);
+        page.load_process(&Process::with_pid(7))?;
+        page.map.insert(8, page.map[&7].clone());
+        assert!(error(&[page], &ram)?.contains("taken by another process"));
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_body_is_rejected_before_allocating() {
+        let mut file = SNAPSHOT_MAGIC.to_vec();
+        file.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        file.extend_from_slice(&0u16.to_le_bytes());
+        for field in [1u32 << 31, 256, 32, 0] {
+            file.extend_from_slice(&field.to_le_bytes());
+        }
+        let checksum = crc32(&file);
+        file.extend_from_slice(&checksum.to_le_bytes());
+        assert!(load(&file[..])
+            .unwrap_err()
+            .to_string()
+            .starts_with("Snapshot is truncated"));
+    }
+
+    #[test]
+    fn corruption_is_detected() -> Result<()> {
+        let ram = Rc::new(Ram::default());
+        let mut file = vec![];
+        save(&ram, &[Page::new(0, &ram)], &mut file)?;
+        file[20] ^= 1;
+        assert!(load(&file[..])
+            .unwrap_err()
+            .to_string()
+            .contains("checksum"));
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use crate::replacement::{PageKey, PolicyKind, ReplacementPolicy};
+use color_eyre::Result;
+use std::{fmt, str::FromStr};
+
+/// Time to look up a translation in the [`Tlb`], in nanoseconds.
+pub const TLB_LATENCY_NS: f64 = 20.0;
+/// Time of a single access to [`Ram`](crate::ram::Ram), in nanoseconds.
+pub const MEMORY_LATENCY_NS: f64 = 100.0;
+
+/// How many entries of the [`Tlb`] a page may be cached in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Associativity {
+    /// Any page may go into any entry.
+    #[default]
+    Full,
+    /// Entries are grouped into sets of this many ways, and a page may only go into the set
+    /// picked by its page number.
+    Ways(usize),
+}
+
+impl FromStr for Associativity {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(Self::Full),
+            ways => match ways.parse() {
+                Ok(0) | Err(_) => Err(eyre::eyre!(
+                    "Invalid associativity '{s}', expected 'full' or a positive number of ways"
+                )),
+                Ok(ways) => Ok(Self::Ways(ways)),
+            },
+        }
+    }
+}
+
+/// Shape of a [`Tlb`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlbConfig {
+    /// Total number of entries.
+    pub entries: usize,
+    pub associativity: Associativity,
+    /// Which entry of a full set gets replaced.
+    pub policy: PolicyKind,
+    /// Tag entries with the PID (ASID) of their process. Without tags, the whole TLB has to be
+    /// flushed on every context switch.
+    pub tagged: bool,
+}
+
+impl TlbConfig {
+    /// A fully associative, ASID-tagged TLB of `entries` entries with LRU replacement.
+    #[must_use]
+    pub const fn new(entries: usize) -> Self {
+        Self {
+            entries,
+            associativity: Associativity::Full,
+            policy: PolicyKind::Lru,
+            tagged: true,
+        }
+    }
+
+    /// Number of ways in a set.
+    #[must_use]
+    pub const fn ways(&self) -> usize {
+        match self.associativity {
+            Associativity::Full => self.entries,
+            Associativity::Ways(ways) => ways,
+        }
+    }
+
+    /// Number of sets.
+    #[must_use]
+    pub const fn set_count(&self) -> usize {
+        self.entries / self.ways()
+    }
+}
+
+/// Counters collected by the [`Tlb`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TlbStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// Context switches that flushed the whole TLB.
+    pub flushes: usize,
+}
+
+impl TlbStats {
+    /// Share of lookups that hit, from 0 to 1.
+    #[must_use]
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+
+    /// Effective memory access time in nanoseconds: a hit costs a TLB lookup and a memory access,
+    /// a miss costs another memory access to walk the page table.
+    #[must_use]
+    pub fn effective_access_time(&self, tlb_ns: f64, memory_ns: f64) -> f64 {
+        let hit = self.hit_ratio();
+        hit * (tlb_ns + memory_ns) + (1.0 - hit) * (tlb_ns + 2.0 * memory_ns)
+    }
+}
+
+impl fmt::Display for TlbStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TLB: попаданий: {}, промахов: {}, сбросов: {}, доля попаданий: {:.1}%, \
+             эффективное время доступа: {:.1} нс",
+            self.hits,
+            self.misses,
+            self.flushes,
+            self.hit_ratio() * 100.0,
+            self.effective_access_time(TLB_LATENCY_NS, MEMORY_LATENCY_NS)
+        )
+    }
+}
+
+/// Cached translation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TlbEntry {
+    key: PageKey,
+    frame: usize,
+}
+
+/// Translation lookaside buffer. Caches page-to-frame translations of the [`Mmu`](crate::page_table::Mmu),
+/// each set replacing its entries with its own [`ReplacementPolicy`].
+#[derive(Debug)]
+pub struct Tlb {
+    config: TlbConfig,
+    sets: Vec<Vec<TlbEntry>>,
+    policies: Vec<Box<dyn ReplacementPolicy>>,
+    /// PID of the running process.
+    current: Option<u16>,
+    pub(crate) stats: TlbStats,
+}
+
+#[allow(dead_code)]
+impl Tlb {
+    /// Create an empty TLB. `future` is the reference string, only used by
+    /// [`Optimal`](crate::replacement::Optimal) replacement.
+    pub fn new(config: TlbConfig, future: &[PageKey]) -> Result<Self> {
+        eyre::ensure!(config.entries > 0, "TLB must have at least one entry");
+        eyre::ensure!(
+            config.entries.is_multiple_of(config.ways()),
+            "TLB of {} entries can't be split into {}-way sets",
+            config.entries,
+            config.ways()
+        );
+        let set_count = config.set_count();
+        let policies = (0..set_count)
+            .map(|set| {
+                let future = future
+                    .iter()
+                    .filter(|&&(_, vpn)| vpn % set_count == set)
+                    .copied()
+                    .collect();
+                config.policy.build(future)
+            })
+            .collect();
+        Ok(Self {
+            config,
+            sets: vec![vec![]; set_count],
+            policies,
+            current: None,
+            stats: TlbStats::default(),
+        })
+    }
+
+    #[must_use]
+    pub const fn config(&self) -> &TlbConfig {
+        &self.config
+    }
+
+    /// Make `pid` the running process, flushing the TLB if entries aren't tagged with a PID.
+    pub fn switch_to(&mut self, pid: u16) {
+        if self.current.is_some_and(|current| current != pid) && !self.config.tagged {
+            self.flush();
+            self.stats.flushes += 1;
+        }
+        self.current = Some(pid);
+    }
+
+    /// Look up the frame the page `vpn` of process `pid` is in.
+    pub fn lookup(&mut self, pid: u16, vpn: usize) -> Option<usize> {
+        let set = self.set_of(vpn);
+        let key = (pid, vpn);
+        match self.sets[set].iter().find(|entry| entry.key == key) {
+            Some(entry) => {
+                self.stats.hits += 1;
+                let frame = entry.frame;
+                self.policies[set].access(key);
+                Some(frame)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache the translation of page `vpn` of process `pid` to `frame`,
+    /// returning the page whose entry was replaced to make room.
+    pub fn insert(&mut self, pid: u16, vpn: usize, frame: usize) -> Option<PageKey> {
+        let set = self.set_of(vpn);
+        let key = (pid, vpn);
+        self.invalidate(pid, vpn);
+        let replaced = if self.sets[set].len() == self.config.ways() {
+            let victim = self.policies[set].victim()?;
+            self.invalidate(victim.0, victim.1);
+            Some(victim)
+        } else {
+            None
+        };
+        self.sets[set].push(TlbEntry { key, frame });
+        self.policies[set].insert(key);
+        self.policies[set].access(key);
+        replaced
+    }
+
+    /// Drop the entry of page `vpn` of process `pid`, e.g. because the page left its frame.
+    pub fn invalidate(&mut self, pid: u16, vpn: usize) {
+        let set = self.set_of(vpn);
+        let key = (pid, vpn);
+        if let Some(index) = self.sets[set].iter().position(|entry| entry.key == key) {
+            self.sets[set].remove(index);
+            self.policies[set].remove(key);
+        }
+    }
+
+    /// Drop every entry of process `pid`.
+    pub fn invalidate_process(&mut self, pid: u16) {
+        let keys: Vec<_> = self
+            .sets
+            .iter()
+            .flatten()
+            .filter(|entry| entry.key.0 == pid)
+            .map(|entry| entry.key)
+            .collect();
+        for (pid, vpn) in keys {
+            self.invalidate(pid, vpn);
+        }
+    }
+
+    /// Drop every entry.
+    pub fn flush(&mut self) {
+        for (set, policy) in self.sets.iter_mut().zip(&mut self.policies) {
+            for entry in set.drain(..) {
+                policy.remove(entry.key);
+            }
+        }
+    }
+
+    const fn set_of(&self, vpn: usize) -> usize {
+        vpn % self.sets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Associativity, Tlb, TlbConfig};
+    use crate::replacement::PolicyKind;
+    use color_eyre::Result;
+
+    #[test]
+    fn set_associative_lru() -> Result<()> {
+        let config = TlbConfig {
+            associativity: Associativity::Ways(2),
+            ..TlbConfig::new(4)
+        };
+        let mut tlb = Tlb::new(config, &[])?;
+        assert_eq!(config.set_count(), 2);
+        tlb.insert(1, 0, 10);
+        tlb.insert(1, 2, 12);
+        tlb.insert(1, 1, 11);
+        assert_eq!(tlb.lookup(1, 0), Some(10));
+        // Pages 0, 2 and 4 share a set, and 2 is the least recently used of them.
+        assert_eq!(tlb.insert(1, 4, 14), Some((1, 2)));
+        assert_eq!(tlb.lookup(1, 2), None);
+        assert_eq!(tlb.lookup(1, 1), Some(11));
+        assert_eq!((tlb.stats.hits, tlb.stats.misses), (2, 1));
+        let uneven = TlbConfig {
+            associativity: Associativity::Ways(4),
+            ..TlbConfig::new(6)
+        };
+        assert!(Tlb::new(uneven, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn context_switches() -> Result<()> {
+        let mut tagged = Tlb::new(TlbConfig::new(4), &[])?;
+        let mut untagged = Tlb::new(
+            TlbConfig {
+                tagged: false,
+                policy: PolicyKind::Fifo,
+                ..TlbConfig::new(4)
+            },
+            &[],
+        )?;
+        for tlb in [&mut tagged, &mut untagged] {
+            tlb.switch_to(1);
+            tlb.insert(1, 0, 0);
+            tlb.switch_to(2);
+            tlb.insert(2, 0, 1);
+            tlb.switch_to(1);
+        }
+        assert_eq!(tagged.lookup(1, 0), Some(0));
+        assert_eq!(untagged.lookup(1, 0), None);
+        assert_eq!((tagged.stats.flushes, untagged.stats.flushes), (0, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn effective_access_time() {
+        let stats = super::TlbStats {
+            hits: 3,
+            misses: 1,
+            flushes: 0,
+        };
+        assert!((stats.hit_ratio() - 0.75).abs() < f64::EPSILON);
+        assert!((stats.effective_access_time(20.0, 100.0) - 145.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::{
+    config::MemoryConfig,
+    page_table::{PageTableKind, TranslationStats},
+    pager::{AccessOutcome, Pager, PagingStats},
+    ram::{AccessKind, Ram},
+    replacement::PolicyKind,
+    tlb::{Tlb, TlbConfig, TlbStats},
+};
+use color_eyre::Result;
+use colored::Colorize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    rc::Rc,
+    str::FromStr,
+};
+
+/// Kind of a memory reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+impl FromStr for Op {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "r" | "R" => Ok(Self::Read),
+            "w" | "W" => Ok(Self::Write),
+            _ => Err(eyre::eyre!("expected 'r' or 'w', got '{s}'")),
+        }
+    }
+}
+
+impl Op {
+    /// Kind of [`Ram`] access the reference makes.
+    #[must_use]
+    pub const fn access_kind(self) -> AccessKind {
+        match self {
+            Self::Read => AccessKind::Read,
+            Self::Write => AccessKind::Write,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Read => "r",
+            Self::Write => "w",
+        })
+    }
+}
+
+/// A single memory reference of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub pid: u16,
+    pub vaddr: usize,
+    pub op: Op,
+}
+
+impl fmt::Display for Reference {
+    /// Format the reference as a line of a trace file.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:#x} {}", self.pid, self.vaddr, self.op)
+    }
+}
+
+impl FromStr for Reference {
+    type Err = eyre::Report;
+
+    /// Parse a `pid vaddr r|w` line. The address may be decimal or `0x`-prefixed hexadecimal.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [pid, vaddr, op] = fields[..] else {
+            eyre::bail!("expected 'pid vaddr r|w', got '{s}'");
+        };
+        let pid = pid
+            .parse()
+            .map_err(|e| eyre::eyre!("invalid pid '{pid}': {e}"))?;
+        let vaddr = parse_number("address", vaddr)?;
+        Ok(Self {
+            pid,
+            vaddr,
+            op: op.parse()?,
+        })
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number, calling it `name` in errors.
+pub(crate) fn parse_number<T: TryFrom<u64>>(name: &str, s: &str) -> Result<T> {
+    let value = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| eyre::eyre!("invalid {name} '{s}': {e}"))?;
+    T::try_from(value).map_err(|_| eyre::eyre!("{name} '{s}' is out of range"))
+}
+
+/// Parse a memory reference trace, one `pid vaddr r|w` per line.
+/// Empty lines and everything after a `#` are ignored.
+pub fn parse(input: &str) -> Result<Vec<Reference>> {
+    input
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            (!line.is_empty()).then_some((i + 1, line))
+        })
+        .map(|(number, line)| {
+            line.parse()
+                .map_err(|e: eyre::Report| eyre::eyre!("Trace line {number}: {e}"))
+        })
+        .collect()
+}
+
+/// One simulated reference along with what the [`Pager`] did to serve it.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub reference: Reference,
+    pub outcome: AccessOutcome,
+}
+
+/// Result of replaying a trace.
+#[derive(Debug)]
+pub struct Report {
+    pub policy: &'static str,
+    /// Name of the page table structure.
+    pub page_table: &'static str,
+    pub frames: usize,
+    pub steps: Vec<Step>,
+    pub stats: PagingStats,
+    pub translation: TranslationStats,
+    /// Counters of the TLB, if the trace was replayed with one.
+    pub tlb: Option<TlbStats>,
+}
+
+/// Replay `trace` through a [`Pager`] over a fresh [`Ram`] of the `config` geometry,
+/// using `frames` of its frames, or all of them if `None`, keeping the mappings in a `page_table` of the given kind
+/// and caching translations in a [`Tlb`] if asked to.
+///
+/// Every process in the trace gets a zeroed page for each page it references,
+/// all of them starting out in swap.
+pub fn run(
+    trace: &[Reference],
+    config: MemoryConfig,
+    frames: Option<usize>,
+    policy: PolicyKind,
+    page_table: PageTableKind,
+    tlb: Option<TlbConfig>,
+) -> Result<Report> {
+    let frames = frames.unwrap_or(config.frame_count());
+    eyre::ensure!(
+        (1..=config.frame_count()).contains(&frames),
+        "Frame count must be between 1 and {}, got {frames}",
+        config.frame_count()
+    );
+    let future: Vec<_> = trace
+        .iter()
+        .map(|r| (r.pid, config.split_vaddr(r.vaddr).0))
+        .collect();
+
+    let ram = Rc::new(Ram::new(config));
+    let mut pager =
+        Pager::with_frames(&ram, frames, policy.build(future.clone())).with_page_table(page_table);
+    if let Some(tlb) = tlb {
+        pager = pager.with_tlb(Tlb::new(tlb, &future)?);
+    }
+    for (pid, vpns) in referenced_pages(trace, config) {
+        pager.allocate(pid, vpns)?;
+    }
+    let steps = trace
+        .iter()
+        .map(|&reference| {
+            let outcome =
+                pager.access_as(reference.pid, reference.vaddr, reference.op.access_kind())?;
+            Ok(Step { reference, outcome })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Report {
+        policy: pager.policy_name(),
+        page_table: pager.mmu.table.name(),
+        frames: pager.frames.len(),
+        steps,
+        stats: pager.stats,
+        translation: pager.mmu.stats,
+        tlb: pager.tlb.map(|tlb| tlb.stats),
+    })
+}
+
+/// Pages each process in `trace` references.
+pub fn referenced_pages(
+    trace: &[Reference],
+    config: MemoryConfig,
+) -> BTreeMap<u16, BTreeSet<usize>> {
+    let mut pages: BTreeMap<u16, BTreeSet<usize>> = BTreeMap::new();
+    for reference in trace {
+        pages
+            .entry(reference.pid)
+            .or_default()
+            .insert(config.split_vaddr(reference.vaddr).0);
+    }
+    pages
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "\n\t\tАлгоритм замещения: {}, таблица страниц: {}, кадров: {}\n",
+            self.policy.bold(),
+            self.page_table.to_lowercase().bold(),
+            self.frames
+        )?;
+        writeln!(
+            f,
+            "\t┌──────┬───────┬──────────┬────┬──────┬──────────┬───────┬─────────────┐"
+        )?;
+        writeln!(
+            f,
+            "\t│ {:>4} │ {:>5} │ {:>8} │ {:2} │ {:>4} │ {:>8} │ {:5} │ {:11} │",
+            "№", "PID", "vaddr", "op", "кадр", "paddr", "отказ", "вытеснена"
+        )?;
+        writeln!(
+            f,
+            "\t├──────┼───────┼──────────┼────┼──────┼──────────┼───────┼─────────────┤"
+        )?;
+        for (i, step) in self.steps.iter().enumerate() {
+            let Step { reference, outcome } = step;
+            let fault = if outcome.fault {
+                format!("{:5}", "да").red()
+            } else {
+                format!("{:5}", "нет").green()
+            };
+            let evicted = outcome
+                .evicted
+                .map_or_else(|| "—".to_string(), |(pid, vpn)| format!("{pid}:{vpn}"));
+            writeln!(
+                f,
+                "\t│ {:>4} │ {:>5} │ {:>#8x} │ {:2} │ {:>4} │ {:>#8x} │ {fault} │ {evicted:11} │",
+                i + 1,
+                reference.pid,
+                reference.vaddr,
+                reference.op,
+                outcome.frame,
+                outcome.paddr,
+            )?;
+        }
+        writeln!(
+            f,
+            "\t└──────┴───────┴──────────┴────┴──────┴──────────┴───────┴─────────────┘"
+        )?;
+        writeln!(f, "\n\t\t{}", self.stats)?;
+        writeln!(f, "\t\t{}", self.translation)?;
+        if let Some(tlb) = &self.tlb {
+            writeln!(f, "\t\t{tlb}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, run, Op, Reference};
+    use crate::{
+        config::MemoryConfig,
+        page::PAGE_SIZE,
+        page_table::PageTableKind,
+        replacement::PolicyKind,
+        tlb::{Associativity, TlbConfig},
+    };
+    use color_eyre::Result;
+
+    #[test]
+    fn parse_lines() -> Result<()> {
+        let trace = parse("# pid vaddr op\n1 0x10 r\n\n2 300 w # comment\n")?;
+        assert_eq!(
+            trace,
+            [
+                Reference {
+                    pid: 1,
+                    vaddr: 0x10,
+                    op: Op::Read
+                },
+                Reference {
+                    pid: 2,
+                    vaddr: 300,
+                    op: Op::Write
+                },
+            ]
+        );
+        let error = parse("1 0 r\n1 0 x\n").unwrap_err();
+        assert!(error.to_string().starts_with("Trace line 2:"));
+        Ok(())
+    }
+
+    #[test]
+    fn replay_is_deterministic() -> Result<()> {
+        let input = format!("1 0 r\n2 0 r\n1 {PAGE_SIZE} w\n1 4 r\n");
+        let report = run(
+            &parse(&input)?,
+            MemoryConfig::default(),
+            Some(2),
+            PolicyKind::Fifo,
+            PageTableKind::Flat,
+            None,
+        )?;
+        let faults: Vec<_> = report.steps.iter().map(|s| s.outcome.fault).collect();
+        assert_eq!(faults, [true, true, true, true]);
+        assert_eq!(report.steps[2].outcome.evicted, Some((1, 0)));
+        assert_eq!(report.steps[3].outcome.evicted, Some((2, 0)));
+        assert!(run(
+            &parse(&input)?,
+            MemoryConfig::default(),
+            Some(3),
+            PolicyKind::Fifo,
+            PageTableKind::Flat,
+            None
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn far_addresses() -> Result<()> {
+        // Only the referenced pages are allocated, not every page below them.
+        let trace = parse("1 4000000000 r\n1 4000000001 w\n1 0 r\n")?;
+        for page_table in PageTableKind::ALL {
+            let report = run(
+                &trace,
+                MemoryConfig::default(),
+                None,
+                PolicyKind::Lru,
+                page_table,
+                None,
+            )?;
+            assert_eq!((report.stats.misses, report.stats.hits), (2, 1));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tlb_in_front_of_pager() -> Result<()> {
+        // Two processes take turns reading the same page.
+        let input = "1 0 r\n1 4 r\n2 0 r\n1 8 r\n2 4 r\n";
+        let replay = |tagged| {
+            let tlb = TlbConfig {
+                associativity: Associativity::Ways(1),
+                tagged,
+                ..TlbConfig::new(2)
+            };
+            run(
+                &parse(input)?,
+                MemoryConfig::default(),
+                None,
+                PolicyKind::Lru,
+                PageTableKind::Flat,
+                Some(tlb),
+            )
+        };
+        let tagged = replay(true)?;
+        let hits: Vec<_> = tagged.steps.iter().map(|s| s.outcome.tlb_hit).collect();
+        // Page 0 of both processes maps to the only entry of set 0, so they evict each other.
+        assert_eq!(hits, [false, true, false, false, false]);
+        assert_eq!(tagged.stats.hits, 3);
+        let untagged = replay(false)?.tlb.unwrap_or_default();
+        assert_eq!((untagged.hits, untagged.flushes), (1, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn page_tables_translate_alike() -> Result<()> {
+        let trace = parse("1 0 r\n1 0x4000 r\n2 0 w\n1 4 r\n1 0x4004 r\n")?;
+        let reports = PageTableKind::ALL
+            .into_iter()
+            .map(|kind| {
+                run(
+                    &trace,
+                    MemoryConfig::default(),
+                    None,
+                    PolicyKind::Lru,
+                    kind,
+                    None,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let paddrs = |report: &super::Report| -> Vec<usize> {
+            report.steps.iter().map(|s| s.outcome.paddr).collect()
+        };
+        for report in &reports[1..] {
+            assert_eq!(paddrs(report), paddrs(&reports[0]), "{}", report.page_table);
+            assert_eq!(report.translation.lookups, 8, "{}", report.page_table);
+        }
+        let accesses: Vec<_> = reports.iter().map(|r| r.translation.accesses).collect();
+        assert_eq!(accesses[..3], [8, 15, 23]);
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    config::MemoryConfig,
+    process::{Burst, Process},
+};
+use color_eyre::Result;
+use std::collections::HashMap;
+
+/// Columns of a workload file, in order.
+pub const COLUMNS: [&str; 6] = ["pid", "arrival", "cpu", "io", "memory", "priority"];
+
+/// Parse a single `pid,arrival,cpu,io,memory,priority` line.
+///
+/// `cpu` and `io` are space-separated lists of burst times: the process alternates between them,
+/// starting and ending on the CPU, so there must be exactly one I/O burst fewer than CPU bursts.
+/// A process can't take more memory than `config` has RAM.
+fn parse_line(line: &str, config: MemoryConfig) -> Result<Process> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [pid, arrival, cpu, io, memory, priority] = fields[..] else {
+        eyre::bail!(
+            "expected {} comma-separated fields ({}), got {}",
+            COLUMNS.len(),
+            COLUMNS.join(","),
+            fields.len()
+        );
+    };
+    let number = |name: &str, value: &str| -> Result<usize> {
+        value
+            .parse()
+            .map_err(|e| eyre::eyre!("invalid {name} '{value}': {e}"))
+    };
+    let times = |name: &str, value: &str| -> Result<Vec<usize>> {
+        value
+            .split_whitespace()
+            .map(|time| match number(name, time)? {
+                0 => Err(eyre::eyre!("{name} burst can't be empty")),
+                time => Ok(time),
+            })
+            .collect()
+    };
+    let pid = pid
+        .parse()
+        .map_err(|e| eyre::eyre!("invalid pid '{pid}': {e}"))?;
+    let cpu = times("cpu", cpu)?;
+    let io = times("io", io)?;
+    eyre::ensure!(!cpu.is_empty(), "process needs at least one CPU burst");
+    eyre::ensure!(
+        io.len() + 1 == cpu.len(),
+        "{} CPU burst(s) need {} I/O burst(s) between them, got {}",
+        cpu.len(),
+        cpu.len() - 1,
+        io.len()
+    );
+    let priority = priority
+        .parse()
+        .map_err(|e| eyre::eyre!("invalid priority '{priority}': {e}"))?;
+    let memory = number("memory", memory)?;
+    eyre::ensure!(
+        memory <= config.ram_size,
+        "memory {memory} is more than the {} bytes of RAM",
+        config.ram_size
+    );
+
+    let mut bursts = vec![Burst::Cpu(cpu[0])];
+    for (&io, &cpu) in io.iter().zip(&cpu[1..]) {
+        bursts.extend([Burst::Io(io), Burst::Cpu(cpu)]);
+    }
+    Ok(
+        Process::with_size(pid, memory).with_schedule(
+            number("arrival", arrival)?,
+            priority,
+            bursts,
+        ),
+    )
+}
+
+/// Parse a workload file: one process per line as `pid,arrival,cpu,io,memory,priority`, e.g.
+/// `1,0,5 3,2,64,1` for a process that arrives at time 0, computes for 5, waits for I/O for 2,
+/// computes for 3 more, takes 64 bytes of memory and has priority 1.
+///
+/// Empty lines, everything after a `#` and a header line naming the columns are ignored.
+/// Processes get random instructions of their memory size, which can't exceed the RAM of `config`.
+pub fn parse(input: &str, config: MemoryConfig) -> Result<Vec<Process>> {
+    let mut lines: HashMap<u16, usize> = HashMap::new();
+    input
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            (!line.is_empty()).then_some((i + 1, line))
+        })
+        .filter(|(_, line)| !line.starts_with(COLUMNS[0]))
+        .map(|(number, line)| {
+            let process =
+                parse_line(line, config).map_err(|e| eyre::eyre!("Workload line {number}: {e}"))?;
+            if let Some(first) = lines.insert(process.pid, number) {
+                eyre::bail!(
+                    "Workload line {number}: pid {} is already used on line {first}",
+                    process.pid
+                );
+            }
+            Ok(process)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::{config::MemoryConfig, process::Burst};
+    use color_eyre::Result;
+
+    #[test]
+    fn parse_lines() -> Result<()> {
+        let processes = parse(
+            "pid,arrival,cpu,io,memory,priority\n\
+             # a comment\n\
+             1, 0, 5 3, 2, 64, 1\n\
+             \n\
+             2,4,6,,32,0 # no I/O\n",
+            MemoryConfig::default(),
+        )?;
+        assert_eq!(processes.len(), 2);
+        let first = &processes[0];
+        assert_eq!((first.pid, first.arrival, first.priority), (1, 0, 1));
+        assert_eq!(first.instructions.len(), 64);
+        assert_eq!(first.bursts, [Burst::Cpu(5), Burst::Io(2), Burst::Cpu(3)]);
+        assert_eq!(processes[1].bursts, [Burst::Cpu(6)]);
+        Ok(())
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |input: &str| {
+            parse(input, MemoryConfig::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("1,0,5,,8,0\n1,0,5,,8,0")
+            .starts_with("Workload line 2: pid 1 is already used on line 1"));
+        assert!(error("\n1,0,5 3,,8,0").starts_with("Workload line 2: 2 CPU burst(s)"));
+        assert!(error("1,0,5,,8").starts_with("Workload line 1: expected 6"));
+        assert!(error("1,x,5,,8,0").starts_with("Workload line 1: invalid arrival 'x'"));
+        assert!(error("1,0,0,,8,0").starts_with("Workload line 1: cpu burst can't be empty"));
+        assert!(error("1,0,5,,8,256").starts_with("Workload line 1: invalid priority"));
+        assert!(error("1,0,5 3,2,99999999999999,1")
+            .starts_with("Workload line 1: memory 99999999999999 is more than"));
+    }
+}
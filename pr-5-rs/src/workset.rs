@@ -0,0 +1,421 @@
+use crate::{
+    config::MemoryConfig,
+    pager::{Pager, PagingStats},
+    ram::Ram,
+    replacement::PolicyKind,
+    trace::{self, Reference},
+};
+use color_eyre::Result;
+use colored::Colorize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt,
+    rc::Rc,
+};
+
+/// How working sets are tracked while replaying a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkingSetConfig {
+    /// Window Δ: the working set holds pages referenced during the last Δ ticks.
+    pub window: usize,
+    /// Suspend processes whenever the working sets of the running ones don't fit in the frames.
+    pub suspend: bool,
+}
+
+/// References a process made during the last Δ ticks.
+#[derive(Debug, Default, Clone)]
+pub struct WorkingSet {
+    /// `(tick, vpn, fault)` of every reference in the window, oldest first.
+    recent: VecDeque<(usize, usize, bool)>,
+}
+
+impl WorkingSet {
+    /// Remember that page `vpn` was referenced at `tick`.
+    pub fn record(&mut self, tick: usize, vpn: usize, fault: bool) {
+        self.recent.push_back((tick, vpn, fault));
+    }
+
+    /// Forget references that fell out of the `window` ticks before `now`.
+    pub fn expire(&mut self, now: usize, window: usize) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&(tick, _, _)| tick + window <= now)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Pages in the working set.
+    #[must_use]
+    pub fn pages(&self) -> BTreeSet<usize> {
+        self.recent.iter().map(|&(_, vpn, _)| vpn).collect()
+    }
+
+    /// Size of the working set in pages.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.pages().len()
+    }
+
+    /// Page-fault frequency over the window: share of references that faulted, from 0 to 1.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fault_frequency(&self) -> f64 {
+        match self.recent.len() {
+            0 => 0.0,
+            len => self.recent.iter().filter(|&&(_, _, fault)| fault).count() as f64 / len as f64,
+        }
+    }
+}
+
+/// What a process did during a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    /// Another process ran.
+    Idle,
+    Hit,
+    Fault,
+    /// The process was suspended.
+    Suspended,
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Idle => write!(f, "{}", "·".bright_black()),
+            Self::Hit => write!(f, "{}", "+".green()),
+            Self::Fault => write!(f, "{}", "F".red().bold()),
+            Self::Suspended => write!(f, "{}", "S".yellow()),
+        }
+    }
+}
+
+/// Per-process counters of a [`WorkingSetReport`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ProcessSummary {
+    pub references: usize,
+    pub faults: usize,
+    pub peak_working_set: usize,
+    /// Sum of the working set size over every tick the process wasn't suspended, for the mean.
+    working_set_total: usize,
+    running_ticks: usize,
+    /// Highest page-fault frequency over a window.
+    pub peak_fault_frequency: f64,
+    pub suspensions: usize,
+}
+
+impl ProcessSummary {
+    /// Mean working set size over the ticks the process wasn't suspended.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn mean_working_set(&self) -> f64 {
+        match self.running_ticks {
+            0 => 0.0,
+            ticks => self.working_set_total as f64 / ticks as f64,
+        }
+    }
+
+    /// Page-fault frequency over the whole run.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fault_frequency(&self) -> f64 {
+        match self.references {
+            0 => 0.0,
+            references => self.faults as f64 / references as f64,
+        }
+    }
+}
+
+/// Result of replaying a trace with working-set tracking.
+#[derive(Debug)]
+pub struct WorkingSetReport {
+    pub config: WorkingSetConfig,
+    pub frames: usize,
+    /// What every process did during every tick.
+    pub timeline: BTreeMap<u16, Vec<Mark>>,
+    pub processes: BTreeMap<u16, ProcessSummary>,
+    /// Highest total working set size of the running processes.
+    pub peak_demand: usize,
+    pub stats: PagingStats,
+}
+
+/// Replay `trace` like [`trace::run`] does, tracking the working set and page-fault frequency
+/// of every process over a window of `config.window` ticks, where a tick is one served reference.
+///
+/// With `config.suspend`, whenever the working sets of the running processes add up to more
+/// than `frames`, the process with the largest one is swapped out and its references are held
+/// back. Suspended processes are resumed, oldest first, as soon as their working set fits again.
+/// A process stops counting towards the demand once it has made its last reference.
+pub fn run(
+    trace: &[Reference],
+    memory: MemoryConfig,
+    frames: Option<usize>,
+    policy: PolicyKind,
+    config: WorkingSetConfig,
+) -> Result<WorkingSetReport> {
+    let frames = frames.unwrap_or(memory.frame_count());
+    eyre::ensure!(
+        (1..=memory.frame_count()).contains(&frames),
+        "Frame count must be between 1 and {}, got {frames}",
+        memory.frame_count()
+    );
+    eyre::ensure!(config.window > 0, "Working set window must be positive");
+    // With suspensions, references may be served out of order, so the future used by `Optimal`
+    // is only the trace as written.
+    let future = trace
+        .iter()
+        .map(|r| (r.pid, memory.split_vaddr(r.vaddr).0))
+        .collect();
+    let ram = Rc::new(Ram::new(memory));
+    let mut pager = Pager::with_frames(&ram, frames, policy.build(future));
+    let pages = trace::referenced_pages(trace, memory);
+    for (&pid, vpns) in &pages {
+        pager.allocate(pid, vpns.iter().copied())?;
+    }
+
+    let mut sets: BTreeMap<u16, WorkingSet> = pages
+        .keys()
+        .map(|&pid| (pid, WorkingSet::default()))
+        .collect();
+    let mut processes: BTreeMap<u16, ProcessSummary> = pages
+        .keys()
+        .map(|&pid| (pid, ProcessSummary::default()))
+        .collect();
+    let mut timeline: BTreeMap<u16, Vec<Mark>> = pages.keys().map(|&pid| (pid, vec![])).collect();
+    let mut remaining: HashMap<u16, usize> = HashMap::new();
+    for reference in trace {
+        *remaining.entry(reference.pid).or_default() += 1;
+    }
+    let mut pending: VecDeque<Reference> = trace.iter().copied().collect();
+    let mut held: HashMap<u16, Vec<Reference>> = HashMap::new();
+    let mut suspended: Vec<u16> = vec![];
+    let mut peak_demand = 0;
+    let mut tick = 0;
+
+    let demand = |sets: &BTreeMap<u16, WorkingSet>, suspended: &[u16]| -> usize {
+        sets.iter()
+            .filter(|(pid, _)| !suspended.contains(pid))
+            .map(|(_, set)| set.size())
+            .sum()
+    };
+
+    loop {
+        // Resume whoever fits, oldest suspension first, or the oldest one if nothing else is left.
+        let mut i = 0;
+        while i < suspended.len() {
+            let pid = suspended[i];
+            let fits = demand(&sets, &suspended) + sets[&pid].size() <= frames;
+            if fits || (pending.is_empty() && i == 0) {
+                suspended.remove(i);
+                for reference in held.remove(&pid).unwrap_or_default().into_iter().rev() {
+                    pending.push_front(reference);
+                }
+            } else {
+                i += 1;
+            }
+        }
+        let Some(reference) = pending.pop_front() else {
+            break;
+        };
+        if suspended.contains(&reference.pid) {
+            held.entry(reference.pid).or_default().push(reference);
+            continue;
+        }
+
+        let outcome =
+            pager.access_as(reference.pid, reference.vaddr, reference.op.access_kind())?;
+        tick += 1;
+        for (pid, set) in &mut sets {
+            if !suspended.contains(pid) {
+                set.expire(tick, config.window);
+            }
+        }
+        let (vpn, _) = memory.split_vaddr(reference.vaddr);
+        if let Some(set) = sets.get_mut(&reference.pid) {
+            set.record(tick, vpn, outcome.fault);
+        }
+
+        for (pid, marks) in &mut timeline {
+            marks.push(if *pid == reference.pid {
+                if outcome.fault {
+                    Mark::Fault
+                } else {
+                    Mark::Hit
+                }
+            } else if suspended.contains(pid) {
+                Mark::Suspended
+            } else {
+                Mark::Idle
+            });
+        }
+        for (pid, summary) in &mut processes {
+            if *pid == reference.pid {
+                summary.references += 1;
+                summary.faults += usize::from(outcome.fault);
+                summary.peak_fault_frequency = summary
+                    .peak_fault_frequency
+                    .max(sets[pid].fault_frequency());
+            }
+            if let Some(set) = sets.get(pid).filter(|_| !suspended.contains(pid)) {
+                let size = set.size();
+                summary.peak_working_set = summary.peak_working_set.max(size);
+                summary.working_set_total += size;
+                summary.running_ticks += 1;
+            }
+        }
+        peak_demand = peak_demand.max(demand(&sets, &suspended));
+        // A process that made its last reference is done and no longer needs any frames.
+        if let Some(count) = remaining.get_mut(&reference.pid) {
+            *count -= 1;
+            if *count == 0 {
+                sets.remove(&reference.pid);
+            }
+        }
+
+        while config.suspend
+            && demand(&sets, &suspended) > frames
+            && sets.len() - suspended.len() > 1
+        {
+            let victim = sets
+                .iter()
+                .filter(|(pid, _)| !suspended.contains(pid))
+                .max_by_key(|&(&pid, set)| (set.size(), pid))
+                .map(|(&pid, _)| pid)
+                .ok_or(eyre::eyre!("No process to suspend"))?;
+            pager.swap_out(victim)?;
+            suspended.push(victim);
+            if let Some(summary) = processes.get_mut(&victim) {
+                summary.suspensions += 1;
+            }
+        }
+    }
+
+    Ok(WorkingSetReport {
+        config,
+        frames,
+        timeline,
+        processes,
+        peak_demand,
+        stats: pager.stats,
+    })
+}
+
+impl fmt::Display for WorkingSetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "\n\t\tРабочие множества: окно Δ = {}, кадров: {}, приостановка процессов: {}\n",
+            self.config.window,
+            self.frames,
+            if self.config.suspend {
+                "да"
+            } else {
+                "нет"
+            }
+        )?;
+        writeln!(
+            f,
+            "\t\tВременная шкала ({} попадание, {} отказ, {} приостановлен):",
+            Mark::Hit,
+            Mark::Fault,
+            Mark::Suspended
+        )?;
+        for (pid, marks) in &self.timeline {
+            let marks: String = marks.iter().map(ToString::to_string).collect();
+            writeln!(f, "\t\tPID {pid:5}: {marks}")?;
+        }
+        writeln!(
+            f,
+            "\n\t┌───────┬───────────┬─────────┬────────────┬────────────┬──────────┬───────────┬──────────────┐"
+        )?;
+        writeln!(
+            f,
+            "\t│ {:>5} │ {:>9} │ {:>7} │ {:>10} │ {:>10} │ {:>8} │ {:>9} │ {:>12} │",
+            "PID",
+            "обращений",
+            "отказов",
+            "макс. |WS|",
+            "сред. |WS|",
+            "PFF",
+            "макс. PFF",
+            "приостановок"
+        )?;
+        writeln!(
+            f,
+            "\t├───────┼───────────┼─────────┼────────────┼────────────┼──────────┼───────────┼──────────────┤"
+        )?;
+        for (pid, summary) in &self.processes {
+            writeln!(
+                f,
+                "\t│ {pid:>5} │ {:>9} │ {:>7} │ {:>10} │ {:>10.2} │ {:>7.1}% │ {:>8.1}% │ {:>12} │",
+                summary.references,
+                summary.faults,
+                summary.peak_working_set,
+                summary.mean_working_set(),
+                summary.fault_frequency() * 100.0,
+                summary.peak_fault_frequency * 100.0,
+                summary.suspensions
+            )?;
+        }
+        writeln!(
+            f,
+            "\t└───────┴───────────┴─────────┴────────────┴────────────┴──────────┴───────────┴──────────────┘"
+        )?;
+        writeln!(
+            f,
+            "\n\t\tПиковый спрос рабочих множеств: {} страниц на {} кадров",
+            self.peak_demand, self.frames
+        )?;
+        writeln!(f, "\t\t{}", self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Mark, WorkingSet, WorkingSetConfig};
+    use crate::{config::MemoryConfig, replacement::PolicyKind, trace::parse};
+    use color_eyre::Result;
+
+    #[test]
+    fn window_slides() {
+        let mut set = WorkingSet::default();
+        for (tick, vpn, fault) in [(1, 0, true), (2, 1, true), (3, 0, false), (4, 2, true)] {
+            set.expire(tick, 3);
+            set.record(tick, vpn, fault);
+        }
+        assert_eq!(set.pages().into_iter().collect::<Vec<_>>(), [0, 1, 2]);
+        assert!((set.fault_frequency() - 2.0 / 3.0).abs() < 1e-9);
+        set.expire(6, 3);
+        assert_eq!(set.size(), 1);
+    }
+
+    #[test]
+    fn suspension_stops_thrashing() -> Result<()> {
+        let memory = MemoryConfig::new(64, 16, 16)?;
+        // Two processes, each cycling through two pages, sharing three frames.
+        let input: String = (0..8)
+            .map(|i| format!("1 {} r\n2 {} r\n", i % 2 * 16, i % 2 * 16))
+            .collect();
+        let trace = parse(&input)?;
+        let config = |suspend| WorkingSetConfig { window: 4, suspend };
+
+        let thrashing = run(&trace, memory, Some(3), PolicyKind::Fifo, config(false))?;
+        assert_eq!(thrashing.peak_demand, 4);
+        assert_eq!(thrashing.processes[&1].peak_working_set, 2);
+        assert!(thrashing.stats.misses > 8);
+
+        let suspended = run(&trace, memory, Some(3), PolicyKind::Fifo, config(true))?;
+        assert_eq!(suspended.processes[&2].suspensions, 1);
+        assert!(suspended.timeline[&2].contains(&Mark::Suspended));
+        assert!(suspended.stats.misses < thrashing.stats.misses);
+        let references: usize = suspended.processes.values().map(|s| s.references).sum();
+        assert_eq!(references, trace.len());
+        let empty_window = WorkingSetConfig {
+            window: 0,
+            suspend: false,
+        };
+        assert!(run(&trace, memory, Some(3), PolicyKind::Fifo, empty_window).is_err());
+        Ok(())
+    }
+}
@@ -4,8 +4,9 @@ use std::collections::VecDeque;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    pr_7_rng::init()?;
 
-    let element_count: usize = rand::thread_rng().gen_range(8..=16);
+    let element_count: usize = pr_7_rng::with(|rng| rng.gen_range(8..=16));
 
     println!("Создание пустой очереди...");
     let mut queue: Queue<Element> = Queue::new();
@@ -89,9 +90,8 @@ pub struct Element {
 
 impl Default for Element {
     fn default() -> Self {
-        let mut rng = rand::thread_rng();
         let mut data = [0; 20 * 1024];
-        rng.fill_bytes(&mut data);
+        pr_7_rng::with(|rng| rng.fill_bytes(&mut data));
         Self { data }
     }
 }
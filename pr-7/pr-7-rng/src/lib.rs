@@ -0,0 +1,61 @@
+use eyre::Result;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    env,
+    sync::{Mutex, OnceLock},
+};
+
+/// Environment variable the seed is read from when `--seed` isn't passed.
+pub const SEED_VAR: &str = "MIREA_SEED";
+
+static RNG: OnceLock<Mutex<StdRng>> = OnceLock::new();
+
+/// Find the seed for this run: the `--seed <N>` argument, then [`SEED_VAR`], then a random one.
+pub fn seed_from(args: impl IntoIterator<Item = String>) -> Result<u64> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            let seed = args
+                .next()
+                .ok_or(eyre::eyre!("Option '--seed' requires a value"))?;
+            return seed
+                .parse()
+                .map_err(|e| eyre::eyre!("Invalid seed '{seed}': {e}"));
+        }
+    }
+    match env::var(SEED_VAR) {
+        Ok(seed) => seed
+            .parse()
+            .map_err(|e| eyre::eyre!("Invalid {SEED_VAR} '{seed}': {e}")),
+        Err(_) => Ok(rand::random()),
+    }
+}
+
+/// Seed the shared generator from the command line or the environment and print the seed.
+///
+/// Must be called once at the start of `main`, before anything calls [`with`].
+pub fn init() -> Result<u64> {
+    let seed = seed_from(env::args().skip(1))?;
+    eprintln!("Зерно генератора: {seed} (повторить: --seed {seed} или {SEED_VAR}={seed})");
+    RNG.set(Mutex::new(StdRng::seed_from_u64(seed)))
+        .map_err(|_| eyre::eyre!("The generator has already been seeded"))?;
+    Ok(seed)
+}
+
+/// Run `f` with the shared generator. Falls back to a randomly seeded one if [`init`] wasn't called.
+pub fn with<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    let rng = RNG.get_or_init(|| Mutex::new(StdRng::from_entropy()));
+    f(&mut rng.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::seed_from;
+
+    #[test]
+    fn seed_flag() {
+        let args = ["--seed", "42"].map(String::from);
+        assert_eq!(seed_from(args).unwrap(), 42);
+        assert!(seed_from(["--seed".to_string()]).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use eyre::Result;
 use owo_colors::OwoColorize;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use std::{
     sync::{Arc, Mutex},
     thread,
@@ -17,6 +17,9 @@ pub struct Server {}
 pub struct Client {
     id: usize,
     handled: bool,
+    /// Время обслуживания в миллисекундах, выбранное заранее, чтобы
+    /// порядок потоков не влиял на последовательность случайных чисел.
+    service_time: u64,
 }
 
 #[derive(Debug, Default)]
@@ -28,9 +31,7 @@ pub struct Seat {
 impl Server {
     /// Имитация работы
     pub fn handle(&self, client: &mut Client) {
-        thread::sleep(std::time::Duration::from_millis(
-            thread_rng().gen_range(4..=8),
-        ));
+        thread::sleep(std::time::Duration::from_millis(client.service_time));
         client.handled = true;
         println!("Клиент #{} обслужен и уходит!", client.id.blue());
     }
@@ -38,14 +39,21 @@ impl Server {
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    pr_7_rng::init()?;
 
     // Создание парикмахера.
     let server: Arc<Mutex<Server>> = Arc::default();
 
     // Создание клиентов.
-    let client_count: usize = thread_rng().gen_range(MIN_CLIENTS..=MAX_CLIENTS);
+    let client_count: usize = pr_7_rng::with(|rng| rng.gen_range(MIN_CLIENTS..=MAX_CLIENTS));
     let clients: Vec<Arc<Mutex<Client>>> = (0..client_count)
-        .map(|id| Arc::new(Mutex::new(Client { id, handled: false })))
+        .map(|id| {
+            Arc::new(Mutex::new(Client {
+                id,
+                handled: false,
+                service_time: pr_7_rng::with(|rng| rng.gen_range(4..=8)),
+            }))
+        })
         .collect();
 
     // Создание мест.